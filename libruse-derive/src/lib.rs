@@ -0,0 +1,312 @@
+//! Derives the conversions between Rust types and Ruse values, `FromRuse`
+//! and `IntoRuse`.
+//!
+//! Structs become association lists, or records if they're marked with
+//! `#[ruse(record)]`, and enums become the name of their variant, followed
+//! by its fields if it has any. The `libruse::convert` module describes the
+//! mapping in full.
+
+#![deny(missing_docs)]
+
+extern crate proc_macro;
+extern crate proc_macro2;
+#[macro_use]
+extern crate quote;
+extern crate syn;
+
+use proc_macro::TokenStream;
+use proc_macro2::{Span, TokenStream as Tokens};
+use syn::spanned::Spanned;
+use syn::{parse_macro_input, parse_quote, Data, DeriveInput, Error, Field, Fields, Ident};
+
+/// Derive `FromRuse`, for converting Ruse values into this type.
+#[proc_macro_derive(FromRuse, attributes(ruse))]
+pub fn derive_from_ruse(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    from_ruse(&input).unwrap_or_else(compile_errors).into()
+}
+
+/// Derive `IntoRuse`, for converting this type into Ruse values.
+#[proc_macro_derive(IntoRuse, attributes(ruse))]
+pub fn derive_into_ruse(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    into_ruse(&input).unwrap_or_else(compile_errors).into()
+}
+
+/// Report each of `error`'s messages where it happened. This is what
+/// `Error::into_compile_error` does, except that it names the macro as
+/// `compile_error!` rather than `::core::compile_error!`, which can't be
+/// found from crates written for Rust 2015.
+fn compile_errors(error: Error) -> Tokens {
+    let errors = error.into_iter().map(|error| {
+        let message = error.to_string();
+        quote_spanned!(error.span()=> compile_error!(#message);)
+    });
+    quote!(#(#errors)*)
+}
+
+fn from_ruse(input: &DeriveInput) -> Result<Tokens, Error> {
+    let name = &input.ident;
+    is_record(input)?;
+
+    let body = match input.data {
+        Data::Struct(ref data) => {
+            let build = from_fields(quote!(#name), &data.fields, quote!(value), true);
+            quote!(Ok(#build))
+        }
+        Data::Enum(ref data) => {
+            let mut arms = Vec::new();
+            let mut names = Vec::new();
+            for variant in &data.variants {
+                let ident = &variant.ident;
+                let tag = ruse_name(&ident.to_string());
+                let build = from_fields(quote!(#name::#ident), &variant.fields, quote!(&rest), false);
+                arms.push(quote!(#tag => Ok(#build)));
+                names.push(tag);
+            }
+            let expected = format!("one of {}", names.join(", "));
+            quote! {
+                let (tag, rest) = ::libruse::convert::variant(value)?;
+                let _ = &rest;
+                match tag {
+                    #(#arms,)*
+                    _ => Err(::libruse::ConversionError::new(#expected, value)),
+                }
+            }
+        }
+        Data::Union(..) => return Err(Error::new_spanned(input, "unions can't be converted")),
+    };
+
+    let mut generics = input.generics.clone();
+    for param in generics.type_params_mut() {
+        param.bounds.push(parse_quote!(::libruse::FromRuse));
+    }
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+
+    Ok(quote! {
+        impl #impl_generics ::libruse::FromRuse for #name #ty_generics #where_clause {
+            fn from_ruse(
+                value: &::libruse::ExprRef,
+            ) -> ::std::result::Result<Self, ::libruse::ConversionError> {
+                #body
+            }
+        }
+    })
+}
+
+fn into_ruse(input: &DeriveInput) -> Result<Tokens, Error> {
+    let name = &input.ident;
+    let record = is_record(input)?;
+
+    let body = match input.data {
+        Data::Struct(ref data) => {
+            let bindings = bindings(&data.fields);
+            let pattern = pattern(quote!(#name), &data.fields, &bindings);
+            let record_name = format!("<{}>", ruse_name(&name.to_string()));
+            let value = match into_fields(&data.fields, &bindings) {
+                Converted::Named(fields) if record => {
+                    quote!(::libruse::convert::fields_into_record(#record_name, #fields))
+                }
+                Converted::Named(fields) => quote!(::libruse::convert::fields_into_alist(#fields)),
+                Converted::Single(value) => value,
+                Converted::List(list) => list,
+                Converted::Unit => {
+                    let symbol = ruse_name(&name.to_string());
+                    quote!(::libruse::Expr::symbol(#symbol))
+                }
+            };
+            quote! {
+                let #pattern = self;
+                #value
+            }
+        }
+        Data::Enum(ref data) => {
+            let mut arms = Vec::new();
+            for variant in &data.variants {
+                let ident = &variant.ident;
+                let tag = ruse_name(&ident.to_string());
+                let bindings = bindings(&variant.fields);
+                let pattern = pattern(quote!(#name::#ident), &variant.fields, &bindings);
+                let fields = match into_fields(&variant.fields, &bindings) {
+                    Converted::Named(fields) => {
+                        quote!(Some(::libruse::convert::fields_into_alist(#fields)))
+                    }
+                    Converted::Single(value) => quote!(Some(::libruse::Expr::list(vec![#value]))),
+                    Converted::List(list) => quote!(Some(#list)),
+                    Converted::Unit => quote!(None),
+                };
+                arms.push(quote!(#pattern => ::libruse::convert::variant_into_ruse(#tag, #fields)));
+            }
+            quote! {
+                match self {
+                    #(#arms,)*
+                }
+            }
+        }
+        Data::Union(..) => return Err(Error::new_spanned(input, "unions can't be converted")),
+    };
+
+    let mut generics = input.generics.clone();
+    for param in generics.type_params_mut() {
+        param.bounds.push(parse_quote!(::libruse::IntoRuse));
+    }
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+
+    Ok(quote! {
+        impl #impl_generics ::libruse::IntoRuse for #name #ty_generics #where_clause {
+            #[allow(unused_variables)]
+            fn into_ruse(self) -> ::libruse::ExprRef {
+                #body
+            }
+        }
+    })
+}
+
+/// Whether a struct is marked `#[ruse(record)]`.
+fn is_record(input: &DeriveInput) -> Result<bool, Error> {
+    let mut record = false;
+    for attr in &input.attrs {
+        if !attr.path().is_ident("ruse") {
+            continue;
+        }
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("record") {
+                record = true;
+                Ok(())
+            } else {
+                Err(meta.error("expected `record`"))
+            }
+        })?;
+    }
+
+    match input.data {
+        Data::Struct(ref data) if record => match data.fields {
+            Fields::Named(..) => Ok(true),
+            _ => Err(Error::new_spanned(input, "only structs with named fields can be records")),
+        },
+        Data::Struct(..) => Ok(false),
+        _ if record => Err(Error::new_spanned(input, "only structs can be records")),
+        _ => Ok(false),
+    }
+}
+
+/// Build a struct or variant out of `value`, which holds its fields. A
+/// struct with a single unnamed field is `transparent`: the value is that
+/// field, rather than a list of one.
+fn from_fields(path: Tokens, fields: &Fields, value: Tokens, transparent: bool) -> Tokens {
+    match *fields {
+        Fields::Named(ref named) => {
+            let fields = named.named.iter().map(|field| {
+                let ident = &field.ident;
+                let name = ruse_name(&ident.as_ref().map(Ident::to_string).unwrap_or_default());
+                let convert = quote_spanned!(field.ty.span()=> ::libruse::convert::field(#value, #name));
+                quote!(#ident: #convert?)
+            });
+            quote!(#path { #(#fields),* })
+        }
+        Fields::Unnamed(ref unnamed) if transparent && unnamed.unnamed.len() == 1 => {
+            let convert = from_ruse_call(&unnamed.unnamed[0], &value);
+            quote!(#path(#convert?))
+        }
+        Fields::Unnamed(ref unnamed) => {
+            let count = unnamed.unnamed.len();
+            let fields = unnamed
+                .unnamed
+                .iter()
+                .enumerate()
+                .map(|(i, field)| from_ruse_call(field, &quote!(&items[#i])));
+            quote!({
+                let items = ::libruse::convert::list_of(#value, #count)?;
+                #path(#(#fields?),*)
+            })
+        }
+        Fields::Unit => path,
+    }
+}
+
+/// Convert `value` into the type of `field`. Any error from the field's
+/// type not being convertible points at the field rather than the derive.
+fn from_ruse_call(field: &Field, value: &Tokens) -> Tokens {
+    quote_spanned!(field.ty.span()=> ::libruse::FromRuse::from_ruse(#value))
+}
+
+/// Convert a field bound to `binding` into Ruse. The binding is given the
+/// field's span too, since that's where an error about its type points.
+fn into_ruse_call(field: &Field, binding: &Ident) -> Tokens {
+    let mut binding = binding.clone();
+    binding.set_span(field.ty.span());
+    quote_spanned!(field.ty.span()=> ::libruse::IntoRuse::into_ruse(#binding))
+}
+
+/// Names to bind each of the fields to when taking a value apart.
+fn bindings(fields: &Fields) -> Vec<Ident> {
+    (0..fields.len())
+        .map(|i| Ident::new(&format!("field{}", i), Span::call_site()))
+        .collect()
+}
+
+/// A pattern taking a struct or variant apart into its fields.
+fn pattern(path: Tokens, fields: &Fields, bindings: &[Ident]) -> Tokens {
+    match *fields {
+        Fields::Named(ref named) => {
+            let idents = named.named.iter().map(|field| &field.ident);
+            quote!(#path { #(#idents: #bindings),* })
+        }
+        Fields::Unnamed(..) => quote!(#path(#(#bindings),*)),
+        Fields::Unit => path,
+    }
+}
+
+/// The fields of a struct or variant, converted into Ruse.
+enum Converted {
+    /// A list of names and values.
+    Named(Tokens),
+    /// The value of the only unnamed field.
+    Single(Tokens),
+    /// A list of the values of several unnamed fields.
+    List(Tokens),
+    Unit,
+}
+
+fn into_fields(fields: &Fields, bindings: &[Ident]) -> Converted {
+    let values = fields
+        .iter()
+        .zip(bindings)
+        .map(|(field, binding)| into_ruse_call(field, binding))
+        .collect::<Vec<_>>();
+    match *fields {
+        Fields::Named(ref named) => {
+            let names = named
+                .named
+                .iter()
+                .map(|field| ruse_name(&field.ident.as_ref().map(Ident::to_string).unwrap_or_default()));
+            Converted::Named(quote!(vec![#((#names, #values)),*]))
+        }
+        Fields::Unnamed(..) if values.len() == 1 => Converted::Single(values[0].clone()),
+        Fields::Unnamed(..) => Converted::List(quote! {
+            ::libruse::Expr::list(vec![#(#values),*])
+        }),
+        Fields::Unit => Converted::Unit,
+    }
+}
+
+/// Write a Rust name the way Ruse names are written, so `first_name` and
+/// `FirstName` are both `first-name`.
+fn ruse_name(name: &str) -> String {
+    let name = name.trim_start_matches("r#");
+    let mut out = String::with_capacity(name.len() + 4);
+    for (i, c) in name.chars().enumerate() {
+        if c == '_' {
+            out.push('-');
+        } else if c.is_uppercase() {
+            if i > 0 && !out.ends_with('-') {
+                out.push('-');
+            }
+            out.extend(c.to_lowercase());
+        } else {
+            out.push(c);
+        }
+    }
+    out
+}
+
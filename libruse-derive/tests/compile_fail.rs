@@ -0,0 +1,7 @@
+extern crate trybuild;
+
+#[test]
+fn derives_reject_what_they_cannot_convert() {
+    let cases = trybuild::TestCases::new();
+    cases.compile_fail("tests/ui/*.rs");
+}
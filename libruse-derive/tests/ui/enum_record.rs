@@ -0,0 +1,12 @@
+extern crate libruse;
+
+use libruse::FromRuse;
+
+#[derive(FromRuse)]
+#[ruse(record)]
+enum Shape {
+    Circle { radius: f64 },
+    Square { side: f64 },
+}
+
+fn main() {}
@@ -0,0 +1,9 @@
+extern crate libruse;
+
+use libruse::IntoRuse;
+
+#[derive(IntoRuse)]
+#[ruse(record)]
+struct Point(i64, i64);
+
+fn main() {}
@@ -0,0 +1,11 @@
+extern crate libruse;
+
+use libruse::{FromRuse, IntoRuse};
+
+#[derive(FromRuse, IntoRuse)]
+union Number {
+    int: i64,
+    real: f64,
+}
+
+fn main() {}
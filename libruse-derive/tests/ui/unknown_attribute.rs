@@ -0,0 +1,12 @@
+extern crate libruse;
+
+use libruse::FromRuse;
+
+#[derive(FromRuse)]
+#[ruse(table)]
+struct Point {
+    x: i64,
+    y: i64,
+}
+
+fn main() {}
@@ -0,0 +1,13 @@
+extern crate libruse;
+
+use libruse::{FromRuse, IntoRuse};
+
+struct Opaque;
+
+#[derive(FromRuse, IntoRuse)]
+struct Config {
+    name: String,
+    handle: Opaque,
+}
+
+fn main() {}
@@ -0,0 +1,59 @@
+//! Escaping continuations and `dynamic-wind`.
+//!
+//! Continuations are escape-only: calling one unwinds the evaluator back to
+//! the `call/cc` that captured it, which must still be running. Everything
+//! with a dynamic extent (exception handlers, `parameterize`, `dynamic-wind`)
+//! restores itself as the unwinding passes through.
+
+use error::Unwind;
+use read::parse::expr::{Continuation, Env, Expr, ExprKind, ExprRef};
+use values::from_values;
+use {define_primitive, Evaluator};
+
+impl Evaluator {
+    /// Call `f` with the current continuation.
+    fn call_cc(&mut self, f: ExprRef) -> Result<ExprRef, Unwind> {
+        let id = self.next_continuation;
+        self.next_continuation += 1;
+
+        let continuation = Expr::new(ExprKind::Continuation(Continuation { id })).alloc();
+        self.continuations.push(id);
+        let result = self.apply(f, vec![continuation]);
+        self.continuations.pop();
+
+        match result {
+            Err(Unwind::Escape { id: target, value }) if target == id => Ok(value),
+            other => other,
+        }
+    }
+
+    /// Return `args` from the `call/cc` a continuation was captured by.
+    pub(crate) fn escape(&mut self, continuation: &Continuation, args: Vec<ExprRef>) -> Unwind {
+        if !self.continuations.contains(&continuation.id) {
+            let message = "continuation called after its extent ended";
+            return self.error(message, args);
+        }
+
+        Unwind::Escape {
+            id: continuation.id,
+            value: from_values(args),
+        }
+    }
+}
+
+pub fn define_primitives(env: &Env) {
+    for &name in &["call-with-current-continuation", "call/cc"] {
+        define_primitive(env, name, 1, Some(1), |ev, args| ev.call_cc(args[0].clone()));
+    }
+
+    define_primitive(env, "dynamic-wind", 3, Some(3), |ev, mut args| {
+        let after = args.pop().unwrap_or_else(Expr::unspecified);
+        let thunk = args.pop().unwrap_or_else(Expr::unspecified);
+        let before = args.pop().unwrap_or_else(Expr::unspecified);
+
+        ev.apply(before, Vec::new())?;
+        let result = ev.apply(thunk, Vec::new());
+        ev.apply(after, Vec::new())?;
+        result
+    });
+}
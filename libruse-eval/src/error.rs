@@ -0,0 +1,124 @@
+//! Types for handling errors during evaluation.
+
+use limits::Limit;
+use read::lex::token::Span;
+use read::parse::expr::{ConditionKind, ExprKind, ExprRef};
+use std::error;
+use std::fmt;
+
+/// The ways evaluation can stop short of producing a value.
+///
+/// These travel up through the evaluator until something handles them, like
+/// a `guard` form, or until they reach the top and become an `Error`.
+pub enum Unwind {
+    /// An object was raised, and is headed for the `guard` with the given id,
+    /// or for the top level if no `guard` was installed.
+    Raise {
+        /// The raised object.
+        payload: ExprRef,
+        /// Where it was raised.
+        span: Option<Span>,
+        /// The `guard` that should catch it.
+        guard: Option<usize>,
+    },
+    /// A continuation was called, and control is headed back to the
+    /// `call/cc` with the given id.
+    Escape {
+        /// The `call/cc` to return from.
+        id: usize,
+        /// What it returns.
+        value: ExprRef,
+    },
+    /// The program ran into one of its limits, and is being stopped.
+    Limit(Limit),
+    /// The program was stopped through an `InterruptHandle`, and is being
+    /// unwound without any handler seeing it.
+    Interrupt,
+}
+
+/// Why a procedure defined outside the evaluator couldn't give back a value.
+/// It's raised as an error object with this message and irritants.
+pub struct Failure {
+    /// What went wrong.
+    pub message: String,
+    /// The values involved.
+    pub irritants: Vec<ExprRef>,
+}
+
+/// Indicates that evaluation failed.
+pub enum Error {
+    /// An object was raised and no handler dealt with it.
+    Uncaught {
+        /// The raised object.
+        payload: ExprRef,
+        /// Where it was raised.
+        span: Option<Span>,
+    },
+    /// The program ran into one of its limits.
+    Limit(Limit),
+    /// The program was interrupted, and either didn't catch the interrupt
+    /// or was stopped outright.
+    Interrupted,
+}
+
+impl From<Unwind> for Error {
+    /// Whatever makes it to the top of the evaluator was never handled.
+    fn from(unwind: Unwind) -> Error {
+        match unwind {
+            Unwind::Raise { ref payload, .. } if is_interrupt(payload) => Error::Interrupted,
+            Unwind::Raise { payload, span, .. } => Error::Uncaught { payload, span },
+            // Continuations only escape to a `call/cc` that's still running,
+            // so none should get this far.
+            Unwind::Escape { value, .. } => Error::Uncaught {
+                payload: value,
+                span: None,
+            },
+            Unwind::Limit(limit) => Error::Limit(limit),
+            Unwind::Interrupt => Error::Interrupted,
+        }
+    }
+}
+
+fn is_interrupt(payload: &ExprRef) -> bool {
+    match payload.kind {
+        ExprKind::Condition(ref condition) => condition.kind == ConditionKind::Interrupt,
+        _ => false,
+    }
+}
+
+impl error::Error for Error {
+    /// Get a simple text description of what each error means.
+    fn description(&self) -> &str {
+        match *self {
+            Error::Uncaught { .. } => "an uncaught exception",
+            Error::Limit(..) => "the program ran into one of its limits",
+            Error::Interrupted => "the program was interrupted",
+        }
+    }
+}
+
+impl fmt::Debug for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Uncaught { ref span, .. } => {
+                write!(f, "Uncaught {{ message: {:?}, span: {:?} }}", self.to_string(), span)
+            }
+            Error::Limit(limit) => write!(f, "Limit({:?})", limit),
+            Error::Interrupted => write!(f, "Interrupted"),
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    /// Print detailed error information.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Uncaught { ref payload, .. } => match payload.kind {
+                ExprKind::Condition(ref condition) => write!(f, "{}", condition.message),
+                _ => write!(f, "uncaught exception"),
+            },
+            Error::Limit(limit) => write!(f, "{}", limit),
+            Error::Interrupted => write!(f, "the program was interrupted"),
+        }
+    }
+}
@@ -0,0 +1,211 @@
+//! Raising and handling exceptions.
+//!
+//! The evaluator keeps a stack of installed handlers. `with-exception-handler`
+//! pushes procedures onto it, and `guard` pushes a marker which raised objects
+//! unwind to. Raising consults the top of the stack: procedures are called in
+//! place, with the stack below them still installed, while reaching a `guard`
+//! (or the bottom of the stack) means unwinding.
+
+use error::Unwind;
+use read::parse::expr::{Condition, ConditionKind, Env, Expr, ExprKind, ExprRef};
+use std::sync::Arc;
+use syntax::binding_key;
+use {define_primitive, Evaluator, Step};
+
+/// An entry on the evaluator's handler stack.
+#[derive(Clone)]
+pub enum Handler {
+    Procedure(ExprRef),
+    Guard(usize),
+}
+
+impl Evaluator {
+    /// Raise `payload` non-continuably, giving back what the evaluator
+    /// should unwind with. Handlers that return cause a secondary exception.
+    pub(crate) fn throw(&mut self, payload: ExprRef) -> Unwind {
+        match self.handlers.pop() {
+            Some(Handler::Procedure(handler)) => {
+                let unwind = match self.apply(handler.clone(), vec![payload.clone()]) {
+                    Err(unwind) => unwind,
+                    Ok(_) => {
+                        let message = "exception handler returned from non-continuable raise";
+                        let secondary = self.condition(ConditionKind::Error, message, vec![payload]);
+                        self.throw(secondary)
+                    }
+                };
+                self.handlers.push(Handler::Procedure(handler));
+                unwind
+            }
+            top => {
+                self.handlers.extend(top);
+                self.unwind(payload)
+            }
+        }
+    }
+
+    /// Raise `payload`, returning whatever the current handler returns.
+    fn raise_continuable(&mut self, payload: ExprRef) -> Result<ExprRef, Unwind> {
+        match self.handlers.pop() {
+            Some(Handler::Procedure(handler)) => {
+                let result = self.apply(handler.clone(), vec![payload]);
+                self.handlers.push(Handler::Procedure(handler));
+                result
+            }
+            top => {
+                self.handlers.extend(top);
+                Err(self.unwind(payload))
+            }
+        }
+    }
+
+    /// Unwind to the innermost `guard`, or to the top level.
+    fn unwind(&self, payload: ExprRef) -> Unwind {
+        let guard = match self.handlers.last() {
+            Some(&Handler::Guard(id)) => Some(id),
+            _ => None,
+        };
+
+        Unwind::Raise {
+            payload,
+            span: self.span,
+            guard,
+        }
+    }
+
+    /// Make an error object located at the expression being evaluated.
+    pub(crate) fn condition<S: Into<String>>(
+        &self,
+        kind: ConditionKind,
+        message: S,
+        irritants: Vec<ExprRef>,
+    ) -> ExprRef {
+        let condition = Condition {
+            kind,
+            message: message.into(),
+            irritants: Expr::list(irritants),
+            span: self.span,
+        };
+        Expr::new(ExprKind::Condition(condition)).alloc()
+    }
+
+    /// Raise an error object with the given message and irritants.
+    pub(crate) fn error<S: Into<String>>(&mut self, message: S, irritants: Vec<ExprRef>) -> Unwind {
+        let condition = self.condition(ConditionKind::Error, message, irritants);
+        self.throw(condition)
+    }
+}
+
+/// `(guard (var clause ...) body ...)`
+pub fn guard(
+    ev: &mut Evaluator,
+    form: &ExprRef,
+    args: &[ExprRef],
+    env: &Arc<Env>,
+) -> Result<Step, Unwind> {
+    let spec = match args.first().and_then(|spec| spec.to_vec()) {
+        Some(ref spec) if !spec.is_empty() && binding_key(&spec[0]).is_some() => spec.clone(),
+        _ => return Err(ev.syntax_error(form)),
+    };
+
+    let id = ev.next_guard;
+    ev.next_guard += 1;
+
+    let depth = ev.handlers.len();
+    ev.handlers.push(Handler::Guard(id));
+    let result = ev.eval_sequence(&args[1..], env);
+    ev.handlers.truncate(depth);
+
+    match result {
+        Err(Unwind::Raise {
+            payload,
+            span,
+            guard: Some(target),
+        }) if target == id => {
+            let clause_env = Env::child(&env);
+            if let Some(key) = binding_key(&spec[0]) {
+                clause_env.define(key, payload.clone());
+            }
+
+            match ev.eval_clauses(&spec[1..], &clause_env)? {
+                Some(step) => Ok(step),
+                None => {
+                    // No clause wanted it, so pass it on to whoever is
+                    // handling exceptions outside of the guard.
+                    ev.span = span;
+                    ev.raise_continuable(payload).map(Step::Done)
+                }
+            }
+        }
+        other => other.map(Step::Done),
+    }
+}
+
+pub fn define_primitives(env: &Env) {
+    define_primitive(env, "with-exception-handler", 2, Some(2), |ev, args| {
+        if !args[0].is_procedure() || !args[1].is_procedure() {
+            return Err(ev.error("with-exception-handler: expected procedures", args));
+        }
+
+        let depth = ev.handlers.len();
+        ev.handlers.push(Handler::Procedure(args[0].clone()));
+        let result = ev.apply(args[1].clone(), Vec::new());
+        ev.handlers.truncate(depth);
+        result
+    });
+
+    define_primitive(env, "raise", 1, Some(1), |ev, args| {
+        Err(ev.throw(args[0].clone()))
+    });
+
+    define_primitive(env, "raise-continuable", 1, Some(1), |ev, args| {
+        ev.raise_continuable(args[0].clone())
+    });
+
+    define_primitive(env, "error", 1, None, |ev, mut args| {
+        let message = match args[0].kind {
+            ExprKind::Str(ref message) => message.borrow().clone(),
+            _ => return Err(ev.error("error: expected a string message", vec![args[0].clone()])),
+        };
+        let irritants = args.split_off(1);
+        Err(ev.error(message, irritants))
+    });
+
+    // Interrupts aren't errors, so that handlers for errors leave them be.
+    define_primitive(env, "error-object?", 1, Some(1), |_, args| {
+        let kind = condition_kind(&args[0]);
+        Ok(Expr::boolean(kind.is_some() && kind != Some(ConditionKind::Interrupt)))
+    });
+
+    define_primitive(env, "error-object-message", 1, Some(1), |ev, args| {
+        match args[0].kind {
+            ExprKind::Condition(ref condition) => Ok(Expr::string(condition.message.clone())),
+            _ => Err(ev.error("error-object-message: expected an error object", args)),
+        }
+    });
+
+    define_primitive(env, "error-object-irritants", 1, Some(1), |ev, args| {
+        match args[0].kind {
+            ExprKind::Condition(ref condition) => Ok(condition.irritants.clone()),
+            _ => Err(ev.error("error-object-irritants: expected an error object", args)),
+        }
+    });
+
+    define_primitive(env, "read-error?", 1, Some(1), |_, args| {
+        Ok(Expr::boolean(condition_kind(&args[0]) == Some(ConditionKind::Read)))
+    });
+
+    define_primitive(env, "file-error?", 1, Some(1), |_, args| {
+        Ok(Expr::boolean(condition_kind(&args[0]) == Some(ConditionKind::File)))
+    });
+
+    define_primitive(env, "interrupt-condition?", 1, Some(1), |_, args| {
+        Ok(Expr::boolean(condition_kind(&args[0]) == Some(ConditionKind::Interrupt)))
+    });
+}
+
+fn condition_kind(expr: &ExprRef) -> Option<ConditionKind> {
+    match expr.kind {
+        ExprKind::Condition(ref condition) => Some(condition.kind),
+        _ => None,
+    }
+}
@@ -0,0 +1,589 @@
+//! Macro expansion as a pass of its own.
+//!
+//! The evaluator expands macros as it meets them, which is all running a
+//! program needs. This pass does the same expansion ahead of time, walking
+//! through the special forms so it knows what each name refers to, and gives
+//! back the program written with special forms and procedure calls alone.
+//! It's mostly useful for seeing what macros turn into.
+//!
+//! Identifiers which an expansion both introduced and bound come out as
+//! symbols named after their unique alias, so they can't be confused with
+//! the names around them. Macro definitions are used up by the expansion,
+//! and leave nothing behind.
+//!
+//! Expanding a program also finds the variables it refers to which nothing
+//! binds, so evaluators which check for them can refuse to run it. Those
+//! evaluators then run the expansion itself, so no macro is expanded twice;
+//! its top-level macro definitions are kept, to be defined again as it runs.
+
+use error::{Error, Unwind};
+use read::parse::expr::{Env, Expr, ExprKind, ExprRef, Symbol};
+use read::parse::symbol::keywords;
+use special;
+use std::mem;
+use std::sync::Arc;
+use syntax::{self, binding_key, cons, Resolved};
+use Evaluator;
+
+/// Walks the operands of one special form.
+type Walker = fn(&mut Evaluator, &ExprRef, &[ExprRef], &Arc<Env>) -> Result<ExprRef, Unwind>;
+
+fn walker(name: Symbol) -> Option<Walker> {
+    match name {
+        keywords::QUOTE => Some(quote),
+        keywords::IF | keywords::SET | keywords::AND | keywords::OR | keywords::DELAY | keywords::DELAY_FORCE => Some(operands),
+        keywords::BEGIN | keywords::WHEN | keywords::UNLESS => Some(sequence),
+        keywords::COND => Some(cond),
+        keywords::DEFINE => Some(define),
+        keywords::LAMBDA => Some(lambda),
+        keywords::CASE_LAMBDA => Some(case_lambda),
+        keywords::LET => Some(let_form),
+        keywords::LET_STAR => Some(let_star),
+        keywords::LETREC | keywords::LETREC_STAR => Some(letrec),
+        keywords::GUARD => Some(guard),
+        keywords::PARAMETERIZE => Some(parameterize),
+        keywords::DEFINE_RECORD_TYPE => Some(record_type),
+        keywords::LET_VALUES => Some(let_values),
+        keywords::LET_STAR_VALUES => Some(let_star_values),
+        keywords::DEFINE_VALUES => Some(define_values),
+        keywords::RECEIVE => Some(receive),
+        keywords::DEFINE_SYNTAX => Some(define_syntax),
+        keywords::LET_SYNTAX => Some(let_syntax),
+        keywords::LETREC_SYNTAX => Some(letrec_syntax),
+        _ => None,
+    }
+}
+
+impl Evaluator {
+    /// Expand every macro use in an expression, as if it were evaluated in
+    /// the global environment.
+    pub fn expand(&mut self, expr: Expr) -> Result<ExprRef, Error> {
+        let global = self.global.clone();
+        expand(self, expr, &global)
+    }
+
+    /// Expand an expression if it's a macro use, just once, leaving any
+    /// macro uses in the expansion as they are.
+    pub fn expand_once(&mut self, expr: Expr) -> Result<ExprRef, Error> {
+        let global = self.global.clone();
+        expand_once(self, expr, &global)
+    }
+
+    fn expand_in(&mut self, expr: Expr, env: &Arc<Env>) -> Result<ExprRef, Error> {
+        // Definitions made while expanding go in a scope of their own, so
+        // they don't disturb the environment being expanded against.
+        let scope = Env::child(&env);
+        self.start();
+        self.expand_expr(&expr.alloc(), &scope).map_err(Error::from)
+    }
+
+    fn expand_once_in(&mut self, expr: Expr, env: &Arc<Env>) -> Result<ExprRef, Error> {
+        let expr = expr.alloc();
+        self.start();
+
+        let head = match expr.car() {
+            Some(head) => head,
+            None => return Ok(expr),
+        };
+        match syntax::resolve(&head, env) {
+            Some(Resolved::Bound(ref value)) => match value.kind {
+                ExprKind::Syntax(ref syntax) => {
+                    self.expand_macro(syntax, &expr, env).map_err(Error::from)
+                }
+                _ => Ok(expr),
+            },
+            _ => Ok(expr),
+        }
+    }
+
+    /// Expand `expr` to be run in the global environment, failing with an
+    /// unbound variable error if it refers to a variable which neither the
+    /// global environment nor `expr` itself binds.
+    pub(crate) fn check_unbound(&mut self, expr: &ExprRef) -> Result<ExprRef, Unwind> {
+        let scope = Env::child(&self.global);
+        self.free.clear();
+        self.toplevel = Some(scope.clone());
+        let expanded = self.expand_expr(expr, &scope);
+        self.toplevel = None;
+        let expanded = expanded?;
+
+        // Names are only looked up again once everything is expanded, since
+        // a definition may come after a procedure which uses it.
+        for (name, env) in mem::replace(&mut self.free, Vec::new()) {
+            if let Some(Resolved::Free(key)) = syntax::resolve(&name, &env) {
+                if !is_keyword(key) {
+                    self.span = name.span;
+                    return Err(self.error("unbound variable", vec![syntax::strip(&name)]));
+                }
+            }
+        }
+
+        // The program's own definitions are made in the global environment
+        // once it runs, so only its macros are left in the scope, for the
+        // identifiers they introduced to look names up through.
+        scope.retain(|value| match value.kind {
+            ExprKind::Syntax(..) => true,
+            _ => false,
+        });
+        Ok(expanded)
+    }
+
+    fn expand_expr(&mut self, expr: &ExprRef, env: &Arc<Env>) -> Result<ExprRef, Unwind> {
+        if self.unbound_checks {
+            if let Some(Resolved::Free(..)) = syntax::resolve(expr, env) {
+                self.free.push((expr.clone(), env.clone()));
+            }
+        }
+
+        match expr.kind {
+            ExprKind::Identifier(..) => Ok(reference(expr, env)),
+            ExprKind::Pair(..) => self.expand_form(expr, env),
+            _ => Ok(expr.clone()),
+        }
+    }
+
+    fn expand_form(&mut self, form: &ExprRef, env: &Arc<Env>) -> Result<ExprRef, Unwind> {
+        let items = match form.to_vec() {
+            Some(items) => items,
+            None => return Err(self.syntax_error(form)),
+        };
+
+        match syntax::resolve(&items[0], env) {
+            Some(Resolved::Free(name)) => {
+                if let Some(walk) = walker(name) {
+                    return walk(self, form, &items[1..], env);
+                }
+            }
+            Some(Resolved::Bound(ref value)) => {
+                if let ExprKind::Syntax(ref syntax) = value.kind {
+                    let expansion = self.expand_macro(syntax, form, env)?;
+                    return self.expand_expr(&expansion, env);
+                }
+            }
+            None => {}
+        }
+
+        let items = self.expand_all(&items, env)?;
+        Ok(rebuild(form, items))
+    }
+
+    fn expand_all(&mut self, exprs: &[ExprRef], env: &Arc<Env>) -> Result<Vec<ExprRef>, Unwind> {
+        let mut expanded = Vec::with_capacity(exprs.len());
+        for expr in exprs {
+            expanded.push(self.expand_expr(expr, env)?);
+        }
+        Ok(expanded)
+    }
+
+    /// Expand a body, dropping the macro definitions it's done with.
+    fn expand_body(&mut self, body: &[ExprRef], env: &Arc<Env>) -> Result<Vec<ExprRef>, Unwind> {
+        let mut expanded = self.expand_all(body, env)?;
+        let last = expanded.pop();
+        expanded.retain(|expr| !is_unspecified(expr));
+        expanded.extend(last);
+        Ok(expanded)
+    }
+
+    /// Expand the parameters of a `lambda`, binding them in `env`.
+    fn expand_params(&mut self, params: &ExprRef, env: &Arc<Env>) -> Result<ExprRef, Unwind> {
+        match params.kind {
+            ExprKind::Nil => Ok(params.clone()),
+            ExprKind::Pair(ref pair) => {
+                let car = self.bind(&pair.car.borrow(), env)?;
+                let cdr = self.expand_params(&pair.cdr.borrow(), env)?;
+                Ok(cons(car, cdr, params.span))
+            }
+            _ => self.bind(params, env),
+        }
+    }
+
+    /// Bind a name in a scope being expanded. Bindings only need to shadow
+    /// whatever the name meant outside, so they're given no value.
+    fn bind(&mut self, name: &ExprRef, env: &Arc<Env>) -> Result<ExprRef, Unwind> {
+        match binding_key(name) {
+            Some(key) => env.define(key, Expr::unspecified()),
+            None => return Err(self.syntax_error(name)),
+        }
+        Ok(reference(name, env))
+    }
+
+    /// Expand the `(name init)` pairs of a `let`, binding the names in
+    /// `scope` and expanding the initializers in `env`.
+    fn expand_bindings(
+        &mut self,
+        form: &ExprRef,
+        bindings: &ExprRef,
+        env: &Arc<Env>,
+        scope: &Arc<Env>,
+    ) -> Result<ExprRef, Unwind> {
+        let mut expanded = Vec::new();
+        for binding in bindings.to_vec().unwrap_or_default() {
+            match binding.to_vec() {
+                Some(ref parts) if parts.len() == 2 => {
+                    let name = self.bind(&parts[0], scope)?;
+                    let init = self.expand_expr(&parts[1], env)?;
+                    expanded.push(rebuild(&binding, vec![name, init]));
+                }
+                _ => return Err(self.syntax_error(form)),
+            }
+        }
+        Ok(Expr::list(expanded))
+    }
+
+    /// An identifier for one of the evaluator's keywords, which means the
+    /// keyword wherever it's used. It's renamed in the outermost scope of
+    /// `env`, the environment the expansion is being done against, where
+    /// nothing shadows the keyword.
+    fn keyword(&mut self, name: &str, env: &Arc<Env>) -> Result<ExprRef, Unwind> {
+        let mut outermost = env;
+        while let Some(parent) = outermost.parent() {
+            outermost = parent;
+        }
+        self.rename(&Expr::symbol(name), outermost)
+    }
+}
+
+/// Expand an expression in an environment, with `ev` running the macros it
+/// uses.
+pub fn expand(ev: &mut Evaluator, expr: Expr, env: &Arc<Env>) -> Result<ExprRef, Error> {
+    ev.in_heap(|ev| ev.expand_in(expr, env))
+}
+
+/// Expand an expression in an environment if it's a macro use, just once,
+/// with `ev` running the macro.
+pub fn expand_once(ev: &mut Evaluator, expr: Expr, env: &Arc<Env>) -> Result<ExprRef, Error> {
+    ev.in_heap(|ev| ev.expand_once_in(expr, env))
+}
+
+// Identifiers bound inside the expansion become their alias; others are
+// left to be resolved where the macro was defined.
+fn reference(expr: &ExprRef, env: &Env) -> ExprRef {
+    match expr.kind {
+        ExprKind::Identifier(ref id) if env.lookup(id.alias).is_some() => {
+            let mut symbol = Expr::new(ExprKind::Symbol(id.alias));
+            symbol.span = expr.span;
+            symbol.alloc()
+        }
+        _ => expr.clone(),
+    }
+}
+
+// Special form names, and the auxiliary keywords some of them use, aren't
+// variables, so they're never unbound.
+fn is_keyword(name: Symbol) -> bool {
+    special::lookup(name).is_some() || name == keywords::ELSE || name == keywords::ARROW
+}
+
+// Make a list of expanded items standing in for `form`.
+fn rebuild(form: &ExprRef, items: Vec<ExprRef>) -> ExprRef {
+    items
+        .into_iter()
+        .rev()
+        .fold(Expr::nil(), |tail, item| cons(item, tail, form.span))
+}
+
+fn is_unspecified(expr: &Expr) -> bool {
+    match expr.kind {
+        ExprKind::Unspecified => true,
+        _ => false,
+    }
+}
+
+fn head(form: &ExprRef) -> ExprRef {
+    form.car().unwrap_or_else(Expr::nil)
+}
+
+fn quote(ev: &mut Evaluator, form: &ExprRef, args: &[ExprRef], _: &Arc<Env>) -> Result<ExprRef, Unwind> {
+    if args.len() != 1 {
+        return Err(ev.syntax_error(form));
+    }
+    Ok(rebuild(form, vec![head(form), syntax::strip(&args[0])]))
+}
+
+fn operands(ev: &mut Evaluator, form: &ExprRef, args: &[ExprRef], env: &Arc<Env>) -> Result<ExprRef, Unwind> {
+    let mut items = vec![head(form)];
+    items.extend(ev.expand_all(args, env)?);
+    Ok(rebuild(form, items))
+}
+
+fn sequence(ev: &mut Evaluator, form: &ExprRef, args: &[ExprRef], env: &Arc<Env>) -> Result<ExprRef, Unwind> {
+    let mut items = vec![head(form)];
+    items.extend(ev.expand_body(args, env)?);
+    Ok(rebuild(form, items))
+}
+
+fn cond(ev: &mut Evaluator, form: &ExprRef, args: &[ExprRef], env: &Arc<Env>) -> Result<ExprRef, Unwind> {
+    let mut items = vec![head(form)];
+    for clause in args {
+        match clause.to_vec() {
+            Some(ref parts) if !parts.is_empty() => {
+                let parts = ev.expand_all(parts, env)?;
+                items.push(rebuild(clause, parts));
+            }
+            _ => return Err(ev.syntax_error(clause)),
+        }
+    }
+    Ok(rebuild(form, items))
+}
+
+fn define(ev: &mut Evaluator, form: &ExprRef, args: &[ExprRef], env: &Arc<Env>) -> Result<ExprRef, Unwind> {
+    if args.is_empty() {
+        return Err(ev.syntax_error(form));
+    }
+
+    if let ExprKind::Pair(ref target) = args[0].kind {
+        let name = ev.bind(&target.car.borrow(), env)?;
+        let scope = Env::child(&env);
+        let params = ev.expand_params(&target.cdr.borrow(), &scope)?;
+        let mut items = vec![head(form), cons(name, params, args[0].span)];
+        items.extend(ev.expand_body(&args[1..], &scope)?);
+        return Ok(rebuild(form, items));
+    }
+
+    if args.len() != 2 {
+        return Err(ev.syntax_error(form));
+    }
+    let name = ev.bind(&args[0], env)?;
+    let value = ev.expand_expr(&args[1], env)?;
+    Ok(rebuild(form, vec![head(form), name, value]))
+}
+
+fn lambda(ev: &mut Evaluator, form: &ExprRef, args: &[ExprRef], env: &Arc<Env>) -> Result<ExprRef, Unwind> {
+    if args.is_empty() {
+        return Err(ev.syntax_error(form));
+    }
+
+    let scope = Env::child(&env);
+    let mut items = vec![head(form), ev.expand_params(&args[0], &scope)?];
+    items.extend(ev.expand_body(&args[1..], &scope)?);
+    Ok(rebuild(form, items))
+}
+
+fn case_lambda(ev: &mut Evaluator, form: &ExprRef, args: &[ExprRef], env: &Arc<Env>) -> Result<ExprRef, Unwind> {
+    let mut items = vec![head(form)];
+    for clause in args {
+        let parts = match clause.to_vec() {
+            Some(ref parts) if !parts.is_empty() => parts.clone(),
+            _ => return Err(ev.syntax_error(form)),
+        };
+        let scope = Env::child(&env);
+        let mut expanded = vec![ev.expand_params(&parts[0], &scope)?];
+        expanded.extend(ev.expand_body(&parts[1..], &scope)?);
+        items.push(rebuild(clause, expanded));
+    }
+    Ok(rebuild(form, items))
+}
+
+fn let_form(ev: &mut Evaluator, form: &ExprRef, args: &[ExprRef], env: &Arc<Env>) -> Result<ExprRef, Unwind> {
+    if args.len() < 2 {
+        return Err(ev.syntax_error(form));
+    }
+
+    let mut items = vec![head(form)];
+    let mut args = args;
+
+    // Named `let` binds its name around the body.
+    let mut outer = env.clone();
+    if binding_key(&args[0]).is_some() {
+        outer = Env::child(&env);
+        items.push(ev.bind(&args[0], &outer)?);
+        args = &args[1..];
+    }
+
+    let scope = Env::child(&outer);
+    items.push(ev.expand_bindings(form, &args[0], env, &scope)?);
+    items.extend(ev.expand_body(&args[1..], &scope)?);
+    Ok(rebuild(form, items))
+}
+
+fn let_star(ev: &mut Evaluator, form: &ExprRef, args: &[ExprRef], env: &Arc<Env>) -> Result<ExprRef, Unwind> {
+    if args.len() < 2 {
+        return Err(ev.syntax_error(form));
+    }
+
+    let mut scope = env.clone();
+    let mut bindings = Vec::new();
+    for binding in args[0].to_vec().unwrap_or_default() {
+        let inner = Env::child(&scope);
+        let expanded = ev.expand_bindings(form, &Expr::list(vec![binding]), &scope, &inner)?;
+        bindings.extend(expanded.car());
+        scope = inner;
+    }
+
+    let mut items = vec![head(form), Expr::list(bindings)];
+    items.extend(ev.expand_body(&args[1..], &scope)?);
+    Ok(rebuild(form, items))
+}
+
+fn letrec(ev: &mut Evaluator, form: &ExprRef, args: &[ExprRef], env: &Arc<Env>) -> Result<ExprRef, Unwind> {
+    if args.len() < 2 {
+        return Err(ev.syntax_error(form));
+    }
+
+    // Every name is bound before any initializer is expanded.
+    let scope = Env::child(&env);
+    for binding in args[0].to_vec().unwrap_or_default() {
+        if let Some(name) = binding.car() {
+            ev.bind(&name, &scope)?;
+        }
+    }
+
+    let mut items = vec![head(form), ev.expand_bindings(form, &args[0], &scope, &scope)?];
+    items.extend(ev.expand_body(&args[1..], &scope)?);
+    Ok(rebuild(form, items))
+}
+
+fn guard(ev: &mut Evaluator, form: &ExprRef, args: &[ExprRef], env: &Arc<Env>) -> Result<ExprRef, Unwind> {
+    let spec = match args.first().and_then(|spec| spec.to_vec()) {
+        Some(ref spec) if !spec.is_empty() => spec.clone(),
+        _ => return Err(ev.syntax_error(form)),
+    };
+
+    let scope = Env::child(&env);
+    let var = ev.bind(&spec[0], &scope)?;
+    let clauses = cond(ev, &args[0], &spec[1..], &scope)?;
+    let clauses = cons(var, clauses.cdr().unwrap_or_else(Expr::nil), args[0].span);
+
+    let mut items = vec![head(form), clauses];
+    items.extend(ev.expand_body(&args[1..], env)?);
+    Ok(rebuild(form, items))
+}
+
+fn let_values(ev: &mut Evaluator, form: &ExprRef, args: &[ExprRef], env: &Arc<Env>) -> Result<ExprRef, Unwind> {
+    values_bindings(ev, form, args, env, false)
+}
+
+fn let_star_values(ev: &mut Evaluator, form: &ExprRef, args: &[ExprRef], env: &Arc<Env>) -> Result<ExprRef, Unwind> {
+    values_bindings(ev, form, args, env, true)
+}
+
+// Each binding's formals are bound like a `lambda`'s parameters, in one
+// scope for `let-values` or a scope apiece for `let*-values`.
+fn values_bindings(
+    ev: &mut Evaluator,
+    form: &ExprRef,
+    args: &[ExprRef],
+    env: &Arc<Env>,
+    sequential: bool,
+) -> Result<ExprRef, Unwind> {
+    if args.len() < 2 {
+        return Err(ev.syntax_error(form));
+    }
+
+    let mut outer = env.clone();
+    let mut scope = Env::child(&env);
+    let mut bindings = Vec::new();
+    for binding in args[0].to_vec().unwrap_or_default() {
+        let parts = match binding.to_vec() {
+            Some(ref parts) if parts.len() == 2 => parts.clone(),
+            _ => return Err(ev.syntax_error(form)),
+        };
+        if sequential {
+            outer = scope.clone();
+            scope = Env::child(&outer);
+        }
+        let init = ev.expand_expr(&parts[1], &outer)?;
+        let formals = ev.expand_params(&parts[0], &scope)?;
+        bindings.push(rebuild(&binding, vec![formals, init]));
+    }
+
+    let mut items = vec![head(form), Expr::list(bindings)];
+    items.extend(ev.expand_body(&args[1..], &scope)?);
+    Ok(rebuild(form, items))
+}
+
+fn define_values(ev: &mut Evaluator, form: &ExprRef, args: &[ExprRef], env: &Arc<Env>) -> Result<ExprRef, Unwind> {
+    if args.len() != 2 {
+        return Err(ev.syntax_error(form));
+    }
+    let value = ev.expand_expr(&args[1], env)?;
+    let formals = ev.expand_params(&args[0], env)?;
+    Ok(rebuild(form, vec![head(form), formals, value]))
+}
+
+fn receive(ev: &mut Evaluator, form: &ExprRef, args: &[ExprRef], env: &Arc<Env>) -> Result<ExprRef, Unwind> {
+    if args.len() < 3 {
+        return Err(ev.syntax_error(form));
+    }
+
+    let value = ev.expand_expr(&args[1], env)?;
+    let scope = Env::child(&env);
+    let mut items = vec![head(form), ev.expand_params(&args[0], &scope)?, value];
+    items.extend(ev.expand_body(&args[2..], &scope)?);
+    Ok(rebuild(form, items))
+}
+
+fn parameterize(ev: &mut Evaluator, form: &ExprRef, args: &[ExprRef], env: &Arc<Env>) -> Result<ExprRef, Unwind> {
+    if args.len() < 2 {
+        return Err(ev.syntax_error(form));
+    }
+
+    let bindings = cond(ev, &args[0], &args[0].to_vec().unwrap_or_default(), env)?;
+    let mut items = vec![head(form), bindings.cdr().unwrap_or_else(Expr::nil)];
+    items.extend(ev.expand_body(&args[1..], env)?);
+    Ok(rebuild(form, items))
+}
+
+// Everything a record type definition names is bound where it appears.
+fn record_type(ev: &mut Evaluator, form: &ExprRef, args: &[ExprRef], env: &Arc<Env>) -> Result<ExprRef, Unwind> {
+    let mut items = vec![head(form)];
+    for (i, arg) in args.iter().enumerate() {
+        items.push(match arg.kind {
+            ExprKind::Pair(..) => {
+                let names = arg.to_vec().unwrap_or_default();
+                let mut bound = Vec::with_capacity(names.len());
+                for (j, name) in names.iter().enumerate() {
+                    // The fields a constructor takes are named, not bound.
+                    if i == 1 && j > 0 || i > 2 && j == 0 {
+                        bound.push(syntax::strip(name));
+                    } else {
+                        bound.push(ev.bind(name, env)?);
+                    }
+                }
+                rebuild(arg, bound)
+            }
+            _ if i < 3 && binding_key(arg).is_some() => ev.bind(arg, env)?,
+            _ => syntax::strip(arg),
+        });
+    }
+    Ok(rebuild(form, items))
+}
+
+// At the top level of a program that's going to be run, the definition is
+// kept as well, so running it leaves the macro defined for later programs.
+fn define_syntax(ev: &mut Evaluator, form: &ExprRef, args: &[ExprRef], env: &Arc<Env>) -> Result<ExprRef, Unwind> {
+    syntax::define_syntax(ev, form, args, env)?;
+    match ev.toplevel {
+        Some(ref scope) if Arc::ptr_eq(scope, env) => Ok(form.clone()),
+        _ => Ok(Expr::unspecified()),
+    }
+}
+
+fn let_syntax(ev: &mut Evaluator, form: &ExprRef, args: &[ExprRef], env: &Arc<Env>) -> Result<ExprRef, Unwind> {
+    syntax_bindings(ev, form, args, env, false)
+}
+
+fn letrec_syntax(ev: &mut Evaluator, form: &ExprRef, args: &[ExprRef], env: &Arc<Env>) -> Result<ExprRef, Unwind> {
+    syntax_bindings(ev, form, args, env, true)
+}
+
+// The macros are used up, leaving the body in a `let` of its own, since it
+// may have definitions of its own.
+fn syntax_bindings(
+    ev: &mut Evaluator,
+    form: &ExprRef,
+    args: &[ExprRef],
+    env: &Arc<Env>,
+    recursive: bool,
+) -> Result<ExprRef, Unwind> {
+    if args.len() < 2 {
+        return Err(ev.syntax_error(form));
+    }
+
+    let scope = Env::child(&env);
+    ev.bind_syntax(form, &args[0], env, &scope, recursive)?;
+
+    let mut items = vec![ev.keyword("let", env)?, Expr::nil()];
+    items.extend(ev.expand_body(&args[1..], &scope)?);
+    Ok(rebuild(form, items))
+}
@@ -0,0 +1,332 @@
+//! Copying an evaluator, so many can start out the same way without each
+//! setting itself up from scratch.
+//!
+//! Everything reachable from the global environment is copied onto the new
+//! evaluator's heap, with values that were shared, or part of a cycle,
+//! shared or cyclic in the copy too. Neither evaluator sees what the other
+//! does afterwards. Values which can't change, like numbers and symbols,
+//! are shared rather than copied, and so are host objects, which share their
+//! Rust value the way a cloned `Host` does.
+//!
+//! Open ports can't be copied at all. A duplicate of a file would still
+//! share its position with the original, and reopening an output file would
+//! empty it, so an evaluator which can reach an open port isn't forked.
+//! Closed ports can't be used for anything, and are shared.
+//!
+//! The copy doesn't recurse into the values which hold others, since a
+//! list may be longer than the stack is deep. Each is made empty at first,
+//! and filled in from a queue once it has a copy of its own to refer to.
+
+use atomic_refcell::AtomicRefCell;
+use read::parse::expr::{
+    CaseLambda, Closure, Condition, Continuation, Entry, Env, Ephemeron, Expr, ExprKind, ExprRef, Guardian,
+    HashTable, Held, Identifier, Pair, Parameter, Primitive, Promise, PromiseState, Record, Syntax, WeakBox,
+};
+use read::parse::heap::{self, Heap};
+use std::collections::HashMap;
+use std::mem;
+use std::sync::Arc;
+use {tables, Evaluator, InterruptHandle};
+
+impl Evaluator {
+    /// Make a new evaluator which starts out as a copy of this one, with the
+    /// same definitions, limits and settings, but a heap of its own. If it
+    /// can reach an open port, it can't be copied, and that port is given
+    /// back instead.
+    pub fn fork(&self) -> Result<Evaluator, ExprRef> {
+        let mode = match self.heap {
+            Some(ref own) => own.mode(),
+            None => heap::mode(),
+        };
+
+        let entered = heap::enter(Heap::new());
+        let mut copier = Copier::new();
+        let global = copier.env(&self.global);
+        copier.finish();
+        if let Some(port) = copier.port.take() {
+            return Err(port);
+        }
+        drop(copier);
+        heap::set_mode(mode);
+
+        Ok(Evaluator {
+            global,
+            handlers: Vec::new(),
+            span: None,
+            next_guard: 0,
+            // Continuations which were copied keep their ids, so new ones
+            // mustn't reuse them.
+            next_continuation: self.next_continuation,
+            continuations: Vec::new(),
+            literal_checks: self.literal_checks,
+            unbound_checks: self.unbound_checks,
+            free: Vec::new(),
+            toplevel: None,
+            limits: self.limits,
+            usage: Default::default(),
+            interrupt: InterruptHandle::new(),
+            heap: Some(entered.leave()),
+        })
+    }
+}
+
+/// The copies made so far, by the address of what they copy.
+struct Copier {
+    exprs: HashMap<usize, ExprRef>,
+    envs: HashMap<usize, Arc<Env>>,
+    /// Promise states, which promises forced through `delay-force` share.
+    states: HashMap<usize, Arc<AtomicRefCell<PromiseState>>>,
+    /// The first open port come across, which stops the copy.
+    port: Option<ExprRef>,
+    /// Copies still to be filled in, each with what it copies.
+    unfilled: Vec<Unfilled>,
+}
+
+enum Unfilled {
+    Expr(ExprRef, ExprRef),
+    Env(Arc<Env>, Arc<Env>),
+}
+
+impl Copier {
+    fn new() -> Copier {
+        Copier {
+            exprs: HashMap::new(),
+            envs: HashMap::new(),
+            states: HashMap::new(),
+            port: None,
+            unfilled: Vec::new(),
+        }
+    }
+
+    /// Fill in every copy made so far, and any made along the way.
+    fn finish(&mut self) {
+        while let Some(unfilled) = self.unfilled.pop() {
+            match unfilled {
+                Unfilled::Expr(original, copy) => self.fill(&original, &copy),
+                Unfilled::Env(original, copy) => {
+                    for (name, value) in original.bindings() {
+                        let value = self.expr(&value);
+                        copy.define(name, value);
+                    }
+                }
+            }
+        }
+    }
+
+    fn env(&mut self, env: &Arc<Env>) -> Arc<Env> {
+        let address = &**env as *const Env as usize;
+        if let Some(copy) = self.envs.get(&address) {
+            return copy.clone();
+        }
+
+        // Environments only nest as deeply as the program's scopes do.
+        let parent = env.parent().map(|parent| self.env(parent));
+        let copy = Env::new(parent).alloc();
+        self.envs.insert(address, copy.clone());
+        self.unfilled.push(Unfilled::Env(env.clone(), copy.clone()));
+        copy
+    }
+
+    fn expr(&mut self, expr: &ExprRef) -> ExprRef {
+        let address = &**expr as *const Expr as usize;
+        if let Some(copy) = self.exprs.get(&address) {
+            return copy.clone();
+        }
+
+        let mut unfilled = true;
+        let kind = match expr.kind {
+            ExprKind::Nil
+            | ExprKind::Unspecified
+            | ExprKind::Bool(..)
+            | ExprKind::Char(..)
+            | ExprKind::Num(..)
+            | ExprKind::Symbol(..) => return expr.clone(),
+            ExprKind::Port(ref port) => {
+                if port.is_open() && self.port.is_none() {
+                    self.port = Some(expr.clone());
+                }
+                return expr.clone();
+            }
+            ExprKind::Pair(..) => ExprKind::Pair(Pair {
+                car: AtomicRefCell::new(Expr::nil()),
+                cdr: AtomicRefCell::new(Expr::nil()),
+            }),
+            ExprKind::Vector(..) => ExprKind::Vector(AtomicRefCell::new(Vec::new())),
+            ExprKind::Record(ref record) => ExprKind::Record(Record {
+                record_type: record.record_type.clone(),
+                values: AtomicRefCell::new(Vec::new()),
+            }),
+            ExprKind::Promise(..) => ExprKind::Promise(Promise::new(PromiseState::Done(Expr::nil()))),
+            ExprKind::Parameter(ref parameter) => ExprKind::Parameter(Parameter {
+                value: AtomicRefCell::new(Expr::nil()),
+                converter: parameter.converter.as_ref().map(|converter| self.expr(converter)),
+            }),
+            ExprKind::Ephemeron(..) => ExprKind::Ephemeron(Ephemeron {
+                key: AtomicRefCell::new(Held::broken()),
+                datum: AtomicRefCell::new(Expr::nil()),
+            }),
+            ExprKind::HashTable(ref table) => ExprKind::HashTable(HashTable::new(table.weakness)),
+            ExprKind::Guardian(..) => ExprKind::Guardian(Guardian::new()),
+            _ => {
+                unfilled = false;
+                self.kind(&expr.kind)
+            }
+        };
+
+        let mut copy = Expr::new(kind);
+        copy.mutable = expr.mutable;
+        copy.span = expr.span;
+        let copy = copy.alloc();
+        self.exprs.insert(address, copy.clone());
+        if unfilled {
+            self.unfilled.push(Unfilled::Expr(expr.clone(), copy.clone()));
+        }
+        copy
+    }
+
+    /// Copy a kind of value which can be made whole straight away. What it
+    /// holds outside of a cell can't lead back to it, so copying that first
+    /// never goes round in a circle.
+    fn kind(&mut self, kind: &ExprKind) -> ExprKind {
+        match *kind {
+            ExprKind::Str(ref s) => ExprKind::Str(AtomicRefCell::new(s.borrow().clone())),
+            ExprKind::ByteVector(ref bytes) => ExprKind::ByteVector(AtomicRefCell::new(bytes.borrow().clone())),
+            ExprKind::Closure(ref closure) => ExprKind::Closure(Closure {
+                env: self.env(&closure.env),
+                syntactic: closure.syntactic,
+                body: self.expr(&closure.body),
+                args: self.expr(&closure.args),
+                arity: closure.arity,
+                name: AtomicRefCell::new(*closure.name.borrow()),
+            }),
+            ExprKind::CaseLambda(ref case) => ExprKind::CaseLambda(CaseLambda {
+                clauses: case.clauses.iter().map(|clause| self.expr(clause)).collect(),
+                name: AtomicRefCell::new(*case.name.borrow()),
+            }),
+            ExprKind::Primitive(ref primitive) => ExprKind::Primitive(Primitive {
+                name: primitive.name.clone(),
+                func: primitive.func.clone(),
+            }),
+            ExprKind::Syntax(ref syntax) => ExprKind::Syntax(Syntax {
+                transformer: self.expr(&syntax.transformer),
+                env: self.env(&syntax.env),
+            }),
+            ExprKind::Identifier(ref id) => ExprKind::Identifier(Identifier {
+                name: self.expr(&id.name),
+                env: self.env(&id.env),
+                alias: id.alias,
+            }),
+            ExprKind::Continuation(ref continuation) => ExprKind::Continuation(Continuation { id: continuation.id }),
+            ExprKind::Condition(ref condition) => ExprKind::Condition(Condition {
+                kind: condition.kind,
+                message: condition.message.clone(),
+                irritants: self.expr(&condition.irritants),
+                span: condition.span,
+            }),
+            ExprKind::Env(ref env) => ExprKind::Env(self.env(env)),
+            ExprKind::RecordType(ref record_type) => ExprKind::RecordType(record_type.clone()),
+            ExprKind::Values(ref values) => ExprKind::Values(values.iter().map(|value| self.expr(value)).collect()),
+            ExprKind::WeakBox(ref weak) => ExprKind::WeakBox(WeakBox {
+                value: self.held(&weak.value),
+            }),
+            ExprKind::Host(ref object) => ExprKind::Host(object.clone()),
+            _ => unreachable!("values holding others in cells are filled in later"),
+        }
+    }
+
+    /// Copy a weakly held value. The copy is only kept alive if something
+    /// else in the copy holds it, just as the original is.
+    fn held(&mut self, held: &Held) -> Held {
+        match *held {
+            Held::Strong(ref value) => Held::Strong(self.expr(value)),
+            Held::Weak(..) => match held.get() {
+                Some(value) => Held::new(&self.expr(&value)),
+                None => Held::broken(),
+            },
+        }
+    }
+
+    fn fill(&mut self, original: &ExprRef, copy: &ExprRef) {
+        let reference = mem::size_of::<ExprRef>();
+        match (&original.kind, &copy.kind) {
+            (&ExprKind::Pair(ref from), &ExprKind::Pair(ref to)) => {
+                let car = self.expr(&from.car.borrow());
+                let cdr = self.expr(&from.cdr.borrow());
+                *to.car.borrow_mut() = car;
+                *to.cdr.borrow_mut() = cdr;
+            }
+            (&ExprKind::Vector(ref from), &ExprKind::Vector(ref to)) => {
+                let items: Vec<_> = from.borrow().iter().map(|item| self.expr(item)).collect();
+                copy.grow(items.len() * reference);
+                *to.borrow_mut() = items;
+            }
+            (&ExprKind::Record(ref from), &ExprKind::Record(ref to)) => {
+                let values: Vec<_> = from.values.borrow().iter().map(|value| self.expr(value)).collect();
+                copy.grow(values.len() * reference);
+                *to.values.borrow_mut() = values;
+            }
+            (&ExprKind::Promise(ref from), &ExprKind::Promise(ref to)) => {
+                let state = from.state.borrow().clone();
+                let address = &*state as *const AtomicRefCell<PromiseState> as usize;
+                let copied = match self.states.get(&address) {
+                    Some(copied) => copied.clone(),
+                    None => {
+                        let copied = match *state.borrow() {
+                            PromiseState::Done(ref value) => PromiseState::Done(self.expr(value)),
+                            PromiseState::Delayed(ref expr, ref env) => {
+                                PromiseState::Delayed(self.expr(expr), self.env(env))
+                            }
+                            PromiseState::DelayedForce(ref expr, ref env) => {
+                                PromiseState::DelayedForce(self.expr(expr), self.env(env))
+                            }
+                        };
+                        let copied = Arc::new(AtomicRefCell::new(copied));
+                        self.states.insert(address, copied.clone());
+                        copied
+                    }
+                };
+                *to.state.borrow_mut() = copied;
+            }
+            (&ExprKind::Parameter(ref from), &ExprKind::Parameter(ref to)) => {
+                let value = self.expr(&from.value.borrow());
+                *to.value.borrow_mut() = value;
+            }
+            (&ExprKind::Ephemeron(ref from), &ExprKind::Ephemeron(ref to)) => {
+                let key = self.held(&from.key.borrow());
+                let datum = self.expr(&from.datum.borrow());
+                *to.key.borrow_mut() = key;
+                *to.datum.borrow_mut() = datum;
+            }
+            // Keys compared by `eq?` are hashed by address, so the entries
+            // are put in again under the keys' copies.
+            (&ExprKind::HashTable(ref from), &ExprKind::HashTable(ref to)) => {
+                let entries: Vec<_> = from
+                    .buckets
+                    .borrow()
+                    .values()
+                    .flat_map(|entries| entries.iter())
+                    .filter_map(|entry| entry.key.get().map(|key| (key, entry.value.clone())))
+                    .collect();
+                for (key, value) in entries {
+                    let key = self.expr(&key);
+                    let value = self.expr(&value);
+                    if tables::insert(to, &key, value) {
+                        copy.grow(mem::size_of::<Entry>());
+                    }
+                }
+            }
+            (&ExprKind::Guardian(ref from), &ExprKind::Guardian(ref to)) => {
+                let registered: Vec<_> = from
+                    .registered
+                    .borrow()
+                    .iter()
+                    .map(|&(ref object, ref representative)| (self.expr(object), self.expr(representative)))
+                    .collect();
+                let queue = from.queue.borrow().iter().map(|value| self.expr(value)).collect();
+                *to.registered.borrow_mut() = registered;
+                *to.queue.borrow_mut() = queue;
+            }
+            _ => {}
+        }
+    }
+}
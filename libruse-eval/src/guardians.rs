@@ -0,0 +1,49 @@
+//! Guardians, in the style of Dybvig, Bruggeman and Eby's "Guardians in a
+//! Generation-Based Garbage Collector".
+//!
+//! A guardian is a procedure. Called with an object (and optionally a
+//! representative for it), it registers the object; called with nothing, it
+//! gives back the next object, or representative, the collector has found
+//! unreachable, or `#f` if there are none.
+
+use error::Unwind;
+use read::parse::expr::{Env, Expr, ExprKind, ExprRef, Guardian};
+use read::parse::heap;
+use {define_primitive, Evaluator, Step};
+
+impl Evaluator {
+    /// Register an object with a guardian, or take the next one off its
+    /// queue.
+    pub(crate) fn call_guardian(
+        &mut self,
+        guardian: &Guardian,
+        mut args: Vec<ExprRef>,
+    ) -> Result<Step, Unwind> {
+        match args.len() {
+            0 => {
+                let next = guardian.queue.borrow_mut().pop_front();
+                match next {
+                    Some(value) => {
+                        // The queue may not have been traced yet.
+                        heap::write_barrier(&value);
+                        Ok(Step::Done(value))
+                    }
+                    None => Ok(Step::Done(Expr::boolean(false))),
+                }
+            }
+            1 | 2 => {
+                let object = args.remove(0);
+                let representative = args.pop().unwrap_or_else(|| object.clone());
+                guardian.registered.borrow_mut().push((object, representative));
+                Ok(Step::Done(Expr::unspecified()))
+            }
+            _ => Err(self.arity_error(None, "0 to 2", args)),
+        }
+    }
+}
+
+pub fn define_primitives(env: &Env) {
+    define_primitive(env, "make-guardian", 0, Some(0), |_, _| {
+        Ok(Expr::new(ExprKind::Guardian(Guardian::new())).alloc())
+    });
+}
@@ -0,0 +1,83 @@
+//! Interrupting a running program from another thread.
+//!
+//! Each evaluator has a flag which its handles set, and which it looks at
+//! every time it takes a step. An interrupt either raises an interrupt
+//! condition, which the program can catch with `interrupt-condition?` but
+//! which isn't an error object, or unwinds it without running any handlers
+//! at all.
+
+use error::Unwind;
+use read::parse::expr::ConditionKind;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use Evaluator;
+
+const NONE: usize = 0;
+const RAISE: usize = 1;
+const STOP: usize = 2;
+
+/// A handle for interrupting the programs an evaluator runs, which can be
+/// sent to and shared between other threads.
+///
+/// An interrupt stays pending until the evaluator next takes a step, and
+/// only then is it cleared. One made while nothing is running, like just
+/// before a program starts, is acted on by the next program the evaluator
+/// runs, unless it's withdrawn with `reset` first.
+#[derive(Debug, Clone)]
+pub struct InterruptHandle {
+    flag: Arc<AtomicUsize>,
+}
+
+impl InterruptHandle {
+    pub(crate) fn new() -> InterruptHandle {
+        InterruptHandle {
+            flag: Arc::new(AtomicUsize::new(NONE)),
+        }
+    }
+
+    /// Raise an interrupt condition in the running program, which it can
+    /// catch and carry on from. Left uncaught, it ends the program with
+    /// `Error::Interrupted`.
+    pub fn interrupt(&self) {
+        // Stopping wins over raising if both are asked for.
+        let _ = self.flag.compare_exchange(NONE, RAISE, Ordering::SeqCst, Ordering::SeqCst);
+    }
+
+    /// Stop the running program with `Error::Interrupted`, without giving
+    /// it the chance to catch anything.
+    pub fn stop(&self) {
+        self.flag.store(STOP, Ordering::SeqCst);
+    }
+
+    // Most steps find nothing pending, so they only need to look.
+    fn take(&self) -> usize {
+        if self.flag.load(Ordering::Relaxed) == NONE {
+            return NONE;
+        }
+        self.flag.swap(NONE, Ordering::SeqCst)
+    }
+
+    /// Withdraw an interrupt which hasn't been acted on yet.
+    pub fn reset(&self) {
+        self.flag.store(NONE, Ordering::SeqCst);
+    }
+}
+
+impl Evaluator {
+    /// A handle for interrupting the programs this evaluator runs.
+    pub fn interrupt_handle(&self) -> InterruptHandle {
+        self.interrupt.clone()
+    }
+
+    /// Act on an interrupt, if one is pending.
+    pub(crate) fn check_interrupt(&mut self) -> Result<(), Unwind> {
+        match self.interrupt.take() {
+            RAISE => {
+                let condition = self.condition(ConditionKind::Interrupt, "interrupted", Vec::new());
+                Err(self.throw(condition))
+            }
+            STOP => Err(Unwind::Interrupt),
+            _ => Ok(()),
+        }
+    }
+}
@@ -0,0 +1,91 @@
+//! Lazy evaluation, as in `(scheme lazy)`.
+//!
+//! Forcing runs in a loop rather than recursing through `delay-force`, and
+//! each promise in a chain hands its state on to the next, so forcing a chain
+//! of any length takes constant space.
+
+use error::Unwind;
+use read::parse::expr::{Env, Expr, ExprKind, ExprRef, Promise, PromiseState};
+use std::sync::Arc;
+use {define_primitive, Evaluator, Step};
+
+impl Evaluator {
+    /// Force a promise, giving back its value. Anything else is its own
+    /// value.
+    fn force(&mut self, promise: &ExprRef) -> Result<ExprRef, Unwind> {
+        let promise = match promise.kind {
+            ExprKind::Promise(ref promise) => promise,
+            _ => return Ok(promise.clone()),
+        };
+
+        loop {
+            let (expr, env, delegates) = match promise.get() {
+                PromiseState::Done(value) => return Ok(value),
+                PromiseState::Delayed(expr, env) => (expr, env, false),
+                PromiseState::DelayedForce(expr, env) => (expr, env, true),
+            };
+
+            let value = self.eval_in(expr, env)?;
+
+            // Forcing the promise again while it was being evaluated may
+            // have finished it already, and the first value it got wins.
+            if promise.is_done() {
+                continue;
+            }
+
+            match value.kind {
+                ExprKind::Promise(ref next) if delegates => promise.adopt(next),
+                _ if delegates => {
+                    let message = "delay-force: expected a promise";
+                    return Err(self.error(message, vec![value.clone()]));
+                }
+                _ => promise.set(PromiseState::Done(value.clone())),
+            }
+        }
+    }
+}
+
+pub fn define_primitives(env: &Env) {
+    define_primitive(env, "force", 1, Some(1), |ev, args| ev.force(&args[0]));
+
+    define_primitive(env, "make-promise", 1, Some(1), |_, mut args| {
+        let value = args.pop().unwrap_or_else(Expr::unspecified);
+        match value.kind {
+            ExprKind::Promise(..) => Ok(value.clone()),
+            _ => Ok(make_promise(PromiseState::Done(value.clone()))),
+        }
+    });
+
+    define_primitive(env, "promise?", 1, Some(1), |_, args| {
+        let is_promise = match args[0].kind {
+            ExprKind::Promise(..) => true,
+            _ => false,
+        };
+        Ok(Expr::boolean(is_promise))
+    });
+}
+
+pub fn delay(ev: &mut Evaluator, form: &ExprRef, args: &[ExprRef], env: &Arc<Env>) -> Result<Step, Unwind> {
+    if args.len() != 1 {
+        return Err(ev.syntax_error(form));
+    }
+    let state = PromiseState::Delayed(args[0].clone(), env.clone());
+    Ok(Step::Done(make_promise(state)))
+}
+
+pub fn delay_force(
+    ev: &mut Evaluator,
+    form: &ExprRef,
+    args: &[ExprRef],
+    env: &Arc<Env>,
+) -> Result<Step, Unwind> {
+    if args.len() != 1 {
+        return Err(ev.syntax_error(form));
+    }
+    let state = PromiseState::DelayedForce(args[0].clone(), env.clone());
+    Ok(Step::Done(make_promise(state)))
+}
+
+fn make_promise(state: PromiseState) -> ExprRef {
+    Expr::new(ExprKind::Promise(Promise::new(state))).alloc()
+}
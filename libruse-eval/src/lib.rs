@@ -3,11 +3,475 @@
 #![deny(missing_docs)]
 
 extern crate libruse_read as read;
+extern crate atomic_refcell;
+extern crate stacker;
 
-use read::parse::expr::Expr;
+pub mod error;
+mod control;
+mod exception;
+mod expand;
+mod fork;
+mod guardians;
+mod interrupt;
+mod lazy;
+mod libraries;
+mod limits;
+mod parameters;
+mod ports;
+mod primitives;
+mod records;
+mod rules;
+mod special;
+mod strings;
+mod syntax;
+mod symbols;
+mod tables;
+mod values;
+mod weak;
+
+pub use error::{Error, Failure};
+pub use expand::{expand, expand_once};
+pub use interrupt::InterruptHandle;
+pub use libraries::Library;
+pub use limits::{Limit, Limits};
+
+use error::Unwind;
+use exception::Handler;
+use limits::Usage;
+use read::lex::token::Span;
+use read::parse::expr::{Env, Expr, ExprKind, ExprRef, Primitive, Symbol};
+use read::parse::heap::{self, Heap, Mode, Stats};
+use std::sync::Arc;
+use std::time::Duration;
+use syntax::Resolved;
+
+/// The signature shared by the evaluator's primitive procedures. Most give
+/// back their value directly, but some finish with a tail call.
+type PrimitiveFn = Fn(&mut Evaluator, Vec<ExprRef>) -> Result<Step, Unwind> + Send + Sync;
+
+/// The evaluator's half of a primitive: how many arguments it takes, and
+/// the Rust function that implements it.
+struct Builtin {
+    min: usize,
+    max: Option<usize>,
+    func: Box<PrimitiveFn>,
+}
+
+/// What is left to do once a form has been looked at. Forms either finish
+/// with a value, or leave an expression to evaluate or a procedure to call in
+/// tail position, which the evaluator loops on rather than recursing.
+enum Step {
+    Done(ExprRef),
+    Tail(ExprRef, Arc<Env>),
+    Apply(ExprRef, Vec<ExprRef>),
+}
+
+/// Evaluates expressions against a global environment, keeping track of the
+/// dynamic state (like installed exception handlers) of the running program.
+pub struct Evaluator {
+    global: Arc<Env>,
+    handlers: Vec<Handler>,
+    span: Option<Span>,
+    next_guard: usize,
+    next_continuation: usize,
+    continuations: Vec<usize>,
+    literal_checks: bool,
+    unbound_checks: bool,
+    free: Vec<(ExprRef, Arc<Env>)>,
+    toplevel: Option<Arc<Env>>,
+    limits: Limits,
+    usage: Usage,
+    interrupt: InterruptHandle,
+    /// The heap the evaluator's values are made on, which is taken while
+    /// it's the thread's current heap.
+    heap: Option<Heap>,
+}
+
+impl Default for Evaluator {
+    fn default() -> Evaluator {
+        Evaluator::new()
+    }
+}
+
+impl Evaluator {
+    /// Create an evaluator whose global environment holds the standard
+    /// procedures.
+    pub fn new() -> Evaluator {
+        Evaluator::from_libraries(Library::all())
+    }
+
+    fn from_libraries(libraries: &[Library]) -> Evaluator {
+        let entered = heap::enter(Heap::new());
+        let global = Env::default().alloc();
+        libraries::define_libraries(&global, libraries);
+
+        Evaluator {
+            global,
+            handlers: Vec::new(),
+            span: None,
+            next_guard: 0,
+            next_continuation: 0,
+            continuations: Vec::new(),
+            literal_checks: true,
+            unbound_checks: false,
+            free: Vec::new(),
+            toplevel: None,
+            limits: Limits::default(),
+            usage: Usage::default(),
+            interrupt: InterruptHandle::new(),
+            heap: Some(entered.leave()),
+        }
+    }
+
+    /// Run `f` with the evaluator's heap as the thread's current heap, so
+    /// the values it makes are charged to and collected by this evaluator.
+    /// Calls made from inside `f` are already on the heap.
+    fn in_heap<R, F: FnOnce(&mut Evaluator) -> R>(&mut self, f: F) -> R {
+        let entered = match self.heap.take() {
+            Some(own) => heap::enter(own),
+            None => return f(self),
+        };
+        let result = f(self);
+        self.heap = Some(entered.leave());
+        result
+    }
+
+    /// Statistics about the evaluator's heap and its collector.
+    pub fn gc_stats(&self) -> Stats {
+        match self.heap {
+            Some(ref own) => own.stats(),
+            None => heap::stats(),
+        }
+    }
+
+    /// How long the collector's most recent pauses took, oldest first.
+    pub fn gc_pauses(&self) -> Vec<Duration> {
+        match self.heap {
+            Some(ref own) => own.recent_pauses(),
+            None => heap::recent_pauses(),
+        }
+    }
+
+    /// Collect garbage on the evaluator's heap now, and give back how many
+    /// values were freed.
+    pub fn collect_garbage(&mut self) -> usize {
+        self.in_heap(|_| heap::collect())
+    }
+
+    /// Choose how the collector runs on the evaluator's heap.
+    pub fn set_gc_mode(&mut self, mode: Mode) {
+        self.in_heap(|_| heap::set_mode(mode))
+    }
+
+    /// Choose whether modifying a literal constant, like the pair read from
+    /// `'(1 2)`, is an error. It is by default; older programs which rely on
+    /// modifying their constants can turn the check off.
+    pub fn set_literal_checks(&mut self, enabled: bool) {
+        self.literal_checks = enabled;
+    }
+
+    /// Choose whether programs are checked for variables which aren't bound
+    /// before they run, rather than failing when they reach one. Evaluators
+    /// made with `with_libraries` check by default, and others don't.
+    pub fn set_unbound_checks(&mut self, enabled: bool) {
+        self.unbound_checks = enabled;
+    }
+
+    /// Bind a procedure implemented in Rust in the global environment. It's
+    /// only called with between `min` and `max` arguments, and if it fails,
+    /// its failure is raised as an error.
+    pub fn define_procedure<F>(&mut self, name: &str, min: usize, max: Option<usize>, func: F)
+    where
+        F: Fn(Vec<ExprRef>) -> Result<ExprRef, Failure> + Send + Sync + 'static,
+    {
+        let global = self.global.clone();
+        self.in_heap(|_| {
+            define_primitive(&global, name, min, max, move |ev, args| {
+                func(args).map_err(|failure| ev.error(failure.message, failure.irritants))
+            })
+        });
+    }
+
+    /// Evaluate an expression in the global environment.
+    pub fn eval(&mut self, expr: Expr) -> Result<ExprRef, Error> {
+        self.in_heap(|ev| {
+            let global = ev.global.clone();
+            let expr = expr.alloc();
+            ev.start();
+            let expr = if ev.unbound_checks {
+                ev.check_unbound(&expr)?
+            } else {
+                expr
+            };
+            ev.eval_in(expr, global).map_err(Error::from)
+        })
+    }
+
+    /// Evaluate an expression in the global environment, giving back every
+    /// value it returns.
+    pub fn eval_values(&mut self, expr: Expr) -> Result<Vec<ExprRef>, Error> {
+        self.eval(expr).map(values::into_values)
+    }
+
+    /// Call a procedure with the given arguments, giving back every value it
+    /// returns.
+    pub fn apply_values(&mut self, f: ExprRef, args: Vec<ExprRef>) -> Result<Vec<ExprRef>, Error> {
+        self.call(f, args).map(values::into_values)
+    }
+
+    /// Call a procedure with the given arguments.
+    pub fn call(&mut self, f: ExprRef, args: Vec<ExprRef>) -> Result<ExprRef, Error> {
+        self.in_heap(|ev| {
+            ev.start();
+            ev.apply(f, args).map_err(Error::from)
+        })
+    }
+
+    /// The procedure bound to `name` in the global environment.
+    pub fn procedure(&mut self, name: &str) -> Result<ExprRef, Error> {
+        self.in_heap(|ev| {
+            ev.span = None;
+            match ev.global.lookup(name) {
+                Some(ref value) if value.is_procedure() => Ok(value.clone()),
+                Some(value) => Err(ev.error("not a procedure", vec![value]).into()),
+                None => Err(ev.error("unbound variable", vec![Expr::symbol(name)]).into()),
+            }
+        })
+    }
+
+    fn eval_in(&mut self, expr: ExprRef, env: Arc<Env>) -> Result<ExprRef, Unwind> {
+        self.finish(Step::Tail(expr, env))
+    }
+
+    /// Find the value of a variable.
+    fn lookup(&mut self, id: &ExprRef, env: &Arc<Env>) -> Result<ExprRef, Unwind> {
+        self.span = id.span;
+        match syntax::resolve(id, env) {
+            Some(Resolved::Bound(ref value)) if is_syntax(value) => {
+                Err(self.error("syntax used as a variable", vec![syntax::strip(id)]))
+            }
+            Some(Resolved::Bound(value)) => Ok(value),
+            _ => Err(self.error("unbound variable", vec![syntax::strip(id)])),
+        }
+    }
+
+    fn eval_form(&mut self, form: &ExprRef, env: &Arc<Env>) -> Result<Step, Unwind> {
+        let items = match form.to_vec() {
+            Some(items) => items,
+            None => return Err(self.syntax_error(form)),
+        };
+
+        // Special forms are recognized by keywords nothing has rebound.
+        match syntax::resolve(&items[0], env) {
+            Some(Resolved::Free(name)) => {
+                if let Some(special) = special::lookup(name) {
+                    return special(self, form, &items[1..], env);
+                }
+            }
+            Some(Resolved::Bound(ref value)) => {
+                if let ExprKind::Syntax(ref syntax) = value.kind {
+                    let expansion = self.expand_macro(syntax, form, env)?;
+                    return Ok(Step::Tail(expansion, env.clone()));
+                }
+            }
+            None => {}
+        }
+
+        let f = self.eval_in(items[0].clone(), env.clone())?;
+        let mut args = Vec::with_capacity(items.len() - 1);
+        for item in &items[1..] {
+            args.push(self.eval_in(item.clone(), env.clone())?);
+        }
+
+        self.span = form.span;
+        self.apply_step(f, args)
+    }
+
+    /// Evaluate a body, leaving its last expression in tail position.
+    fn eval_body(&mut self, body: &[ExprRef], env: &Arc<Env>) -> Result<Step, Unwind> {
+        match body.split_last() {
+            None => Ok(Step::Done(Expr::unspecified())),
+            Some((last, init)) => {
+                for expr in init {
+                    self.eval_in(expr.clone(), env.clone())?;
+                }
+                Ok(Step::Tail(last.clone(), env.clone()))
+            }
+        }
+    }
+
+    /// Evaluate a body all the way to its value.
+    fn eval_sequence(&mut self, body: &[ExprRef], env: &Arc<Env>) -> Result<ExprRef, Unwind> {
+        let step = self.eval_body(body, env)?;
+        self.finish(step)
+    }
+
+    /// Run steps until one of them finishes with a value.
+    fn finish(&mut self, step: Step) -> Result<ExprRef, Unwind> {
+        self.enter()?;
+        let result = self.run(step);
+        self.leave();
+        result
+    }
+
+    fn run(&mut self, step: Step) -> Result<ExprRef, Unwind> {
+        let mut step = step;
+
+        loop {
+            step = match step {
+                Step::Done(value) => return Ok(value),
+                Step::Tail(expr, env) => match expr.kind {
+                    ExprKind::Symbol(..) | ExprKind::Identifier(..) => {
+                        return self.lookup(&expr, &env)
+                    }
+                    ExprKind::Pair(..) => {
+                        self.step()?;
+                        heap::safe_point();
+                        self.eval_form(&expr, &env)?
+                    }
+                    _ => return Ok(expr.clone()),
+                },
+                Step::Apply(f, args) => {
+                    self.step()?;
+                    self.apply_step(f, args)?
+                }
+            };
+        }
+    }
+
+    /// Call a procedure and wait for its result.
+    fn apply(&mut self, f: ExprRef, args: Vec<ExprRef>) -> Result<ExprRef, Unwind> {
+        let step = self.apply_step(f, args)?;
+        self.finish(step)
+    }
+
+    fn apply_step(&mut self, f: ExprRef, args: Vec<ExprRef>) -> Result<Step, Unwind> {
+        match f.kind {
+            ExprKind::Primitive(ref primitive) => {
+                let builtin = match primitive.func.downcast_ref::<Builtin>() {
+                    Some(builtin) => builtin,
+                    None => return Err(self.error("not a procedure", vec![f.clone()])),
+                };
+
+                let count = args.len();
+                if count < builtin.min || builtin.max.map_or(false, |max| count > max) {
+                    let expected = match builtin.max {
+                        Some(max) if max == builtin.min => max.to_string(),
+                        Some(max) => format!("{} to {}", builtin.min, max),
+                        None => format!("at least {}", builtin.min),
+                    };
+                    return Err(self.arity_error(Some(&primitive.name), &expected, args));
+                }
+
+                (builtin.func)(self, args)
+            }
+            ExprKind::Closure(ref closure) => {
+                if !closure.arity.accepts(args.len()) {
+                    let expected = closure.arity.to_string();
+                    return Err(self.arity_error(closure.name.borrow().map(Symbol::name), &expected, args));
+                }
+
+                let env = Env::child(&closure.env);
+                syntax::bind_formals(&closure.args, args, &env);
+                let body = closure.body.to_vec().unwrap_or_default();
+                self.eval_body(&body, &env)
+            }
+            ExprKind::CaseLambda(ref case) => {
+                let clause = case.clauses.iter().find(|clause| match clause.kind {
+                    ExprKind::Closure(ref closure) => closure.arity.accepts(args.len()),
+                    _ => false,
+                });
+                match clause {
+                    Some(clause) => self.apply_step(clause.clone(), args),
+                    None => {
+                        let expected: Vec<String> = case
+                            .clauses
+                            .iter()
+                            .filter_map(|clause| match clause.kind {
+                                ExprKind::Closure(ref closure) => Some(closure.arity.to_string()),
+                                _ => None,
+                            })
+                            .collect();
+                        let expected = expected.join(" or ");
+                        Err(self.arity_error(case.name.borrow().map(Symbol::name), &expected, args))
+                    }
+                }
+            }
+            ExprKind::Continuation(ref continuation) => Err(self.escape(continuation, args)),
+            ExprKind::Guardian(ref guardian) => self.call_guardian(guardian, args),
+            ExprKind::Parameter(ref parameter) => {
+                if !args.is_empty() {
+                    return Err(self.arity_error(None, "0", args));
+                }
+                Ok(Step::Done(parameter.value.borrow().clone()))
+            }
+            _ => Err(self.error("not a procedure", vec![f.clone()])),
+        }
+    }
+
+    /// Report a procedure being called with the wrong number of arguments,
+    /// which are given as the irritants.
+    fn arity_error(&mut self, name: Option<&str>, expected: &str, args: Vec<ExprRef>) -> Unwind {
+        let message = match name {
+            Some(name) => format!(
+                "wrong number of arguments to {}: expected {}, got {}",
+                name,
+                expected,
+                args.len()
+            ),
+            None => format!("wrong number of arguments: expected {}, got {}", expected, args.len()),
+        };
+        self.error(message, args)
+    }
+
+    fn syntax_error(&mut self, form: &ExprRef) -> Unwind {
+        self.span = form.span;
+        self.error("bad syntax", vec![form.clone()])
+    }
+}
+
+fn is_syntax(expr: &Expr) -> bool {
+    match expr.kind {
+        ExprKind::Syntax(..) => true,
+        _ => false,
+    }
+}
+
+/// Add a primitive procedure to an environment.
+fn define_primitive<F>(env: &Env, name: &str, min: usize, max: Option<usize>, func: F)
+where
+    F: Fn(&mut Evaluator, Vec<ExprRef>) -> Result<ExprRef, Unwind> + Send + Sync + 'static,
+{
+    env.define(name, make_primitive(name, min, max, func));
+}
+
+/// Add a primitive procedure which can finish with a tail call to an
+/// environment.
+fn define_tail_primitive<F>(env: &Env, name: &str, min: usize, max: Option<usize>, func: F)
+where
+    F: Fn(&mut Evaluator, Vec<ExprRef>) -> Result<Step, Unwind> + Send + Sync + 'static,
+{
+    env.define(name, make_builtin(name, min, max, Box::new(func)));
+}
+
+/// Make a primitive procedure out of a Rust function.
+fn make_primitive<F>(name: &str, min: usize, max: Option<usize>, func: F) -> ExprRef
+where
+    F: Fn(&mut Evaluator, Vec<ExprRef>) -> Result<ExprRef, Unwind> + Send + Sync + 'static,
+{
+    make_builtin(name, min, max, Box::new(move |ev, args| func(ev, args).map(Step::Done)))
+}
+
+fn make_builtin(name: &str, min: usize, max: Option<usize>, func: Box<PrimitiveFn>) -> ExprRef {
+    let builtin = Builtin { min, max, func };
+    let primitive = Primitive {
+        name: name.to_string(),
+        func: Arc::new(builtin),
+    };
+    Expr::new(ExprKind::Primitive(primitive)).alloc()
+}
 
-// TODO: Implement this function.
 /// Evaluates an expression into a result for printing.
-pub fn eval(_: Expr) -> Result<Expr, ()> {
-    unimplemented!()
+pub fn eval(expr: Expr) -> Result<ExprRef, Error> {
+    Evaluator::new().eval(expr)
 }
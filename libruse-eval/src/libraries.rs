@@ -0,0 +1,100 @@
+//! The libraries of standard procedures an evaluator can be given.
+//!
+//! An evaluator made with `Evaluator::new` has every library. One made with
+//! `Evaluator::with_libraries` has only those it's given, along with any
+//! procedures allowed one at a time, so untrusted programs can be kept away
+//! from things like the file system. Programs run by it are checked for
+//! names which aren't bound before they run, so reaching for something left
+//! out fails straight away rather than halfway through.
+
+use read::parse::expr::Env;
+use {control, exception, guardians, lazy, parameters, ports, primitives};
+use {strings, symbols, syntax, tables, values, weak, Evaluator};
+
+/// A group of standard procedures.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Library {
+    /// Numbers, booleans, pairs and lists, symbols, vectors and
+    /// bytevectors, along with exceptions, continuations, multiple values,
+    /// parameters and procedural macros. Of the string procedures there are
+    /// only `string?`, `make-string`, `string-length`, `string-ref`,
+    /// `string-set!`, `string-fill!` and `string-copy`.
+    Base,
+    /// Promises, as in `(scheme lazy)`.
+    Lazy,
+    /// Opening and closing files, as in `(scheme file)`.
+    File,
+    /// Hash tables.
+    HashTables,
+    /// Weak boxes, ephemerons and guardians.
+    Weak,
+}
+
+impl Library {
+    /// Every library.
+    pub fn all() -> &'static [Library] {
+        &[
+            Library::Base,
+            Library::Lazy,
+            Library::File,
+            Library::HashTables,
+            Library::Weak,
+        ]
+    }
+
+    fn define(self, env: &Env) {
+        match self {
+            Library::Base => {
+                primitives::define_primitives(env);
+                control::define_primitives(env);
+                exception::define_primitives(env);
+                parameters::define_primitives(env);
+                syntax::define_primitives(env);
+                symbols::define_primitives(env);
+                strings::define_primitives(env);
+                values::define_primitives(env);
+            }
+            Library::Lazy => lazy::define_primitives(env),
+            Library::File => ports::define_primitives(env),
+            Library::HashTables => tables::define_primitives(env),
+            Library::Weak => {
+                weak::define_primitives(env);
+                guardians::define_primitives(env);
+            }
+        }
+    }
+}
+
+/// Define the procedures of each library in `env`.
+pub fn define_libraries(env: &Env, libraries: &[Library]) {
+    for library in libraries {
+        library.define(env);
+    }
+}
+
+impl Evaluator {
+    /// Create an evaluator whose global environment holds only the
+    /// procedures of the given libraries. Programs it runs are checked for
+    /// unbound variables before they run.
+    pub fn with_libraries(libraries: &[Library]) -> Evaluator {
+        let mut evaluator = Evaluator::from_libraries(libraries);
+        evaluator.unbound_checks = true;
+        evaluator
+    }
+
+    /// Bind one standard procedure in the global environment, whichever
+    /// library it belongs to. Returns `false` if there's no such procedure.
+    pub fn allow(&mut self, name: &str) -> bool {
+        self.in_heap(|ev| {
+            let all = Env::default();
+            define_libraries(&all, Library::all());
+            match all.lookup(name) {
+                Some(procedure) => {
+                    ev.global.define(name, procedure);
+                    true
+                }
+                None => false,
+            }
+        })
+    }
+}
@@ -0,0 +1,186 @@
+//! Limits on how much a program can do.
+//!
+//! A program which runs into one of its limits is stopped where it is.
+//! Unlike an exception, nothing in the program can catch that, though
+//! everything with a dynamic extent still restores itself on the way out,
+//! so the evaluator can go on to run something else.
+
+use error::Unwind;
+use read::parse::heap;
+use stacker;
+use std::fmt;
+use std::time::{Duration, Instant};
+use Evaluator;
+
+/// How many steps are taken between looks at the clock and the heap.
+const CHECK_INTERVAL: u64 = 64;
+
+/// How much of the native stack is kept free. Evaluation which nests any
+/// deeper is stopped as if it ran into its depth limit, leaving room for
+/// whatever a primitive does between one nested evaluation and the next.
+const STACK_RESERVE: usize = 256 * 1024;
+
+/// Limits on the resources a program can use. None are set by default, and
+/// each is counted afresh every time the evaluator is asked to run
+/// something. Whatever the depth limit, evaluation is always stopped before
+/// it runs out of native stack.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Limits {
+    /// How many steps the program can take. Each form evaluated and each
+    /// procedure called in tail position is a step.
+    pub fuel: Option<u64>,
+    /// How deeply evaluation can nest: how many evaluations can be waiting
+    /// on the results of others at once. Tail calls don't nest.
+    pub depth: Option<usize>,
+    /// Roughly how many bytes the values on the evaluator's heap can take
+    /// up. Procedures like `make-vector` check the room they need before
+    /// they allocate, but otherwise the heap is only checked now and then,
+    /// so it can grow an eighth or so past the limit before the program is
+    /// stopped.
+    pub heap: Option<usize>,
+    /// How long the program can run for.
+    pub timeout: Option<Duration>,
+}
+
+/// A limit a program ran into.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Limit {
+    /// It took every step it was allowed.
+    Fuel,
+    /// Its evaluation nested too deeply.
+    Depth,
+    /// The heap filled up, even after collecting garbage.
+    Heap,
+    /// It ran for too long.
+    Timeout,
+}
+
+impl fmt::Display for Limit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let description = match *self {
+            Limit::Fuel => "the program ran out of fuel",
+            Limit::Depth => "the program's evaluation nested too deeply",
+            Limit::Heap => "the program filled the heap",
+            Limit::Timeout => "the program ran for too long",
+        };
+        write!(f, "{}", description)
+    }
+}
+
+/// How much of its limits the program being run has used.
+#[derive(Default)]
+pub(crate) struct Usage {
+    steps: u64,
+    depth: usize,
+    deadline: Option<Instant>,
+    /// How many bytes were in use after the heap limit last called for a
+    /// collection.
+    collected_at: Option<usize>,
+}
+
+impl Evaluator {
+    /// Limit the resources the programs this evaluator runs can use.
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.limits = limits;
+    }
+
+    /// The limits on the resources programs can use.
+    pub fn limits(&self) -> Limits {
+        self.limits
+    }
+
+    /// Get ready to run something new: no handlers are installed, and none
+    /// of the limits have been used up. An interrupt made before it starts
+    /// is left pending, to be acted on at the first step.
+    pub(crate) fn start(&mut self) {
+        self.handlers.clear();
+        self.usage = Usage {
+            steps: 0,
+            depth: 0,
+            deadline: self.limits.timeout.map(|timeout| Instant::now() + timeout),
+            collected_at: None,
+        };
+    }
+
+    /// Take a step, unless that's one more than the program is allowed, or
+    /// it's run out of time or space, or been interrupted.
+    pub(crate) fn step(&mut self) -> Result<(), Unwind> {
+        self.check_interrupt()?;
+        self.usage.steps += 1;
+        if self.limits.fuel.map_or(false, |fuel| self.usage.steps > fuel) {
+            return Err(Unwind::Limit(Limit::Fuel));
+        }
+        if self.usage.steps % CHECK_INTERVAL != 0 {
+            return Ok(());
+        }
+
+        if self.usage.deadline.map_or(false, |deadline| Instant::now() >= deadline) {
+            return Err(Unwind::Limit(Limit::Timeout));
+        }
+        if let Some(cap) = self.limits.heap {
+            self.check_heap(cap)?;
+        }
+        Ok(())
+    }
+
+    /// Stop the program if its values take up more than `cap` bytes. Some
+    /// of them may be garbage, so the heap's only full if it's still too big
+    /// after a collection. Collecting every time the heap is checked while
+    /// it's close to full would take longer than the program, so a
+    /// collection isn't called for again until it's grown by another eighth
+    /// of `cap`.
+    fn check_heap(&mut self, cap: usize) -> Result<(), Unwind> {
+        let bytes = heap::stats().bytes;
+        if bytes <= cap {
+            return Ok(());
+        }
+        let due = self
+            .usage
+            .collected_at
+            .map_or(true, |at| bytes.saturating_sub(at) >= cap / 8);
+        if due {
+            self.collect_for(cap, 0)?;
+        }
+        Ok(())
+    }
+
+    /// Make sure there's room on the heap for `bytes` more before something
+    /// that big is allocated, collecting garbage first if there isn't.
+    pub(crate) fn reserve(&mut self, bytes: usize) -> Result<(), Unwind> {
+        match self.limits.heap {
+            Some(cap) if heap::stats().bytes.saturating_add(bytes) > cap => self.collect_for(cap, bytes),
+            _ => Ok(()),
+        }
+    }
+
+    /// Collect garbage, stopping the program if what's left and `bytes`
+    /// more still take up more than `cap`.
+    fn collect_for(&mut self, cap: usize, bytes: usize) -> Result<(), Unwind> {
+        heap::collect();
+        let used = heap::stats().bytes;
+        self.usage.collected_at = Some(used);
+        if used.saturating_add(bytes) > cap {
+            return Err(Unwind::Limit(Limit::Heap));
+        }
+        Ok(())
+    }
+
+    /// Start an evaluation which the current one waits on, unless that
+    /// nests too deeply, or there's too little native stack left for it.
+    /// Every call must be paired with a call to `leave`.
+    pub(crate) fn enter(&mut self) -> Result<(), Unwind> {
+        if self.limits.depth.map_or(false, |depth| self.usage.depth >= depth) {
+            return Err(Unwind::Limit(Limit::Depth));
+        }
+        if stacker::remaining_stack().map_or(false, |left| left < STACK_RESERVE) {
+            return Err(Unwind::Limit(Limit::Depth));
+        }
+        self.usage.depth += 1;
+        Ok(())
+    }
+
+    /// Finish an evaluation started with `enter`.
+    pub(crate) fn leave(&mut self) {
+        self.usage.depth -= 1;
+    }
+}
@@ -0,0 +1,150 @@
+//! Parameter objects, as made by `make-parameter` and bound by
+//! `parameterize`.
+//!
+//! A parameter holds its current value, which `parameterize` swaps out for
+//! the extent of its body and swaps back however the body is left.
+
+use atomic_refcell::AtomicRefCell;
+use error::{Error, Unwind};
+use read::parse::expr::{Env, Expr, ExprKind, ExprRef, Parameter};
+use read::parse::heap;
+use std::mem;
+use std::sync::Arc;
+use {define_primitive, Evaluator, Step};
+
+impl Evaluator {
+    /// The current value of the parameter bound to `name` in the global
+    /// environment.
+    pub fn parameter(&mut self, name: &str) -> Result<ExprRef, Error> {
+        self.in_heap(|ev| {
+            let parameter = ev.global_parameter(name)?;
+            let value = match parameter.kind {
+                ExprKind::Parameter(ref parameter) => parameter.value.borrow().clone(),
+                _ => Expr::unspecified(),
+            };
+            Ok(value)
+        })
+    }
+
+    /// Give the parameter bound to `name` in the global environment a new
+    /// value, after passing it through the parameter's converter.
+    pub fn set_parameter(&mut self, name: &str, value: ExprRef) -> Result<(), Error> {
+        self.in_heap(|ev| {
+            let parameter = ev.global_parameter(name)?;
+            ev.start();
+            if let ExprKind::Parameter(ref parameter) = parameter.kind {
+                let value = ev.convert(parameter, value)?;
+                heap::write_barrier(&mem::replace(&mut *parameter.value.borrow_mut(), value));
+            }
+            Ok(())
+        })
+    }
+
+    fn global_parameter(&mut self, name: &str) -> Result<ExprRef, Error> {
+        self.span = None;
+        match self.global.lookup(name) {
+            Some(ref value) if is_parameter(value) => Ok(value.clone()),
+            Some(_) => Err(self.error("not a parameter", vec![Expr::symbol(name)]).into()),
+            None => Err(self.error("unbound variable", vec![Expr::symbol(name)]).into()),
+        }
+    }
+
+    /// Pass a value through a parameter's converter, if it has one.
+    fn convert(&mut self, parameter: &Parameter, value: ExprRef) -> Result<ExprRef, Unwind> {
+        match parameter.converter {
+            Some(ref converter) => self.apply(converter.clone(), vec![value]),
+            None => Ok(value),
+        }
+    }
+}
+
+pub fn define_primitives(env: &Env) {
+    define_primitive(env, "make-parameter", 1, Some(2), |ev, mut args| {
+        let converter = if args.len() == 2 { args.pop() } else { None };
+        let value = args.pop().unwrap_or_else(Expr::unspecified);
+
+        if let Some(ref converter) = converter {
+            if !converter.is_procedure() {
+                let message = "make-parameter: expected a procedure";
+                return Err(ev.error(message, vec![converter.clone()]));
+            }
+        }
+
+        let mut parameter = Parameter {
+            value: AtomicRefCell::new(Expr::unspecified()),
+            converter,
+        };
+        let value = ev.convert(&parameter, value)?;
+        parameter.value = AtomicRefCell::new(value);
+        Ok(Expr::new(ExprKind::Parameter(parameter)).alloc())
+    });
+}
+
+/// `(parameterize ((param value) ...) body ...)`
+pub fn parameterize(
+    ev: &mut Evaluator,
+    form: &ExprRef,
+    args: &[ExprRef],
+    env: &Arc<Env>,
+) -> Result<Step, Unwind> {
+    let bindings = match args.first().and_then(|bindings| bindings.to_vec()) {
+        Some(bindings) if args.len() >= 2 => bindings,
+        _ => return Err(ev.syntax_error(form)),
+    };
+
+    let mut pairs = Vec::with_capacity(bindings.len());
+    for binding in bindings {
+        match binding.to_vec() {
+            Some(ref parts) if parts.len() == 2 => {
+                let parameter = ev.eval_in(parts[0].clone(), env.clone())?;
+                let value = ev.eval_in(parts[1].clone(), env.clone())?;
+                pairs.push((parameter, value));
+            }
+            _ => return Err(ev.syntax_error(form)),
+        }
+    }
+
+    // Every value is converted before any parameter takes its new value.
+    let mut converted = Vec::with_capacity(pairs.len());
+    for (parameter, value) in pairs {
+        let value = match parameter.kind {
+            ExprKind::Parameter(ref p) => ev.convert(p, value)?,
+            _ => {
+                ev.span = form.span;
+                let message = "parameterize: expected a parameter";
+                return Err(ev.error(message, vec![parameter.clone()]));
+            }
+        };
+        converted.push((parameter, value));
+    }
+
+    let saved: Vec<ExprRef> = converted
+        .iter()
+        .map(|&(ref p, ref value)| swap(p, value.clone()))
+        .collect();
+    let result = ev.eval_sequence(&args[1..], env);
+    for (&(ref p, _), old) in converted.iter().zip(saved).rev() {
+        swap(p, old);
+    }
+
+    result.map(Step::Done)
+}
+
+/// Give a parameter a new value, returning the old one.
+fn swap(parameter: &ExprRef, value: ExprRef) -> ExprRef {
+    match parameter.kind {
+        ExprKind::Parameter(ref p) => {
+            let old = mem::replace(&mut *p.value.borrow_mut(), value);
+            heap::write_barrier(&old);
+            old
+        }
+        _ => value,
+    }
+}
+
+fn is_parameter(expr: &Expr) -> bool {
+    match expr.kind {
+        ExprKind::Parameter(..) => true,
+        _ => false,
+    }
+}
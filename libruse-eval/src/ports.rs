@@ -0,0 +1,113 @@
+//! Opening and closing file ports.
+//!
+//! A port closes its file when it's freed, but that's up to the collector.
+//! Programs which care when it happens should close ports themselves, or
+//! register them with a guardian to close them once they're unreachable.
+
+use atomic_refcell::AtomicRefCell;
+use error::Unwind;
+use read::parse::expr::{ConditionKind, Env, Expr, ExprKind, ExprRef, Port, PortKind};
+use std::fs::{File, OpenOptions};
+use std::io;
+use {define_primitive, Evaluator};
+
+impl Evaluator {
+    /// Raise a file error for a file that couldn't be opened.
+    fn file_error(&mut self, name: &str, path: &ExprRef, error: io::Error) -> Unwind {
+        let message = format!("{}: {}", name, error);
+        let condition = self.condition(ConditionKind::File, message, vec![path.clone()]);
+        self.throw(condition)
+    }
+}
+
+pub fn define_primitives(env: &Env) {
+    define_primitive(env, "open-input-file", 1, Some(1), |ev, args| {
+        let path = path(ev, "open-input-file", &args[0])?;
+        match File::open(path) {
+            Ok(file) => Ok(make_port(file, true, false)),
+            Err(error) => Err(ev.file_error("open-input-file", &args[0], error)),
+        }
+    });
+    define_primitive(env, "open-output-file", 1, Some(1), |ev, args| {
+        let path = path(ev, "open-output-file", &args[0])?;
+        let file = OpenOptions::new().write(true).create(true).truncate(true).open(path);
+        match file {
+            Ok(file) => Ok(make_port(file, false, true)),
+            Err(error) => Err(ev.file_error("open-output-file", &args[0], error)),
+        }
+    });
+
+    define_primitive(env, "close-port", 1, Some(1), |ev, args| {
+        port(ev, "close-port", &args[0])?.close();
+        Ok(Expr::unspecified())
+    });
+    define_primitive(env, "close-input-port", 1, Some(1), |ev, args| {
+        let port = port(ev, "close-input-port", &args[0])?;
+        if port.readable {
+            port.close();
+        }
+        Ok(Expr::unspecified())
+    });
+    define_primitive(env, "close-output-port", 1, Some(1), |ev, args| {
+        let port = port(ev, "close-output-port", &args[0])?;
+        if port.writable {
+            port.close();
+        }
+        Ok(Expr::unspecified())
+    });
+
+    define_primitive(env, "port?", 1, Some(1), |_, args| {
+        let is_port = match args[0].kind {
+            ExprKind::Port(..) => true,
+            _ => false,
+        };
+        Ok(Expr::boolean(is_port))
+    });
+    define_primitive(env, "input-port?", 1, Some(1), |_, args| {
+        let is_input = match args[0].kind {
+            ExprKind::Port(ref port) => port.readable,
+            _ => false,
+        };
+        Ok(Expr::boolean(is_input))
+    });
+    define_primitive(env, "output-port?", 1, Some(1), |_, args| {
+        let is_output = match args[0].kind {
+            ExprKind::Port(ref port) => port.writable,
+            _ => false,
+        };
+        Ok(Expr::boolean(is_output))
+    });
+    define_primitive(env, "input-port-open?", 1, Some(1), |ev, args| {
+        let port = port(ev, "input-port-open?", &args[0])?;
+        Ok(Expr::boolean(port.readable && port.is_open()))
+    });
+    define_primitive(env, "output-port-open?", 1, Some(1), |ev, args| {
+        let port = port(ev, "output-port-open?", &args[0])?;
+        Ok(Expr::boolean(port.writable && port.is_open()))
+    });
+}
+
+fn make_port(file: File, readable: bool, writable: bool) -> ExprRef {
+    let port = Port {
+        file: AtomicRefCell::new(Some(file)),
+        string: String::new(),
+        kind: PortKind::Textual,
+        writable,
+        readable,
+    };
+    Expr::new(ExprKind::Port(port)).alloc()
+}
+
+fn path(ev: &mut Evaluator, name: &str, arg: &ExprRef) -> Result<String, Unwind> {
+    match arg.kind {
+        ExprKind::Str(ref path) => Ok(path.borrow().clone()),
+        _ => Err(ev.error(format!("{}: expected a string", name), vec![arg.clone()])),
+    }
+}
+
+fn port<'a>(ev: &mut Evaluator, name: &str, arg: &'a ExprRef) -> Result<&'a Port, Unwind> {
+    match arg.kind {
+        ExprKind::Port(ref port) => Ok(port),
+        _ => Err(ev.error(format!("{}: expected a port", name), vec![arg.clone()])),
+    }
+}
@@ -0,0 +1,375 @@
+//! The standard procedures: numbers, pairs and lists, and equivalence.
+
+use error::Unwind;
+use read::parse::expr::{Env, Expr, ExprKind, ExprRef, NumberKind};
+use read::parse::heap;
+use std::collections::HashSet;
+use std::mem;
+use std::sync::Arc;
+use syntax::binding_key;
+use {define_primitive, Evaluator};
+
+pub fn define_primitives(env: &Env) {
+    define_primitive(env, "+", 0, None, |ev, args| {
+        fold(ev, "+", args, Num::Int(0), add)
+    });
+    define_primitive(env, "*", 0, None, |ev, args| {
+        fold(ev, "*", args, Num::Int(1), mul)
+    });
+    define_primitive(env, "-", 1, None, |ev, mut args| {
+        if args.len() == 1 {
+            args.insert(0, Expr::integer(0));
+        }
+        let first = number(ev, "-", &args[0])?;
+        fold(ev, "-", args.split_off(1), first, sub)
+    });
+    define_primitive(env, "/", 1, None, |ev, mut args| {
+        if args.len() == 1 {
+            args.insert(0, Expr::integer(1));
+        }
+        let mut result = number(ev, "/", &args[0])?;
+        for arg in &args[1..] {
+            let divisor = number(ev, "/", arg)?;
+            result = match div(result, divisor) {
+                Some(quotient) => quotient,
+                None => return Err(ev.error("/: division by zero", args.clone())),
+            };
+        }
+        Ok(result.into_expr())
+    });
+
+    define_primitive(env, "=", 1, None, |ev, args| compare(ev, "=", args, |a, b| a == b));
+    define_primitive(env, "<", 1, None, |ev, args| compare(ev, "<", args, |a, b| a < b));
+    define_primitive(env, ">", 1, None, |ev, args| compare(ev, ">", args, |a, b| a > b));
+    define_primitive(env, "<=", 1, None, |ev, args| compare(ev, "<=", args, |a, b| a <= b));
+    define_primitive(env, ">=", 1, None, |ev, args| compare(ev, ">=", args, |a, b| a >= b));
+
+    define_primitive(env, "cons", 2, Some(2), |_, args| {
+        Ok(Expr::cons(args[0].clone(), args[1].clone()))
+    });
+    define_primitive(env, "car", 1, Some(1), |ev, args| {
+        match args[0].car() {
+            Some(car) => Ok(car),
+            None => Err(ev.error("car: expected a pair", args)),
+        }
+    });
+    define_primitive(env, "cdr", 1, Some(1), |ev, args| {
+        match args[0].cdr() {
+            Some(cdr) => Ok(cdr),
+            None => Err(ev.error("cdr: expected a pair", args)),
+        }
+    });
+    define_primitive(env, "set-car!", 2, Some(2), |ev, args| {
+        match args[0].kind {
+            ExprKind::Pair(ref pair) => {
+                modifiable(ev, "set-car!", &args[0])?;
+                heap::write_barrier(&mem::replace(&mut *pair.car.borrow_mut(), args[1].clone()));
+                Ok(Expr::unspecified())
+            }
+            _ => Err(ev.error("set-car!: expected a pair", args.clone())),
+        }
+    });
+    define_primitive(env, "set-cdr!", 2, Some(2), |ev, args| {
+        match args[0].kind {
+            ExprKind::Pair(ref pair) => {
+                modifiable(ev, "set-cdr!", &args[0])?;
+                heap::write_barrier(&mem::replace(&mut *pair.cdr.borrow_mut(), args[1].clone()));
+                Ok(Expr::unspecified())
+            }
+            _ => Err(ev.error("set-cdr!: expected a pair", args.clone())),
+        }
+    });
+    define_primitive(env, "list", 0, None, |_, args| Ok(Expr::list(args)));
+    define_primitive(env, "length", 1, Some(1), |ev, args| {
+        match args[0].to_vec() {
+            Some(items) => Ok(Expr::integer(items.len() as i64)),
+            None => Err(ev.error("length: expected a list", args)),
+        }
+    });
+
+    define_primitive(env, "vector", 0, None, |_, args| Ok(Expr::vector(args)));
+    define_primitive(env, "make-vector", 1, Some(2), |ev, args| {
+        let length = index(ev, "make-vector", &args[0], None)?;
+        ev.reserve(length.saturating_mul(mem::size_of::<ExprRef>()))?;
+        let fill = args.get(1).cloned().unwrap_or_else(Expr::unspecified);
+        Ok(Expr::vector(vec![fill; length]))
+    });
+    define_primitive(env, "vector-length", 1, Some(1), |ev, args| {
+        match args[0].kind {
+            ExprKind::Vector(ref items) => Ok(Expr::integer(items.borrow().len() as i64)),
+            _ => Err(ev.error("vector-length: expected a vector", args.clone())),
+        }
+    });
+    define_primitive(env, "vector-ref", 2, Some(2), |ev, args| {
+        match args[0].kind {
+            ExprKind::Vector(ref items) => {
+                let length = items.borrow().len();
+                let i = index(ev, "vector-ref", &args[1], Some(length))?;
+                Ok(items.borrow()[i].clone())
+            }
+            _ => Err(ev.error("vector-ref: expected a vector", args.clone())),
+        }
+    });
+    define_primitive(env, "vector-set!", 3, Some(3), |ev, args| {
+        match args[0].kind {
+            ExprKind::Vector(ref items) => {
+                let length = items.borrow().len();
+                let i = index(ev, "vector-set!", &args[1], Some(length))?;
+                modifiable(ev, "vector-set!", &args[0])?;
+                let old = mem::replace(&mut items.borrow_mut()[i], args[2].clone());
+                heap::write_barrier(&old);
+                Ok(Expr::unspecified())
+            }
+            _ => Err(ev.error("vector-set!: expected a vector", args.clone())),
+        }
+    });
+    define_primitive(env, "vector->list", 1, Some(1), |ev, args| {
+        match args[0].kind {
+            ExprKind::Vector(ref items) => Ok(Expr::list(items.borrow().clone())),
+            _ => Err(ev.error("vector->list: expected a vector", args.clone())),
+        }
+    });
+    define_primitive(env, "list->vector", 1, Some(1), |ev, args| {
+        match args[0].to_vec() {
+            Some(items) => Ok(Expr::vector(items)),
+            None => Err(ev.error("list->vector: expected a list", args)),
+        }
+    });
+
+    define_primitive(env, "not", 1, Some(1), |_, args| Ok(Expr::boolean(!args[0].is_true())));
+    define_primitive(env, "eq?", 2, Some(2), |_, args| Ok(Expr::boolean(eqv(&args[0], &args[1]))));
+    define_primitive(env, "eqv?", 2, Some(2), |_, args| Ok(Expr::boolean(eqv(&args[0], &args[1]))));
+    define_primitive(env, "equal?", 2, Some(2), |_, args| {
+        Ok(Expr::boolean(equal(&args[0], &args[1])))
+    });
+
+    define_primitive(env, "null?", 1, Some(1), |_, args| Ok(Expr::boolean(args[0].is_nil())));
+    define_primitive(env, "pair?", 1, Some(1), |_, args| {
+        Ok(Expr::boolean(args[0].car().is_some()))
+    });
+    // Identifiers inserted by macros are symbols too, as far as the
+    // transformers that handle them are concerned.
+    define_primitive(env, "symbol?", 1, Some(1), |_, args| {
+        Ok(Expr::boolean(binding_key(&args[0]).is_some()))
+    });
+    define_primitive(env, "procedure?", 1, Some(1), |_, args| {
+        Ok(Expr::boolean(args[0].is_procedure()))
+    });
+    define_primitive(env, "boolean?", 1, Some(1), |_, args| {
+        let is_bool = match args[0].kind {
+            ExprKind::Bool(..) => true,
+            _ => false,
+        };
+        Ok(Expr::boolean(is_bool))
+    });
+    define_primitive(env, "number?", 1, Some(1), |_, args| {
+        let is_number = match args[0].kind {
+            ExprKind::Num(..) => true,
+            _ => false,
+        };
+        Ok(Expr::boolean(is_number))
+    });
+    define_primitive(env, "string?", 1, Some(1), |_, args| {
+        let is_string = match args[0].kind {
+            ExprKind::Str(..) => true,
+            _ => false,
+        };
+        Ok(Expr::boolean(is_string))
+    });
+    define_primitive(env, "vector?", 1, Some(1), |_, args| {
+        let is_vector = match args[0].kind {
+            ExprKind::Vector(..) => true,
+            _ => false,
+        };
+        Ok(Expr::boolean(is_vector))
+    });
+}
+
+/// An exact, non-negative integer, less than `length` if there is one.
+pub(crate) fn index(ev: &mut Evaluator, name: &str, arg: &ExprRef, length: Option<usize>) -> Result<usize, Unwind> {
+    let i = match arg.kind {
+        ExprKind::Num(ref n) => match n.kind {
+            NumberKind::Int(i) if i >= 0 => i as usize,
+            _ => return Err(ev.error(format!("{}: expected an index", name), vec![arg.clone()])),
+        },
+        _ => return Err(ev.error(format!("{}: expected an index", name), vec![arg.clone()])),
+    };
+    if length.map_or(false, |length| i >= length) {
+        return Err(ev.error(format!("{}: index out of range", name), vec![arg.clone()]));
+    }
+    Ok(i)
+}
+
+/// Make sure `name` may modify a value: literal constants can't be, unless
+/// the evaluator has been told not to check.
+pub(crate) fn modifiable(ev: &mut Evaluator, name: &str, arg: &ExprRef) -> Result<(), Unwind> {
+    if arg.mutable || !ev.literal_checks {
+        return Ok(());
+    }
+    Err(ev.error(format!("{}: can't modify a literal constant", name), vec![arg.clone()]))
+}
+
+/// A number pulled out of an expression, for doing arithmetic on.
+#[derive(Clone, Copy, PartialEq, PartialOrd)]
+enum Num {
+    Int(i64),
+    Real(f64),
+}
+
+impl Num {
+    fn to_real(self) -> f64 {
+        match self {
+            Num::Int(i) => i as f64,
+            Num::Real(f) => f,
+        }
+    }
+
+    fn into_expr(self) -> ExprRef {
+        match self {
+            Num::Int(i) => Expr::integer(i),
+            Num::Real(f) => Expr::real(f),
+        }
+    }
+}
+
+fn number(ev: &mut Evaluator, name: &str, arg: &ExprRef) -> Result<Num, Unwind> {
+    match arg.kind {
+        ExprKind::Num(ref n) => Ok(match n.kind {
+            NumberKind::Int(i) => Num::Int(i),
+            NumberKind::Real(f) => Num::Real(f),
+            NumberKind::Rational { numerator, denominator } => {
+                Num::Real(numerator as f64 / denominator as f64)
+            }
+        }),
+        _ => Err(ev.error(format!("{}: expected a number", name), vec![arg.clone()])),
+    }
+}
+
+// Integer arithmetic that overflows falls back to reals.
+fn arith(a: Num, b: Num, int: fn(i64, i64) -> Option<i64>, real: fn(f64, f64) -> f64) -> Num {
+    match (a, b) {
+        (Num::Int(x), Num::Int(y)) => match int(x, y) {
+            Some(z) => Num::Int(z),
+            None => Num::Real(real(x as f64, y as f64)),
+        },
+        _ => Num::Real(real(a.to_real(), b.to_real())),
+    }
+}
+
+fn add(a: Num, b: Num) -> Num {
+    arith(a, b, i64::checked_add, |x, y| x + y)
+}
+
+fn sub(a: Num, b: Num) -> Num {
+    arith(a, b, i64::checked_sub, |x, y| x - y)
+}
+
+fn mul(a: Num, b: Num) -> Num {
+    arith(a, b, i64::checked_mul, |x, y| x * y)
+}
+
+fn div(a: Num, b: Num) -> Option<Num> {
+    match (a, b) {
+        (_, Num::Int(0)) => None,
+        (Num::Int(x), Num::Int(y)) if x % y == 0 => Some(Num::Int(x / y)),
+        _ => Some(Num::Real(a.to_real() / b.to_real())),
+    }
+}
+
+fn fold(
+    ev: &mut Evaluator,
+    name: &str,
+    args: Vec<ExprRef>,
+    init: Num,
+    op: fn(Num, Num) -> Num,
+) -> Result<ExprRef, Unwind> {
+    let mut result = init;
+    for arg in &args {
+        result = op(result, number(ev, name, arg)?);
+    }
+    Ok(result.into_expr())
+}
+
+fn compare(
+    ev: &mut Evaluator,
+    name: &str,
+    args: Vec<ExprRef>,
+    holds: fn(f64, f64) -> bool,
+) -> Result<ExprRef, Unwind> {
+    let mut numbers = Vec::with_capacity(args.len());
+    for arg in &args {
+        numbers.push(number(ev, name, arg)?.to_real());
+    }
+    let result = numbers.windows(2).all(|pair| holds(pair[0], pair[1]));
+    Ok(Expr::boolean(result))
+}
+
+/// Whether two expressions are the same object, or equal atoms.
+pub fn eqv(a: &ExprRef, b: &ExprRef) -> bool {
+    if Arc::ptr_eq(a, b) {
+        return true;
+    }
+
+    match (&a.kind, &b.kind) {
+        (&ExprKind::Nil, &ExprKind::Nil) => true,
+        (&ExprKind::Unspecified, &ExprKind::Unspecified) => true,
+        (&ExprKind::Bool(x), &ExprKind::Bool(y)) => x == y,
+        (&ExprKind::Char(x), &ExprKind::Char(y)) => x == y,
+        (&ExprKind::Num(ref x), &ExprKind::Num(ref y)) => x == y,
+        (&ExprKind::Symbol(ref x), &ExprKind::Symbol(ref y)) => x == y,
+        // The same Rust value can be handed to Ruse more than once.
+        (&ExprKind::Host(ref x), &ExprKind::Host(ref y)) => Arc::ptr_eq(&x.value, &y.value),
+        _ => false,
+    }
+}
+
+/// Whether two expressions have the same structure and contents.
+///
+/// Rather than calling itself on what lists and vectors hold, this keeps
+/// the values still to compare on a stack, so that deeply nested values
+/// don't use up the Rust stack. Lists and vectors which are already being
+/// compared are taken to be equal when they come up again, which is what
+/// lets circular values be compared at all.
+pub fn equal(a: &ExprRef, b: &ExprRef) -> bool {
+    let mut pending = vec![(a.clone(), b.clone())];
+    let mut compared = HashSet::new();
+    while let Some((a, b)) = pending.pop() {
+        if eqv(&a, &b) {
+            continue;
+        }
+
+        match (&a.kind, &b.kind) {
+            (&ExprKind::Pair(ref x), &ExprKind::Pair(ref y)) => {
+                if compared.insert((address(&a), address(&b))) {
+                    pending.push((x.cdr.borrow().clone(), y.cdr.borrow().clone()));
+                    pending.push((x.car.borrow().clone(), y.car.borrow().clone()));
+                }
+            }
+            (&ExprKind::Str(ref x), &ExprKind::Str(ref y)) => {
+                if *x.borrow() != *y.borrow() {
+                    return false;
+                }
+            }
+            (&ExprKind::Vector(ref x), &ExprKind::Vector(ref y)) => {
+                let (x, y) = (x.borrow(), y.borrow());
+                if x.len() != y.len() {
+                    return false;
+                }
+                if compared.insert((address(&a), address(&b))) {
+                    pending.extend(x.iter().cloned().zip(y.iter().cloned()).rev());
+                }
+            }
+            (&ExprKind::ByteVector(ref x), &ExprKind::ByteVector(ref y)) => {
+                if *x.borrow() != *y.borrow() {
+                    return false;
+                }
+            }
+            _ => return false,
+        }
+    }
+    true
+}
+
+fn address(expr: &ExprRef) -> usize {
+    &**expr as *const Expr as usize
+}
@@ -0,0 +1,172 @@
+//! Record types, as defined by `define-record-type`.
+//!
+//! Each definition makes a new record type, distinct from every other, and
+//! binds procedures to construct its instances, recognize them, and get and
+//! set their fields.
+
+use atomic_refcell::AtomicRefCell;
+use error::Unwind;
+use read::parse::expr::{Env, Expr, ExprKind, ExprRef, Record, RecordType, Symbol};
+use read::parse::heap;
+use std::mem;
+use std::sync::Arc;
+use syntax::{base_name, binding_key};
+use {make_primitive, Evaluator, Step};
+
+/// One field clause: `(field accessor [modifier])`.
+struct FieldSpec {
+    name: Symbol,
+    accessor: Option<ExprRef>,
+    modifier: Option<ExprRef>,
+}
+
+pub fn define_record_type(
+    ev: &mut Evaluator,
+    form: &ExprRef,
+    args: &[ExprRef],
+    env: &Arc<Env>,
+) -> Result<Step, Unwind> {
+    let names_ok = args.len() >= 3
+        && binding_key(&args[0]).is_some()
+        && binding_key(&args[2]).is_some()
+        && (binding_key(&args[1]).is_some() || args[1].car().is_some() || !args[1].is_true());
+    if !names_ok {
+        return Err(ev.syntax_error(form));
+    }
+
+    let mut specs = Vec::with_capacity(args.len() - 3);
+    for spec in args.iter().skip(3) {
+        match field_spec(spec) {
+            Some(spec) => specs.push(spec),
+            None => return Err(ev.syntax_error(form)),
+        }
+    }
+
+    let record_type = Arc::new(RecordType {
+        name: base_name(&args[0]).unwrap_or_else(|| Symbol::intern("")),
+        fields: specs.iter().map(|spec| spec.name).collect(),
+    });
+    define(env, &args[0], Expr::new(ExprKind::RecordType(record_type.clone())).alloc());
+
+    // The constructor is either `#f`, a bare name taking every field in
+    // order, or `(name field ...)`.
+    let fields = record_type.fields.len();
+    let constructor = match args[1].kind {
+        ExprKind::Bool(false) => None,
+        ExprKind::Pair(..) => {
+            let parts = args[1].to_vec().unwrap_or_default();
+            if binding_key(&parts[0]).is_none() {
+                return Err(ev.syntax_error(form));
+            }
+            let mut indices = Vec::with_capacity(parts.len());
+            for field in parts.iter().skip(1) {
+                match base_name(field).and_then(|name| record_type.field_index(name)) {
+                    Some(index) => indices.push(index),
+                    None => return Err(ev.syntax_error(form)),
+                }
+            }
+            Some((parts[0].clone(), indices))
+        }
+        _ => Some((args[1].clone(), (0..fields).collect())),
+    };
+
+    if let Some((name, indices)) = constructor {
+        let rt = record_type.clone();
+        let count = indices.len();
+        let procedure = make_primitive(&procedure_name(&name), count, Some(count), move |_, args| {
+            let mut values = vec![Expr::unspecified(); fields];
+            for (&index, value) in indices.iter().zip(args) {
+                values[index] = value;
+            }
+            let record = Record {
+                record_type: rt.clone(),
+                values: AtomicRefCell::new(values),
+            };
+            Ok(Expr::new(ExprKind::Record(record)).alloc())
+        });
+        define(env, &name, procedure);
+    }
+
+    let rt = record_type.clone();
+    let predicate = make_primitive(&procedure_name(&args[2]), 1, Some(1), move |_, args| {
+        Ok(Expr::boolean(instance(&args[0], &rt).is_some()))
+    });
+    define(env, &args[2], predicate);
+
+    for (index, spec) in specs.into_iter().enumerate() {
+        if let Some(accessor) = spec.accessor {
+            let rt = record_type.clone();
+            let name = procedure_name(&accessor);
+            let message = expected(&name, &rt);
+            let procedure = make_primitive(&name, 1, Some(1), move |ev, args| {
+                match instance(&args[0], &rt) {
+                    Some(record) => Ok(record.values.borrow()[index].clone()),
+                    None => Err(ev.error(message.clone(), args)),
+                }
+            });
+            define(env, &accessor, procedure);
+        }
+
+        if let Some(modifier) = spec.modifier {
+            let rt = record_type.clone();
+            let name = procedure_name(&modifier);
+            let message = expected(&name, &rt);
+            let procedure = make_primitive(&name, 2, Some(2), move |ev, args| {
+                match instance(&args[0], &rt) {
+                    Some(record) => {
+                        let old = mem::replace(&mut record.values.borrow_mut()[index], args[1].clone());
+                        heap::write_barrier(&old);
+                        Ok(Expr::unspecified())
+                    }
+                    None => Err(ev.error(message.clone(), args)),
+                }
+            });
+            define(env, &modifier, procedure);
+        }
+    }
+
+    Ok(Step::Done(Expr::unspecified()))
+}
+
+fn field_spec(spec: &ExprRef) -> Option<FieldSpec> {
+    if let Some(name) = base_name(spec) {
+        return Some(FieldSpec {
+            name,
+            accessor: None,
+            modifier: None,
+        });
+    }
+
+    let parts = spec.to_vec()?;
+    if parts.is_empty() || parts.len() > 3 || parts.iter().any(|p| binding_key(p).is_none()) {
+        return None;
+    }
+
+    Some(FieldSpec {
+        name: base_name(&parts[0])?,
+        accessor: parts.get(1).cloned(),
+        modifier: parts.get(2).cloned(),
+    })
+}
+
+/// The record, if `expr` is an instance of `record_type`.
+fn instance<'a>(expr: &'a Expr, record_type: &Arc<RecordType>) -> Option<&'a Record> {
+    match expr.kind {
+        ExprKind::Record(ref record) if Arc::ptr_eq(&record.record_type, record_type) => Some(record),
+        _ => None,
+    }
+}
+
+fn define(env: &Env, name: &ExprRef, value: ExprRef) {
+    if let Some(key) = binding_key(name) {
+        env.define(key, value);
+    }
+}
+
+fn procedure_name(name: &ExprRef) -> String {
+    base_name(name).map(|name| name.to_string()).unwrap_or_default()
+}
+
+fn expected(name: &str, record_type: &RecordType) -> String {
+    format!("{}: expected a {}", name, record_type.short_name())
+}
@@ -0,0 +1,448 @@
+//! `syntax-rules`: macros written as patterns and templates.
+//!
+//! A use of the macro is matched against each pattern in turn. The first
+//! that fits binds its pattern variables to pieces of the use, and the
+//! matching template is filled in with them. Every other symbol in the
+//! template is renamed into an identifier, once per expansion.
+
+use error::Unwind;
+use primitives::equal;
+use read::parse::expr::{Env, Expr, ExprKind, ExprRef, Symbol, Syntax};
+use read::parse::symbol::keywords;
+use std::collections::HashMap;
+use std::sync::Arc;
+use syntax::{self, base_name, binding_key, refill_pair, refill_vector, same_binding};
+use Evaluator;
+
+/// A parsed `(syntax-rules [ellipsis] (literal ...) (pattern template) ...)`.
+struct Rules {
+    ellipsis: Option<Symbol>,
+    literals: Vec<Symbol>,
+    rules: Vec<(ExprRef, ExprRef)>,
+}
+
+/// What a pattern variable matched. Variables followed by `n` ellipses in
+/// their pattern match `n` levels of sequences.
+#[derive(Clone)]
+enum Match {
+    One(ExprRef),
+    Many(Vec<Match>),
+}
+
+type Bindings = HashMap<Symbol, Match>;
+
+/// Whether `spec` is a well-formed `syntax-rules` transformer.
+pub fn is_valid(spec: &ExprRef) -> bool {
+    Rules::parse(spec).is_some()
+}
+
+/// Expand `form`, a use of the `syntax-rules` macro `syntax` in `env`.
+pub fn expand(
+    ev: &mut Evaluator,
+    syntax: &Syntax,
+    form: &ExprRef,
+    env: &Arc<Env>,
+) -> Result<ExprRef, Unwind> {
+    let rules = match Rules::parse(&syntax.transformer) {
+        Some(rules) => rules,
+        None => return Err(ev.syntax_error(&syntax.transformer)),
+    };
+    let operands = form.cdr().unwrap_or_else(Expr::nil);
+
+    for &(ref pattern, ref template) in &rules.rules {
+        // The keyword position of the pattern is never matched.
+        let pattern = pattern.cdr().unwrap_or_else(Expr::nil);
+
+        let mut bindings = Bindings::new();
+        if !rules.matches(&pattern, &operands, &syntax.env, env, &mut bindings) {
+            continue;
+        }
+
+        ev.span = form.span;
+        let mut expansion = Expansion {
+            ev,
+            form,
+            rules: &rules,
+            env: &syntax.env,
+            renames: HashMap::new(),
+        };
+        return expansion.instantiate(template, &bindings, false);
+    }
+
+    Err(ev.syntax_error(form))
+}
+
+impl Rules {
+    fn parse(spec: &ExprRef) -> Option<Rules> {
+        let items = spec.to_vec()?;
+        let mut rest = items.get(1..)?;
+
+        // A custom ellipsis comes before the literals.
+        let mut ellipsis = None;
+        if let Some(custom) = rest.first().and_then(|first| binding_key(first)) {
+            ellipsis = Some(custom);
+            rest = &rest[1..];
+        }
+
+        let mut literals = Vec::new();
+        for literal in rest.first()?.to_vec()? {
+            literals.push(binding_key(&literal)?);
+        }
+
+        let mut rules = Vec::new();
+        for rule in &rest[1..] {
+            match rule.to_vec() {
+                Some(ref parts) if parts.len() == 2 && parts[0].car().is_some() => {
+                    rules.push((parts[0].clone(), parts[1].clone()));
+                }
+                _ => return None,
+            }
+        }
+
+        Some(Rules {
+            ellipsis,
+            literals,
+            rules,
+        })
+    }
+
+    // The standard ellipsis is recognized even when it was inserted by
+    // another macro's expansion, so macros can define macros.
+    fn is_ellipsis(&self, expr: &Expr) -> bool {
+        match self.ellipsis {
+            Some(custom) => binding_key(expr) == Some(custom),
+            None => base_name(expr).map_or(false, |name| name == keywords::ELLIPSIS),
+        }
+    }
+
+    fn is_literal(&self, key: Symbol) -> bool {
+        self.literals.contains(&key)
+    }
+
+    /// Match `input`, from the macro use in `use_env`, against `pattern`,
+    /// from the macro's definition in `env`.
+    fn matches(
+        &self,
+        pattern: &ExprRef,
+        input: &ExprRef,
+        env: &Env,
+        use_env: &Env,
+        bindings: &mut Bindings,
+    ) -> bool {
+        if let Some(key) = binding_key(pattern) {
+            if self.is_literal(key) {
+                return same_binding(pattern, env, input, use_env);
+            }
+            if !is_underscore(pattern) {
+                bindings.insert(key, Match::One(input.clone()));
+            }
+            return true;
+        }
+
+        match pattern.kind {
+            ExprKind::Pair(..) => {
+                if input.car().is_none() && !input.is_nil() {
+                    return false;
+                }
+                let (patterns, pattern_tail) = split(pattern);
+                let (items, tail) = split(input);
+                self.matches_sequence(&patterns, &pattern_tail, &items, &tail, env, use_env, bindings)
+            }
+            ExprKind::Vector(ref patterns) => match input.kind {
+                ExprKind::Vector(ref items) => {
+                    let nil = Expr::nil();
+                    let (patterns, items) = (patterns.borrow(), items.borrow());
+                    self.matches_sequence(&patterns, &nil, &items, &nil, env, use_env, bindings)
+                }
+                _ => false,
+            },
+            _ => equal(pattern, input),
+        }
+    }
+
+    /// Match the items and tail of a list (or the items of a vector)
+    /// against a sequence of patterns, at most one of which is followed by
+    /// an ellipsis.
+    fn matches_sequence(
+        &self,
+        patterns: &[ExprRef],
+        pattern_tail: &ExprRef,
+        items: &[ExprRef],
+        tail: &ExprRef,
+        env: &Env,
+        use_env: &Env,
+        bindings: &mut Bindings,
+    ) -> bool {
+        let ellipsis = patterns.iter().position(|p| self.is_ellipsis(p));
+
+        let (before, repeated, after) = match ellipsis {
+            Some(0) => return false,
+            Some(i) => (&patterns[..i - 1], Some(&patterns[i - 1]), &patterns[i + 1..]),
+            None => (patterns, None, &[][..]),
+        };
+
+        if items.len() < before.len() + after.len() {
+            return false;
+        }
+        let repeats = items.len() - before.len() - after.len();
+
+        for (pattern, item) in before.iter().zip(items) {
+            if !self.matches(pattern, item, env, use_env, bindings) {
+                return false;
+            }
+        }
+
+        // Without an ellipsis, extra items can only be matched by a dotted
+        // tail pattern.
+        let repeated = match repeated {
+            Some(repeated) => repeated,
+            None => {
+                if pattern_tail.is_nil() {
+                    return repeats == 0 && tail.is_nil();
+                }
+                let rest = items[before.len()..]
+                    .iter()
+                    .rev()
+                    .fold(tail.clone(), |tail, item| Expr::cons(item.clone(), tail));
+                return self.matches(pattern_tail, &rest, env, use_env, bindings);
+            }
+        };
+
+        let mut matched = Vec::with_capacity(repeats);
+        for item in &items[before.len()..before.len() + repeats] {
+            let mut inner = Bindings::new();
+            if !self.matches(repeated, item, env, use_env, &mut inner) {
+                return false;
+            }
+            matched.push(inner);
+        }
+        for var in self.pattern_vars(repeated) {
+            let matches = matched.iter().filter_map(|inner| inner.get(&var).cloned()).collect();
+            bindings.insert(var, Match::Many(matches));
+        }
+
+        for (pattern, item) in after.iter().zip(&items[before.len() + repeats..]) {
+            if !self.matches(pattern, item, env, use_env, bindings) {
+                return false;
+            }
+        }
+
+        if pattern_tail.is_nil() {
+            tail.is_nil()
+        } else {
+            self.matches(pattern_tail, tail, env, use_env, bindings)
+        }
+    }
+
+    /// The variables a pattern binds.
+    fn pattern_vars(&self, pattern: &ExprRef) -> Vec<Symbol> {
+        let mut vars = Vec::new();
+        self.collect_vars(pattern, &mut vars);
+        vars
+    }
+
+    fn collect_vars(&self, pattern: &ExprRef, vars: &mut Vec<Symbol>) {
+        if let Some(key) = binding_key(pattern) {
+            if !self.is_literal(key) && !self.is_ellipsis(pattern) && !is_underscore(pattern) {
+                vars.push(key);
+            }
+            return;
+        }
+
+        match pattern.kind {
+            ExprKind::Pair(ref pair) => {
+                self.collect_vars(&pair.car.borrow(), vars);
+                self.collect_vars(&pair.cdr.borrow(), vars);
+            }
+            ExprKind::Vector(ref items) => {
+                for item in items.borrow().iter() {
+                    self.collect_vars(item, vars);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// The state of filling in one template.
+struct Expansion<'a> {
+    ev: &'a mut Evaluator,
+    /// The use of the macro being expanded.
+    form: &'a ExprRef,
+    rules: &'a Rules,
+    env: &'a Arc<Env>,
+    renames: HashMap<Symbol, ExprRef>,
+}
+
+impl<'a> Expansion<'a> {
+    /// Fill in `template`. Inside `(... template)`, ellipses are escaped and
+    /// copied through like any other symbol.
+    fn instantiate(
+        &mut self,
+        template: &ExprRef,
+        bindings: &Bindings,
+        escaped: bool,
+    ) -> Result<ExprRef, Unwind> {
+        if let Some(key) = binding_key(template) {
+            return match bindings.get(&key) {
+                Some(&Match::One(ref input)) => Ok(input.clone()),
+                Some(&Match::Many(..)) => Err(self.error("pattern variable used without an ellipsis")),
+                None => self.rename(template, key),
+            };
+        }
+
+        match template.kind {
+            ExprKind::Pair(..) => {
+                let (items, tail) = split(template);
+
+                if !escaped && items.len() == 2 && tail.is_nil() && self.rules.is_ellipsis(&items[0]) {
+                    return self.instantiate(&items[1], bindings, true);
+                }
+
+                let items = self.instantiate_sequence(&items, bindings, escaped)?;
+                let tail = self.instantiate(&tail, bindings, escaped)?;
+                let span = self.ev.span;
+                Ok(items
+                    .into_iter()
+                    .rev()
+                    .fold(tail, |tail, item| refill_pair(item, tail, template, span)))
+            }
+            ExprKind::Vector(ref items) => {
+                let items = self.instantiate_sequence(&items.borrow(), bindings, escaped)?;
+                Ok(refill_vector(items, template))
+            }
+            _ => Ok(template.clone()),
+        }
+    }
+
+    fn instantiate_sequence(
+        &mut self,
+        templates: &[ExprRef],
+        bindings: &Bindings,
+        escaped: bool,
+    ) -> Result<Vec<ExprRef>, Unwind> {
+        let mut items = Vec::with_capacity(templates.len());
+        let mut i = 0;
+
+        while i < templates.len() {
+            let mut depth = 0;
+            while !escaped
+                && i + depth + 1 < templates.len()
+                && self.rules.is_ellipsis(&templates[i + depth + 1])
+            {
+                depth += 1;
+            }
+
+            if depth == 0 {
+                items.push(self.instantiate(&templates[i], bindings, escaped)?);
+            } else {
+                self.instantiate_repeated(&templates[i], bindings, depth, &mut items)?;
+            }
+            i += depth + 1;
+        }
+
+        Ok(items)
+    }
+
+    /// Fill in a template followed by `depth` ellipses, once for each match
+    /// of the sequence variables inside it.
+    fn instantiate_repeated(
+        &mut self,
+        template: &ExprRef,
+        bindings: &Bindings,
+        depth: usize,
+        items: &mut Vec<ExprRef>,
+    ) -> Result<(), Unwind> {
+        let mut sequences = Vec::new();
+        template_vars(template, &mut |key| {
+            if let Some(&Match::Many(ref matches)) = bindings.get(&key) {
+                sequences.push((key, matches.clone()));
+            }
+        });
+
+        let count = match sequences.first() {
+            Some(&(_, ref matches)) => matches.len(),
+            None => return Err(self.error("no pattern variables before ellipsis in template")),
+        };
+        if sequences.iter().any(|&(_, ref matches)| matches.len() != count) {
+            return Err(self.error("pattern variables under one ellipsis matched different lengths"));
+        }
+
+        for i in 0..count {
+            let mut inner = bindings.clone();
+            for &(key, ref matches) in &sequences {
+                inner.insert(key, matches[i].clone());
+            }
+
+            if depth == 1 {
+                items.push(self.instantiate(template, &inner, false)?);
+            } else {
+                self.instantiate_repeated(template, &inner, depth - 1, items)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Every occurrence of a symbol in one expansion becomes the same
+    /// identifier.
+    fn rename(&mut self, template: &ExprRef, key: Symbol) -> Result<ExprRef, Unwind> {
+        if let Some(renamed) = self.renames.get(&key) {
+            return Ok(renamed.clone());
+        }
+
+        let renamed = self.ev.rename(template, self.env)?;
+        self.renames.insert(key, renamed.clone());
+        Ok(renamed)
+    }
+
+    /// Raise an error about the template, naming the macro use.
+    fn error(&mut self, message: &str) -> Unwind {
+        self.ev.error(message, vec![syntax::strip(self.form)])
+    }
+}
+
+// `_` matches anything without binding it, wherever it was inserted from.
+fn is_underscore(expr: &Expr) -> bool {
+    base_name(expr).map_or(false, |name| name == keywords::UNDERSCORE)
+}
+
+// Call `f` with every symbol and identifier in a template, including the
+// same one repeatedly.
+fn template_vars<F: FnMut(Symbol)>(template: &ExprRef, f: &mut F) {
+    if let Some(key) = binding_key(template) {
+        return f(key);
+    }
+
+    match template.kind {
+        ExprKind::Pair(ref pair) => {
+            template_vars(&pair.car.borrow(), f);
+            template_vars(&pair.cdr.borrow(), f);
+        }
+        ExprKind::Vector(ref items) => {
+            for item in items.borrow().iter() {
+                template_vars(item, f);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Split a possibly improper list into its items and its tail.
+fn split(list: &ExprRef) -> (Vec<ExprRef>, ExprRef) {
+    let mut items = Vec::new();
+    let mut next = list.clone();
+
+    loop {
+        let rest = match next.kind {
+            ExprKind::Pair(ref pair) => {
+                items.push(pair.car.borrow().clone());
+                pair.cdr.borrow().clone()
+            }
+            _ => break,
+        };
+        next = rest;
+    }
+
+    (items, next)
+}
@@ -0,0 +1,371 @@
+//! The special forms built into the evaluator.
+//!
+//! Each special form gets the whole form (for error reporting), the
+//! unevaluated operands, and the environment it appears in.
+
+use atomic_refcell::AtomicRefCell;
+use error::Unwind;
+use exception;
+use lazy;
+use parameters;
+use read::parse::expr::{Arity, CaseLambda, Closure, Env, Expr, ExprKind, ExprRef, Symbol};
+use read::parse::symbol::keywords;
+use records;
+use std::sync::Arc;
+use syntax::{self, base_name, binding_key, is_formals, is_keyword};
+use values;
+use {Evaluator, Step};
+
+pub type SpecialForm = fn(&mut Evaluator, &ExprRef, &[ExprRef], &Arc<Env>) -> Result<Step, Unwind>;
+
+pub fn lookup(name: Symbol) -> Option<SpecialForm> {
+    match name {
+        keywords::QUOTE => Some(quote),
+        keywords::IF => Some(if_form),
+        keywords::DEFINE => Some(define),
+        keywords::SET => Some(set),
+        keywords::LAMBDA => Some(lambda),
+        keywords::CASE_LAMBDA => Some(case_lambda),
+        keywords::BEGIN => Some(begin),
+        keywords::LET => Some(let_form),
+        keywords::LET_STAR => Some(let_star),
+        keywords::LETREC | keywords::LETREC_STAR => Some(letrec),
+        keywords::COND => Some(cond),
+        keywords::AND => Some(and),
+        keywords::OR => Some(or),
+        keywords::WHEN => Some(when),
+        keywords::UNLESS => Some(unless),
+        keywords::GUARD => Some(exception::guard),
+        keywords::DEFINE_SYNTAX => Some(syntax::define_syntax),
+        keywords::LET_SYNTAX => Some(syntax::let_syntax),
+        keywords::LETREC_SYNTAX => Some(syntax::letrec_syntax),
+        keywords::DEFINE_RECORD_TYPE => Some(records::define_record_type),
+        keywords::DELAY => Some(lazy::delay),
+        keywords::DELAY_FORCE => Some(lazy::delay_force),
+        keywords::PARAMETERIZE => Some(parameters::parameterize),
+        keywords::LET_VALUES => Some(values::let_values),
+        keywords::LET_STAR_VALUES => Some(values::let_star_values),
+        keywords::DEFINE_VALUES => Some(values::define_values),
+        keywords::RECEIVE => Some(values::receive),
+        _ => None,
+    }
+}
+
+impl Evaluator {
+    /// Try each `cond`-style clause in turn, giving back the step for the
+    /// first one whose test passes.
+    pub(crate) fn eval_clauses(
+        &mut self,
+        clauses: &[ExprRef],
+        env: &Arc<Env>,
+    ) -> Result<Option<Step>, Unwind> {
+        for clause in clauses {
+            let parts = match clause.to_vec() {
+                Some(ref parts) if !parts.is_empty() => parts.clone(),
+                _ => return Err(self.syntax_error(clause)),
+            };
+
+            if is_keyword(&parts[0], env, keywords::ELSE) {
+                return self.eval_body(&parts[1..], env).map(Some);
+            }
+
+            let test = self.eval_in(parts[0].clone(), env.clone())?;
+            if !test.is_true() {
+                continue;
+            }
+
+            if parts.len() == 1 {
+                return Ok(Some(Step::Done(test)));
+            }
+
+            if is_keyword(&parts[1], env, keywords::ARROW) {
+                if parts.len() != 3 {
+                    return Err(self.syntax_error(clause));
+                }
+                let receiver = self.eval_in(parts[2].clone(), env.clone())?;
+                self.span = clause.span;
+                return self.apply_step(receiver, vec![test]).map(Some);
+            }
+
+            return self.eval_body(&parts[1..], env).map(Some);
+        }
+
+        Ok(None)
+    }
+
+    fn make_closure(
+        &mut self,
+        form: &ExprRef,
+        params: &ExprRef,
+        body: &[ExprRef],
+        env: &Arc<Env>,
+    ) -> Result<ExprRef, Unwind> {
+        if !is_formals(params) || body.is_empty() {
+            return Err(self.syntax_error(form));
+        }
+
+        let closure = Closure {
+            env: env.clone(),
+            syntactic: false,
+            body: Expr::list(body.to_vec()),
+            args: params.clone(),
+            arity: Arity::of(params),
+            name: AtomicRefCell::new(None),
+        };
+        Ok(Expr::new(ExprKind::Closure(closure)).alloc())
+    }
+
+    /// Split `((name init) ...)` into names and initializers.
+    pub(crate) fn bindings(
+        &mut self,
+        form: &ExprRef,
+        bindings: &ExprRef,
+    ) -> Result<Vec<(Symbol, ExprRef)>, Unwind> {
+        let mut pairs = Vec::new();
+
+        for binding in bindings.to_vec().unwrap_or_default() {
+            match binding.to_vec() {
+                Some(ref parts) if parts.len() == 2 => match binding_key(&parts[0]) {
+                    Some(name) => pairs.push((name, parts[1].clone())),
+                    None => return Err(self.syntax_error(form)),
+                },
+                _ => return Err(self.syntax_error(form)),
+            }
+        }
+
+        if !bindings.is_nil() && pairs.is_empty() {
+            return Err(self.syntax_error(form));
+        }
+
+        Ok(pairs)
+    }
+}
+
+fn quote(ev: &mut Evaluator, form: &ExprRef, args: &[ExprRef], _: &Arc<Env>) -> Result<Step, Unwind> {
+    if args.len() != 1 {
+        return Err(ev.syntax_error(form));
+    }
+    Ok(Step::Done(syntax::strip(&args[0])))
+}
+
+fn if_form(ev: &mut Evaluator, form: &ExprRef, args: &[ExprRef], env: &Arc<Env>) -> Result<Step, Unwind> {
+    if args.len() != 2 && args.len() != 3 {
+        return Err(ev.syntax_error(form));
+    }
+
+    let test = ev.eval_in(args[0].clone(), env.clone())?;
+    if test.is_true() {
+        Ok(Step::Tail(args[1].clone(), env.clone()))
+    } else if args.len() == 3 {
+        Ok(Step::Tail(args[2].clone(), env.clone()))
+    } else {
+        Ok(Step::Done(Expr::unspecified()))
+    }
+}
+
+fn define(ev: &mut Evaluator, form: &ExprRef, args: &[ExprRef], env: &Arc<Env>) -> Result<Step, Unwind> {
+    if args.is_empty() {
+        return Err(ev.syntax_error(form));
+    }
+
+    // `(define (name . params) body ...)` is shorthand for binding a lambda.
+    if let ExprKind::Pair(ref target) = args[0].kind {
+        let name = match binding_key(&target.car.borrow()) {
+            Some(name) => name,
+            None => return Err(ev.syntax_error(form)),
+        };
+        let params = target.cdr.borrow().clone();
+        let closure = ev.make_closure(form, &params, &args[1..], env)?;
+        name_procedure(&closure, &target.car.borrow());
+        env.define(name, closure);
+        return Ok(Step::Done(Expr::unspecified()));
+    }
+
+    let name = match binding_key(&args[0]) {
+        Some(name) if args.len() == 2 => name,
+        _ => return Err(ev.syntax_error(form)),
+    };
+    let value = ev.eval_in(args[1].clone(), env.clone())?;
+    name_procedure(&value, &args[0]);
+    env.define(name, value);
+    Ok(Step::Done(Expr::unspecified()))
+}
+
+/// Give an anonymous procedure the name it's being defined as.
+fn name_procedure(procedure: &Expr, name: &Expr) {
+    let slot = match procedure.kind {
+        ExprKind::Closure(ref closure) => &closure.name,
+        ExprKind::CaseLambda(ref case) => &case.name,
+        _ => return,
+    };
+    if slot.borrow().is_none() {
+        *slot.borrow_mut() = base_name(name);
+    }
+}
+
+fn set(ev: &mut Evaluator, form: &ExprRef, args: &[ExprRef], env: &Arc<Env>) -> Result<Step, Unwind> {
+    if args.len() != 2 || binding_key(&args[0]).is_none() {
+        return Err(ev.syntax_error(form));
+    }
+
+    let value = ev.eval_in(args[1].clone(), env.clone())?;
+    if !syntax::assign(&args[0], env, value) {
+        ev.span = form.span;
+        return Err(ev.error("unbound variable", vec![syntax::strip(&args[0])]));
+    }
+    Ok(Step::Done(Expr::unspecified()))
+}
+
+fn lambda(ev: &mut Evaluator, form: &ExprRef, args: &[ExprRef], env: &Arc<Env>) -> Result<Step, Unwind> {
+    if args.is_empty() {
+        return Err(ev.syntax_error(form));
+    }
+    ev.make_closure(form, &args[0], &args[1..], env).map(Step::Done)
+}
+
+/// `(case-lambda (formals body ...) ...)`
+fn case_lambda(ev: &mut Evaluator, form: &ExprRef, args: &[ExprRef], env: &Arc<Env>) -> Result<Step, Unwind> {
+    let mut clauses = Vec::with_capacity(args.len());
+    for clause in args {
+        let (formals, body) = match (clause.car(), clause.cdr().and_then(|body| body.to_vec())) {
+            (Some(formals), Some(body)) => (formals, body),
+            _ => return Err(ev.syntax_error(form)),
+        };
+        clauses.push(ev.make_closure(form, &formals, &body, env)?);
+    }
+
+    let case = CaseLambda {
+        clauses,
+        name: AtomicRefCell::new(None),
+    };
+    Ok(Step::Done(Expr::new(ExprKind::CaseLambda(case)).alloc()))
+}
+
+fn begin(ev: &mut Evaluator, _: &ExprRef, args: &[ExprRef], env: &Arc<Env>) -> Result<Step, Unwind> {
+    ev.eval_body(args, env)
+}
+
+fn let_form(ev: &mut Evaluator, form: &ExprRef, args: &[ExprRef], env: &Arc<Env>) -> Result<Step, Unwind> {
+    if args.len() < 2 {
+        return Err(ev.syntax_error(form));
+    }
+
+    // Named `let` binds a procedure over the body, visible inside it.
+    if let Some(name) = binding_key(&args[0]) {
+        let bindings = ev.bindings(form, &args[1])?;
+        let params = bindings.iter().map(|&(name, _)| Expr::symbol(name)).collect();
+
+        let loop_env = Env::child(&env);
+        let procedure = ev.make_closure(form, &Expr::list(params), &args[2..], &loop_env)?;
+        name_procedure(&procedure, &args[0]);
+        loop_env.define(name, procedure.clone());
+
+        let mut values = Vec::with_capacity(bindings.len());
+        for (_, init) in bindings {
+            values.push(ev.eval_in(init, env.clone())?);
+        }
+        ev.span = form.span;
+        return ev.apply_step(procedure, values);
+    }
+
+    let bindings = ev.bindings(form, &args[0])?;
+    let body_env = Env::child(&env);
+    for (name, init) in bindings {
+        let value = ev.eval_in(init, env.clone())?;
+        body_env.define(name, value);
+    }
+    ev.eval_body(&args[1..], &body_env)
+}
+
+fn let_star(ev: &mut Evaluator, form: &ExprRef, args: &[ExprRef], env: &Arc<Env>) -> Result<Step, Unwind> {
+    if args.len() < 2 {
+        return Err(ev.syntax_error(form));
+    }
+
+    let mut body_env = env.clone();
+    for (name, init) in ev.bindings(form, &args[0])? {
+        let value = ev.eval_in(init, body_env.clone())?;
+        let next = Env::child(&body_env);
+        next.define(name, value);
+        body_env = next;
+    }
+    ev.eval_body(&args[1..], &body_env)
+}
+
+fn letrec(ev: &mut Evaluator, form: &ExprRef, args: &[ExprRef], env: &Arc<Env>) -> Result<Step, Unwind> {
+    if args.len() < 2 {
+        return Err(ev.syntax_error(form));
+    }
+
+    let bindings = ev.bindings(form, &args[0])?;
+    let body_env = Env::child(&env);
+    for &(name, _) in &bindings {
+        body_env.define(name, Expr::unspecified());
+    }
+    for (name, init) in bindings {
+        let value = ev.eval_in(init, body_env.clone())?;
+        body_env.define(name, value);
+    }
+    ev.eval_body(&args[1..], &body_env)
+}
+
+fn cond(ev: &mut Evaluator, _: &ExprRef, args: &[ExprRef], env: &Arc<Env>) -> Result<Step, Unwind> {
+    let step = ev.eval_clauses(args, env)?;
+    Ok(step.unwrap_or_else(|| Step::Done(Expr::unspecified())))
+}
+
+fn and(ev: &mut Evaluator, _: &ExprRef, args: &[ExprRef], env: &Arc<Env>) -> Result<Step, Unwind> {
+    match args.split_last() {
+        None => Ok(Step::Done(Expr::boolean(true))),
+        Some((last, init)) => {
+            for expr in init {
+                let value = ev.eval_in(expr.clone(), env.clone())?;
+                if !value.is_true() {
+                    return Ok(Step::Done(value));
+                }
+            }
+            Ok(Step::Tail(last.clone(), env.clone()))
+        }
+    }
+}
+
+fn or(ev: &mut Evaluator, _: &ExprRef, args: &[ExprRef], env: &Arc<Env>) -> Result<Step, Unwind> {
+    match args.split_last() {
+        None => Ok(Step::Done(Expr::boolean(false))),
+        Some((last, init)) => {
+            for expr in init {
+                let value = ev.eval_in(expr.clone(), env.clone())?;
+                if value.is_true() {
+                    return Ok(Step::Done(value));
+                }
+            }
+            Ok(Step::Tail(last.clone(), env.clone()))
+        }
+    }
+}
+
+fn when(ev: &mut Evaluator, form: &ExprRef, args: &[ExprRef], env: &Arc<Env>) -> Result<Step, Unwind> {
+    if args.is_empty() {
+        return Err(ev.syntax_error(form));
+    }
+
+    let test = ev.eval_in(args[0].clone(), env.clone())?;
+    if test.is_true() {
+        ev.eval_body(&args[1..], env)
+    } else {
+        Ok(Step::Done(Expr::unspecified()))
+    }
+}
+
+fn unless(ev: &mut Evaluator, form: &ExprRef, args: &[ExprRef], env: &Arc<Env>) -> Result<Step, Unwind> {
+    if args.is_empty() {
+        return Err(ev.syntax_error(form));
+    }
+
+    let test = ev.eval_in(args[0].clone(), env.clone())?;
+    if test.is_true() {
+        Ok(Step::Done(Expr::unspecified()))
+    } else {
+        ev.eval_body(&args[1..], env)
+    }
+}
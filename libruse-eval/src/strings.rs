@@ -0,0 +1,142 @@
+//! Strings and bytevectors: making them, looking inside them, and changing
+//! them.
+//!
+//! Like pairs and vectors, strings and bytevectors which are literal
+//! constants in a program can't be changed.
+
+use error::Unwind;
+use primitives::{index, modifiable};
+use read::parse::expr::{Env, Expr, ExprKind, ExprRef, NumberKind};
+use std::iter;
+use {define_primitive, Evaluator};
+
+pub fn define_primitives(env: &Env) {
+    define_primitive(env, "make-string", 1, Some(2), |ev, args| {
+        let length = index(ev, "make-string", &args[0], None)?;
+        let fill = match args.get(1) {
+            Some(arg) => character(ev, "make-string", arg)?,
+            None => ' ',
+        };
+        ev.reserve(length.saturating_mul(fill.len_utf8()))?;
+        Ok(Expr::string(iter::repeat(fill).take(length).collect::<String>()))
+    });
+    define_primitive(env, "string-length", 1, Some(1), |ev, args| {
+        match args[0].kind {
+            ExprKind::Str(ref s) => Ok(Expr::integer(s.borrow().chars().count() as i64)),
+            _ => Err(ev.error("string-length: expected a string", args.clone())),
+        }
+    });
+    define_primitive(env, "string-ref", 2, Some(2), |ev, args| {
+        match args[0].kind {
+            ExprKind::Str(ref s) => {
+                let chars: Vec<char> = s.borrow().chars().collect();
+                let i = index(ev, "string-ref", &args[1], Some(chars.len()))?;
+                Ok(Expr::character(chars[i]))
+            }
+            _ => Err(ev.error("string-ref: expected a string", args.clone())),
+        }
+    });
+    define_primitive(env, "string-set!", 3, Some(3), |ev, args| {
+        match args[0].kind {
+            ExprKind::Str(ref s) => {
+                let mut chars: Vec<char> = s.borrow().chars().collect();
+                let i = index(ev, "string-set!", &args[1], Some(chars.len()))?;
+                let c = character(ev, "string-set!", &args[2])?;
+                modifiable(ev, "string-set!", &args[0])?;
+                chars[i] = c;
+                *s.borrow_mut() = chars.into_iter().collect();
+                Ok(Expr::unspecified())
+            }
+            _ => Err(ev.error("string-set!: expected a string", args.clone())),
+        }
+    });
+    define_primitive(env, "string-fill!", 2, Some(2), |ev, args| {
+        match args[0].kind {
+            ExprKind::Str(ref s) => {
+                let c = character(ev, "string-fill!", &args[1])?;
+                modifiable(ev, "string-fill!", &args[0])?;
+                let length = s.borrow().chars().count();
+                *s.borrow_mut() = iter::repeat(c).take(length).collect();
+                Ok(Expr::unspecified())
+            }
+            _ => Err(ev.error("string-fill!: expected a string", args.clone())),
+        }
+    });
+    define_primitive(env, "string-copy", 1, Some(1), |ev, args| {
+        match args[0].kind {
+            ExprKind::Str(ref s) => Ok(Expr::string(s.borrow().clone())),
+            _ => Err(ev.error("string-copy: expected a string", args.clone())),
+        }
+    });
+
+    define_primitive(env, "bytevector?", 1, Some(1), |_, args| {
+        let is_bytevector = match args[0].kind {
+            ExprKind::ByteVector(..) => true,
+            _ => false,
+        };
+        Ok(Expr::boolean(is_bytevector))
+    });
+    define_primitive(env, "bytevector", 0, None, |ev, args| {
+        let mut bytes = Vec::with_capacity(args.len());
+        for arg in &args {
+            bytes.push(byte(ev, "bytevector", arg)?);
+        }
+        Ok(Expr::bytevector(bytes))
+    });
+    define_primitive(env, "make-bytevector", 1, Some(2), |ev, args| {
+        let length = index(ev, "make-bytevector", &args[0], None)?;
+        let fill = match args.get(1) {
+            Some(arg) => byte(ev, "make-bytevector", arg)?,
+            None => 0,
+        };
+        ev.reserve(length)?;
+        Ok(Expr::bytevector(vec![fill; length]))
+    });
+    define_primitive(env, "bytevector-length", 1, Some(1), |ev, args| {
+        match args[0].kind {
+            ExprKind::ByteVector(ref bytes) => Ok(Expr::integer(bytes.borrow().len() as i64)),
+            _ => Err(ev.error("bytevector-length: expected a bytevector", args.clone())),
+        }
+    });
+    define_primitive(env, "bytevector-u8-ref", 2, Some(2), |ev, args| {
+        match args[0].kind {
+            ExprKind::ByteVector(ref bytes) => {
+                let length = bytes.borrow().len();
+                let i = index(ev, "bytevector-u8-ref", &args[1], Some(length))?;
+                Ok(Expr::integer(bytes.borrow()[i] as i64))
+            }
+            _ => Err(ev.error("bytevector-u8-ref: expected a bytevector", args.clone())),
+        }
+    });
+    define_primitive(env, "bytevector-u8-set!", 3, Some(3), |ev, args| {
+        match args[0].kind {
+            ExprKind::ByteVector(ref bytes) => {
+                let length = bytes.borrow().len();
+                let i = index(ev, "bytevector-u8-set!", &args[1], Some(length))?;
+                let b = byte(ev, "bytevector-u8-set!", &args[2])?;
+                modifiable(ev, "bytevector-u8-set!", &args[0])?;
+                bytes.borrow_mut()[i] = b;
+                Ok(Expr::unspecified())
+            }
+            _ => Err(ev.error("bytevector-u8-set!: expected a bytevector", args.clone())),
+        }
+    });
+}
+
+fn character(ev: &mut Evaluator, name: &str, arg: &ExprRef) -> Result<char, Unwind> {
+    match arg.kind {
+        ExprKind::Char(c) => Ok(c),
+        _ => Err(ev.error(format!("{}: expected a character", name), vec![arg.clone()])),
+    }
+}
+
+/// An exact integer from 0 to 255.
+fn byte(ev: &mut Evaluator, name: &str, arg: &ExprRef) -> Result<u8, Unwind> {
+    match arg.kind {
+        ExprKind::Num(ref n) => match n.kind {
+            NumberKind::Int(i) if i >= 0 && i <= 255 => Ok(i as u8),
+            _ => Err(ev.error(format!("{}: expected a byte", name), vec![arg.clone()])),
+        },
+        _ => Err(ev.error(format!("{}: expected a byte", name), vec![arg.clone()])),
+    }
+}
@@ -0,0 +1,70 @@
+//! Converting between symbols and strings, and making symbols which aren't
+//! interned.
+//!
+//! Identifiers inserted by macros count as the symbols they were made from,
+//! as they do for `symbol?`.
+//!
+//! Interned names are never freed, so a program interning a new one is
+//! charged for it on its heap, for good, and stopped like any other
+//! allocation once it's out of room.
+
+use error::Unwind;
+use read::parse::expr::{Env, Expr, ExprKind, ExprRef, Symbol};
+use read::parse::heap;
+use syntax::base_name;
+use {define_primitive, Evaluator};
+
+pub fn define_primitives(env: &Env) {
+    define_primitive(env, "symbol->string", 1, Some(1), |ev, args| {
+        let symbol = symbol(ev, "symbol->string", &args[0])?;
+        Ok(Expr::string(symbol.to_string()))
+    });
+    define_primitive(env, "string->symbol", 1, Some(1), |ev, args| {
+        match args[0].kind {
+            ExprKind::Str(ref s) => Ok(Expr::symbol(intern(ev, &s.borrow())?)),
+            _ => Err(ev.error("string->symbol: expected a string", args.clone())),
+        }
+    });
+    define_primitive(env, "symbol=?", 2, None, |ev, args| {
+        let first = symbol(ev, "symbol=?", &args[0])?;
+        let mut same = true;
+        for arg in &args[1..] {
+            same &= symbol(ev, "symbol=?", arg)? == first;
+        }
+        Ok(Expr::boolean(same))
+    });
+
+    define_primitive(env, "gensym", 0, Some(1), gensym);
+    define_primitive(env, "generate-uninterned-symbol", 0, Some(1), gensym);
+}
+
+/// Make a symbol no other is equal to, named after the string or symbol it's
+/// given, or `g` if it isn't given one.
+fn gensym(ev: &mut Evaluator, args: Vec<ExprRef>) -> Result<ExprRef, Unwind> {
+    let base = match args.first() {
+        None => Symbol::intern("g"),
+        Some(arg) => match arg.kind {
+            ExprKind::Str(ref s) => intern(ev, &s.borrow())?,
+            _ => symbol(ev, "gensym", arg)?.base(),
+        },
+    };
+    let symbol = ev.fresh_symbol(base)?;
+    Ok(Expr::symbol(symbol))
+}
+
+fn intern(ev: &mut Evaluator, name: &str) -> Result<Symbol, Unwind> {
+    if let Some(symbol) = Symbol::interned(name) {
+        return Ok(symbol);
+    }
+    let bytes = Symbol::interning_cost(name);
+    ev.reserve(bytes)?;
+    heap::charge_for_good(bytes);
+    Ok(Symbol::intern(name))
+}
+
+fn symbol(ev: &mut Evaluator, name: &str, arg: &ExprRef) -> Result<Symbol, Unwind> {
+    match base_name(arg) {
+        Some(symbol) => Ok(symbol),
+        None => Err(ev.error(format!("{}: expected a symbol", name), vec![arg.clone()])),
+    }
+}
@@ -0,0 +1,530 @@
+//! Macros, and the identifiers their expansions introduce.
+//!
+//! Macros are bound in environments like any other value, as `Syntax`
+//! objects. When the head of a form refers to one, the evaluator expands the
+//! form and evaluates the expansion in its place. Symbols inserted by an
+//! expansion are wrapped up as `Identifier`s which remember the environment
+//! the macro was defined in, and that is what keeps expansion hygienic: they
+//! refer to what they meant where the macro was written, and bindings they
+//! make are invisible to the code the macro was used in.
+//!
+//! Besides `syntax-rules`, macros can be written as procedures which run at
+//! expansion time. `er-macro-transformer` procedures rename the symbols they
+//! insert themselves, while `ir-macro-transformer` procedures have every
+//! symbol renamed for them, except the ones they explicitly inject.
+
+use atomic_refcell::AtomicRefCell;
+use error::Unwind;
+use primitives::eqv;
+use read::lex::token::Span;
+use read::parse::expr::{Env, Expr, ExprKind, ExprRef, Identifier, Pair, Symbol, Syntax};
+use read::parse::symbol::keywords;
+use rules;
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use {define_primitive, make_primitive, Evaluator, Step};
+
+/// What a symbol or identifier refers to where it appears.
+pub enum Resolved {
+    /// A variable or macro binding, with its current value.
+    Bound(ExprRef),
+    /// Nothing is bound, so it stands for itself: a special form keyword,
+    /// an auxiliary keyword like `else`, or an unbound variable.
+    Free(Symbol),
+}
+
+/// Work out what `expr` refers to in `env`, or `None` if it isn't a symbol
+/// or identifier.
+pub fn resolve(expr: &Expr, env: &Env) -> Option<Resolved> {
+    match expr.kind {
+        ExprKind::Symbol(name) => Some(match env.lookup(name) {
+            Some(value) => Resolved::Bound(value),
+            None => Resolved::Free(name),
+        }),
+        ExprKind::Identifier(ref id) => match env.lookup(id.alias) {
+            Some(value) => Some(Resolved::Bound(value)),
+            None => resolve(&id.name, &id.env),
+        },
+        _ => None,
+    }
+}
+
+/// The name a binding form binds `expr` under, if it's a symbol or
+/// identifier.
+pub fn binding_key(expr: &Expr) -> Option<Symbol> {
+    match expr.kind {
+        ExprKind::Symbol(name) => Some(name),
+        ExprKind::Identifier(ref id) => Some(id.alias),
+        _ => None,
+    }
+}
+
+/// Whether `formals` is a proper or dotted list of names, or a lone name, as
+/// a `lambda`'s parameters are.
+pub fn is_formals(formals: &Expr) -> bool {
+    match formals.kind {
+        ExprKind::Nil => true,
+        ExprKind::Pair(ref pair) => {
+            binding_key(&pair.car.borrow()).is_some() && is_formals(&pair.cdr.borrow())
+        }
+        _ => binding_key(formals).is_some(),
+    }
+}
+
+/// Bind `formals` to `values` in `env`, the way a procedure's parameters are
+/// bound to its arguments, with any left over collected into a list by a
+/// dotted tail. Gives back `false` if the counts don't match.
+pub fn bind_formals(formals: &ExprRef, values: Vec<ExprRef>, env: &Env) -> bool {
+    let mut values = values.into_iter();
+    let mut rest = formals.clone();
+
+    loop {
+        let next = match rest.kind {
+            ExprKind::Nil => return values.len() == 0,
+            ExprKind::Pair(ref pair) => match values.next() {
+                Some(value) => {
+                    if let Some(key) = binding_key(&pair.car.borrow()) {
+                        env.define(key, value);
+                    }
+                    pair.cdr.borrow().clone()
+                }
+                None => return false,
+            },
+            _ => {
+                if let Some(key) = binding_key(&rest) {
+                    env.define(key, Expr::list(values.collect()));
+                }
+                return true;
+            }
+        };
+        rest = next;
+    }
+}
+
+/// The symbol an identifier was made from, however many expansions ago.
+pub fn base_name(expr: &Expr) -> Option<Symbol> {
+    match expr.kind {
+        ExprKind::Symbol(name) => Some(name),
+        ExprKind::Identifier(ref id) => base_name(&id.name),
+        _ => None,
+    }
+}
+
+/// Whether `expr` is the unbound keyword `name`, as `else` and `=>` are in
+/// `cond` clauses.
+pub fn is_keyword(expr: &Expr, env: &Env, name: Symbol) -> bool {
+    match resolve(expr, env) {
+        Some(Resolved::Free(free)) => free == name,
+        _ => false,
+    }
+}
+
+/// Update the variable `expr` refers to. Returns `false` if it's unbound.
+pub fn assign(expr: &Expr, env: &Env, value: ExprRef) -> bool {
+    match expr.kind {
+        ExprKind::Symbol(name) => env.set(name, value),
+        ExprKind::Identifier(ref id) => {
+            if env.lookup(id.alias).is_some() {
+                env.set(id.alias, value)
+            } else {
+                assign(&id.name, &id.env, value)
+            }
+        }
+        _ => false,
+    }
+}
+
+/// Turn identifiers back into plain symbols, as `quote` does.
+///
+/// Quoted data can be nested as deeply as the program likes, so rather than
+/// calling itself, this keeps what's left to do on a stack. Lists and
+/// vectors are rebuilt once everything in them has been stripped.
+pub fn strip(expr: &ExprRef) -> ExprRef {
+    enum Task {
+        Strip(ExprRef),
+        Pair(ExprRef),
+        Vector(ExprRef, usize),
+    }
+
+    let mut tasks = vec![Task::Strip(expr.clone())];
+    let mut stripped: Vec<ExprRef> = Vec::new();
+    while let Some(task) = tasks.pop() {
+        match task {
+            Task::Strip(expr) => match expr.kind {
+                ExprKind::Identifier(ref id) => tasks.push(Task::Strip(id.name.clone())),
+                ExprKind::Pair(ref pair) => {
+                    tasks.push(Task::Pair(expr.clone()));
+                    tasks.push(Task::Strip(pair.cdr.borrow().clone()));
+                    tasks.push(Task::Strip(pair.car.borrow().clone()));
+                }
+                ExprKind::Vector(ref items) => {
+                    let items = items.borrow();
+                    tasks.push(Task::Vector(expr.clone(), items.len()));
+                    tasks.extend(items.iter().rev().map(|item| Task::Strip(item.clone())));
+                }
+                _ => stripped.push(expr.clone()),
+            },
+            Task::Pair(expr) => {
+                let cdr = stripped.pop().expect("a pair's cdr is stripped before it");
+                let car = stripped.pop().expect("a pair's car is stripped before it");
+                let pair = match expr.kind {
+                    ExprKind::Pair(ref pair) => pair,
+                    _ => unreachable!("only pairs are rebuilt as pairs"),
+                };
+                if Arc::ptr_eq(&car, &pair.car.borrow()) && Arc::ptr_eq(&cdr, &pair.cdr.borrow()) {
+                    stripped.push(expr.clone());
+                    continue;
+                }
+                let pair = Pair {
+                    car: AtomicRefCell::new(car),
+                    cdr: AtomicRefCell::new(cdr),
+                };
+                stripped.push(rebuilt(&expr, ExprKind::Pair(pair)));
+            }
+            Task::Vector(expr, len) => {
+                let items = stripped.split_off(stripped.len() - len);
+                stripped.push(rebuilt(&expr, ExprKind::Vector(AtomicRefCell::new(items))));
+            }
+        }
+    }
+    stripped.pop().expect("stripping gives back one value")
+}
+
+// A copy of `expr` holding `kind` instead, and read from the same place.
+fn rebuilt(expr: &Expr, kind: ExprKind) -> ExprRef {
+    let mut copy = Expr::new(kind);
+    copy.mutable = expr.mutable;
+    copy.span = expr.span;
+    copy.alloc()
+}
+
+/// Whether two identifiers refer to the same binding, or are both unbound
+/// and have the same name.
+pub fn same_binding(a: &Expr, a_env: &Env, b: &Expr, b_env: &Env) -> bool {
+    match (resolve(a, a_env), resolve(b, b_env)) {
+        (Some(Resolved::Free(a)), Some(Resolved::Free(b))) => a == b,
+        (Some(Resolved::Bound(a)), Some(Resolved::Bound(b))) => Arc::ptr_eq(&a, &b),
+        _ => false,
+    }
+}
+
+/// Make a pair which reports `span` as its place in the program.
+pub fn cons(car: ExprRef, cdr: ExprRef, span: Option<Span>) -> ExprRef {
+    let pair = Pair {
+        car: AtomicRefCell::new(car),
+        cdr: AtomicRefCell::new(cdr),
+    };
+    let mut expr = Expr::new(ExprKind::Pair(pair));
+    expr.span = span;
+    expr.alloc()
+}
+
+/// Make a pair standing in for one from a macro's template, with its parts
+/// filled in. It's a literal constant if the original was, so quoted data
+/// in a template stays constant.
+pub fn refill_pair(car: ExprRef, cdr: ExprRef, original: &Expr, span: Option<Span>) -> ExprRef {
+    let pair = Pair {
+        car: AtomicRefCell::new(car),
+        cdr: AtomicRefCell::new(cdr),
+    };
+    let mut expr = Expr::new(ExprKind::Pair(pair));
+    expr.mutable = original.mutable;
+    expr.span = span;
+    expr.alloc()
+}
+
+/// Make a vector standing in for one from a macro's template, like
+/// `refill_pair`.
+pub fn refill_vector(items: Vec<ExprRef>, original: &Expr) -> ExprRef {
+    let mut expr = Expr::new(ExprKind::Vector(AtomicRefCell::new(items)));
+    expr.mutable = original.mutable;
+    expr.span = original.span;
+    expr.alloc()
+}
+
+impl Evaluator {
+    /// Expand one use of a macro, giving back the form it stands for.
+    pub(crate) fn expand_macro(
+        &mut self,
+        syntax: &Syntax,
+        form: &ExprRef,
+        env: &Arc<Env>,
+    ) -> Result<ExprRef, Unwind> {
+        if syntax.transformer.is_procedure() {
+            return self.expand_explicit(syntax, form, env);
+        }
+        rules::expand(self, syntax, form, env)
+    }
+
+    /// Call an explicit renaming transformer with the form, and `rename` and
+    /// `compare` procedures for this expansion.
+    fn expand_explicit(
+        &mut self,
+        syntax: &Syntax,
+        form: &ExprRef,
+        env: &Arc<Env>,
+    ) -> Result<ExprRef, Unwind> {
+        let renames = AtomicRefCell::new(HashMap::<Symbol, ExprRef>::new());
+        let macro_env = syntax.env.clone();
+        let span = form.span;
+        let rename = make_primitive("rename", 1, Some(1), move |ev, args| {
+            let key = match binding_key(&args[0]) {
+                Some(key) => key,
+                None => return Err(ev.error("rename: expected a symbol", args)),
+            };
+            if let Some(renamed) = renames.borrow().get(&key) {
+                return Ok(renamed.clone());
+            }
+
+            ev.span = span;
+            let renamed = ev.rename(&args[0], &macro_env)?;
+            renames.borrow_mut().insert(key, renamed.clone());
+            Ok(renamed)
+        });
+
+        let use_env = env.clone();
+        let compare = make_primitive("compare", 2, Some(2), move |_, args| {
+            let same = match (binding_key(&args[0]), binding_key(&args[1])) {
+                (Some(..), Some(..)) => same_binding(&args[0], &use_env, &args[1], &use_env),
+                _ => eqv(&args[0], &args[1]),
+            };
+            Ok(Expr::boolean(same))
+        });
+
+        self.span = form.span;
+        self.apply(syntax.transformer.clone(), vec![form.clone(), rename, compare])
+    }
+
+    /// Make a macro out of a transformer spec. `(syntax-rules ...)` is
+    /// handled here, and anything else is evaluated and should give back a
+    /// macro, like those made by `er-macro-transformer`.
+    fn make_syntax(&mut self, spec: &ExprRef, env: &Arc<Env>) -> Result<ExprRef, Unwind> {
+        let is_rules = spec
+            .car()
+            .map_or(false, |head| is_keyword(&head, env, keywords::SYNTAX_RULES));
+
+        if is_rules {
+            if !rules::is_valid(spec) {
+                return Err(self.syntax_error(spec));
+            }
+
+            let syntax = Syntax {
+                transformer: spec.clone(),
+                env: env.clone(),
+            };
+            return Ok(Expr::new(ExprKind::Syntax(syntax)).alloc());
+        }
+
+        let transformer = self.eval_in(spec.clone(), env.clone())?;
+        match transformer.kind {
+            ExprKind::Syntax(..) => Ok(transformer),
+            _ => {
+                self.span = spec.span;
+                Err(self.error("not a macro transformer", vec![transformer.clone()]))
+            }
+        }
+    }
+
+    /// Bind the `(name transformer)` pairs of a `let-syntax` in `scope`.
+    /// Recursive macros are defined in the scope they're bound in, so their
+    /// expansions can refer to each other.
+    pub(crate) fn bind_syntax(
+        &mut self,
+        form: &ExprRef,
+        bindings: &ExprRef,
+        env: &Arc<Env>,
+        scope: &Arc<Env>,
+        recursive: bool,
+    ) -> Result<(), Unwind> {
+        let macro_env = if recursive { scope } else { env };
+        for (name, spec) in self.bindings(form, bindings)? {
+            let syntax = self.make_syntax(&spec, macro_env)?;
+            scope.define(name, syntax);
+        }
+        Ok(())
+    }
+
+    /// Wrap up an explicit renaming procedure as a macro. The macro's
+    /// environment is the one `procedure` closes over, which is where the
+    /// symbols it renames will be looked up.
+    fn make_transformer(&mut self, procedure: &ExprRef, transformer: ExprRef) -> Result<ExprRef, Unwind> {
+        let env = match procedure.kind {
+            ExprKind::Closure(ref closure) => closure.env.clone(),
+            ExprKind::Primitive(..) => self.global.clone(),
+            _ => return Err(self.error("expected a procedure", vec![procedure.clone()])),
+        };
+
+        let syntax = Syntax { transformer, env };
+        Ok(Expr::new(ExprKind::Syntax(syntax)).alloc())
+    }
+
+    /// Make a fresh identifier for `name`, to be resolved in `env`.
+    pub(crate) fn rename(&mut self, name: &ExprRef, env: &Arc<Env>) -> Result<ExprRef, Unwind> {
+        let base = base_name(name).unwrap_or_else(|| Symbol::intern(""));
+        let id = Identifier {
+            name: name.clone(),
+            env: env.clone(),
+            alias: self.fresh_symbol(base)?,
+        };
+        let mut expr = Expr::new(ExprKind::Identifier(id));
+        expr.span = self.span;
+        Ok(expr.alloc())
+    }
+
+    /// Make a symbol named after `base` which no other symbol is equal to.
+    /// Aliases and `gensym` share a counter, so they never clash either.
+    pub(crate) fn fresh_symbol(&mut self, base: Symbol) -> Result<Symbol, Unwind> {
+        match Symbol::fresh(base) {
+            Some(symbol) => Ok(symbol),
+            None => Err(self.error("ran out of fresh symbols", vec![])),
+        }
+    }
+}
+
+pub fn define_primitives(env: &Env) {
+    define_primitive(env, "er-macro-transformer", 1, Some(1), |ev, args| {
+        ev.make_transformer(&args[0], args[0].clone())
+    });
+    define_primitive(env, "ir-macro-transformer", 1, Some(1), |ev, args| {
+        let procedure = args[0].clone();
+        let transformer = make_primitive("ir-macro-transformer", 3, Some(3), move |ev, args| {
+            implicit_renaming(ev, &procedure, args)
+        });
+        ev.make_transformer(&args[0], transformer)
+    });
+}
+
+/// Run an implicit renaming transformer, as an explicit renaming one.
+///
+/// The transformer gets the form, an `inject` procedure and `compare`. The
+/// symbols it was given, and those it injected, refer to the macro use;
+/// every other symbol in what it returns is renamed.
+fn implicit_renaming(ev: &mut Evaluator, procedure: &ExprRef, args: Vec<ExprRef>) -> Result<ExprRef, Unwind> {
+    // The symbols we're keeping are tracked by address, and kept alive by
+    // the form and the list of injected symbols for as long as we need them.
+    let kept = Arc::new(AtomicRefCell::new(HashSet::new()));
+    let injected = Arc::new(AtomicRefCell::new(Vec::new()));
+    collect_symbols(&args[0], &mut kept.borrow_mut());
+
+    let (inject_kept, inject_injected) = (kept.clone(), injected.clone());
+    let inject = make_primitive("inject", 1, Some(1), move |_, args| {
+        let symbol = match args[0].kind {
+            ExprKind::Symbol(ref name) => Expr::symbol(name.clone()),
+            ExprKind::Identifier(ref id) => {
+                let id = Identifier {
+                    name: id.name.clone(),
+                    env: id.env.clone(),
+                    alias: id.alias.clone(),
+                };
+                Expr::new(ExprKind::Identifier(id)).alloc()
+            }
+            _ => return Ok(args[0].clone()),
+        };
+        inject_kept.borrow_mut().insert(&*symbol as *const Expr as usize);
+        inject_injected.borrow_mut().push(symbol.clone());
+        Ok(symbol)
+    });
+
+    let expansion = ev.apply(procedure.clone(), vec![args[0].clone(), inject, args[2].clone()])?;
+    let kept = kept.borrow();
+    rename_introduced(ev, &expansion, &kept, &args[1])
+}
+
+fn collect_symbols(expr: &ExprRef, symbols: &mut HashSet<usize>) {
+    match expr.kind {
+        ExprKind::Symbol(..) | ExprKind::Identifier(..) => {
+            symbols.insert(&**expr as *const Expr as usize);
+        }
+        ExprKind::Pair(ref pair) => {
+            collect_symbols(&pair.car.borrow(), symbols);
+            collect_symbols(&pair.cdr.borrow(), symbols);
+        }
+        ExprKind::Vector(ref items) => {
+            for item in items.borrow().iter() {
+                collect_symbols(item, symbols);
+            }
+        }
+        _ => {}
+    }
+}
+
+fn rename_introduced(
+    ev: &mut Evaluator,
+    expr: &ExprRef,
+    kept: &HashSet<usize>,
+    rename: &ExprRef,
+) -> Result<ExprRef, Unwind> {
+    match expr.kind {
+        ExprKind::Symbol(..) | ExprKind::Identifier(..) => {
+            if kept.contains(&(&**expr as *const Expr as usize)) {
+                Ok(expr.clone())
+            } else {
+                ev.apply(rename.clone(), vec![expr.clone()])
+            }
+        }
+        ExprKind::Pair(ref pair) => {
+            let car = rename_introduced(ev, &pair.car.borrow(), kept, rename)?;
+            let cdr = rename_introduced(ev, &pair.cdr.borrow(), kept, rename)?;
+            Ok(refill_pair(car, cdr, expr, expr.span))
+        }
+        ExprKind::Vector(ref items) => {
+            let items = items.borrow().clone();
+            let mut renamed = Vec::with_capacity(items.len());
+            for item in &items {
+                renamed.push(rename_introduced(ev, item, kept, rename)?);
+            }
+            Ok(refill_vector(renamed, expr))
+        }
+        _ => Ok(expr.clone()),
+    }
+}
+
+pub fn define_syntax(
+    ev: &mut Evaluator,
+    form: &ExprRef,
+    args: &[ExprRef],
+    env: &Arc<Env>,
+) -> Result<Step, Unwind> {
+    let name = match args.first().and_then(|name| binding_key(name)) {
+        Some(name) if args.len() == 2 => name,
+        _ => return Err(ev.syntax_error(form)),
+    };
+
+    let syntax = ev.make_syntax(&args[1], env)?;
+    env.define(name, syntax);
+    Ok(Step::Done(Expr::unspecified()))
+}
+
+pub fn let_syntax(
+    ev: &mut Evaluator,
+    form: &ExprRef,
+    args: &[ExprRef],
+    env: &Arc<Env>,
+) -> Result<Step, Unwind> {
+    syntax_bindings(ev, form, args, env, false)
+}
+
+pub fn letrec_syntax(
+    ev: &mut Evaluator,
+    form: &ExprRef,
+    args: &[ExprRef],
+    env: &Arc<Env>,
+) -> Result<Step, Unwind> {
+    syntax_bindings(ev, form, args, env, true)
+}
+
+fn syntax_bindings(
+    ev: &mut Evaluator,
+    form: &ExprRef,
+    args: &[ExprRef],
+    env: &Arc<Env>,
+    recursive: bool,
+) -> Result<Step, Unwind> {
+    if args.len() < 2 {
+        return Err(ev.syntax_error(form));
+    }
+
+    let body_env = Env::child(&env);
+    ev.bind_syntax(form, &args[0], env, &body_env, recursive)?;
+    ev.eval_body(&args[1..], &body_env)
+}
@@ -0,0 +1,176 @@
+//! Hash tables, with keys compared by `eqv?`.
+//!
+//! Besides ordinary tables, there are weak tables, which don't keep their
+//! keys alive, and ephemeron tables, which don't keep their keys alive nor
+//! any value whose key is gone. Entries whose keys have been collected are
+//! dropped as they're come across.
+
+use error::Unwind;
+use primitives::eqv;
+use read::parse::expr::{Entry, Env, Expr, ExprKind, ExprRef, HashTable, Held, NumberKind, Weakness};
+use read::parse::heap;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::mem;
+use {define_primitive, Evaluator};
+
+pub fn define_primitives(env: &Env) {
+    define_primitive(env, "make-hash-table", 0, Some(0), |_, _| Ok(make_table(Weakness::Strong)));
+    define_primitive(env, "make-weak-hash-table", 0, Some(0), |_, _| {
+        Ok(make_table(Weakness::WeakKeys))
+    });
+    define_primitive(env, "make-ephemeron-hash-table", 0, Some(0), |_, _| {
+        Ok(make_table(Weakness::Ephemeral))
+    });
+    define_primitive(env, "hash-table?", 1, Some(1), |_, args| {
+        let is_table = match args[0].kind {
+            ExprKind::HashTable(..) => true,
+            _ => false,
+        };
+        Ok(Expr::boolean(is_table))
+    });
+
+    define_primitive(env, "hash-table-ref/default", 3, Some(3), |ev, args| {
+        let table = table(ev, "hash-table-ref/default", &args[0])?;
+        Ok(lookup(table, &args[1]).unwrap_or_else(|| args[2].clone()))
+    });
+    define_primitive(env, "hash-table-contains?", 2, Some(2), |ev, args| {
+        let table = table(ev, "hash-table-contains?", &args[0])?;
+        Ok(Expr::boolean(lookup(table, &args[1]).is_some()))
+    });
+    define_primitive(env, "hash-table-set!", 3, Some(3), |ev, args| {
+        let table = table(ev, "hash-table-set!", &args[0])?;
+        if insert(table, &args[1], args[2].clone()) {
+            args[0].grow(mem::size_of::<Entry>());
+        }
+        Ok(Expr::unspecified())
+    });
+    define_primitive(env, "hash-table-delete!", 2, Some(2), |ev, args| {
+        let table = table(ev, "hash-table-delete!", &args[0])?;
+        remove(table, &args[1]);
+        Ok(Expr::unspecified())
+    });
+    define_primitive(env, "hash-table-count", 1, Some(1), |ev, args| {
+        let table = table(ev, "hash-table-count", &args[0])?;
+        prune(table);
+        let count = table.buckets.borrow().values().map(Vec::len).sum::<usize>();
+        Ok(Expr::integer(count as i64))
+    });
+    define_primitive(env, "hash-table-keys", 1, Some(1), |ev, args| {
+        let table = table(ev, "hash-table-keys", &args[0])?;
+        prune(table);
+        let buckets = table.buckets.borrow();
+        let keys = buckets
+            .values()
+            .flat_map(|entries| entries.iter())
+            .filter_map(|entry| entry.key.get())
+            .collect();
+        Ok(Expr::list(keys))
+    });
+}
+
+fn make_table(weakness: Weakness) -> ExprRef {
+    Expr::new(ExprKind::HashTable(HashTable::new(weakness))).alloc()
+}
+
+fn table<'a>(ev: &mut Evaluator, name: &str, arg: &'a ExprRef) -> Result<&'a HashTable, Unwind> {
+    match arg.kind {
+        ExprKind::HashTable(ref table) => Ok(table),
+        _ => Err(ev.error(format!("{}: expected a hash table", name), vec![arg.clone()])),
+    }
+}
+
+/// A hash of a key which agrees with `eqv?`: atoms are hashed by value, and
+/// everything else by where it lives.
+fn hash(key: &ExprRef) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    mem::discriminant(&key.kind).hash(&mut hasher);
+    match key.kind {
+        ExprKind::Nil | ExprKind::Unspecified => {}
+        ExprKind::Bool(b) => b.hash(&mut hasher),
+        ExprKind::Char(c) => c.hash(&mut hasher),
+        ExprKind::Symbol(ref s) => s.hash(&mut hasher),
+        ExprKind::Num(ref n) => match n.kind {
+            NumberKind::Int(i) => i.hash(&mut hasher),
+            NumberKind::Real(f) => f.to_bits().hash(&mut hasher),
+            NumberKind::Rational { numerator, denominator } => {
+                (numerator, denominator).hash(&mut hasher)
+            }
+        },
+        _ => (&**key as *const Expr as usize).hash(&mut hasher),
+    }
+    hasher.finish()
+}
+
+fn matches(entry: &Entry, key: &ExprRef) -> bool {
+    match entry.key {
+        Held::Strong(ref held) => eqv(held, key),
+        Held::Weak(..) => entry.key.is(key),
+    }
+}
+
+fn lookup(table: &HashTable, key: &ExprRef) -> Option<ExprRef> {
+    let buckets = table.buckets.borrow();
+    let entry = buckets.get(&hash(key))?.iter().find(|entry| matches(entry, key))?;
+    // An ephemeron table's values may not have been marked yet.
+    heap::read_barrier(&entry.value);
+    Some(entry.value.clone())
+}
+
+/// Set the value of `key`, giving back `true` if it's a new entry.
+pub fn insert(table: &HashTable, key: &ExprRef, value: ExprRef) -> bool {
+    let mut buckets = table.buckets.borrow_mut();
+    let entries = buckets.entry(hash(key)).or_insert_with(Vec::new);
+    drop_entries(entries, |entry| entry.key.is_broken());
+
+    match entries.iter_mut().find(|entry| matches(entry, key)) {
+        Some(entry) => {
+            heap::write_barrier(&mem::replace(&mut entry.value, value));
+            false
+        }
+        None => {
+            let key = match table.weakness {
+                Weakness::Strong => Held::Strong(key.clone()),
+                Weakness::WeakKeys | Weakness::Ephemeral => Held::new(key),
+            };
+            entries.push(Entry { key, value });
+            true
+        }
+    }
+}
+
+fn remove(table: &HashTable, key: &ExprRef) {
+    let mut buckets = table.buckets.borrow_mut();
+    let hash = hash(key);
+    if let Some(entries) = buckets.get_mut(&hash) {
+        drop_entries(entries, |entry| matches(entry, key) || entry.key.is_broken());
+    }
+    if buckets.get(&hash).map_or(false, Vec::is_empty) {
+        buckets.remove(&hash);
+    }
+}
+
+/// Drop every entry whose key has been collected.
+fn prune(table: &HashTable) {
+    let mut buckets = table.buckets.borrow_mut();
+    for entries in buckets.values_mut() {
+        drop_entries(entries, |entry| entry.key.is_broken());
+    }
+    buckets.retain(|_, entries| !entries.is_empty());
+}
+
+/// Drop the entries `doomed` picks out, telling the collector about each.
+fn drop_entries<F: Fn(&Entry) -> bool>(entries: &mut Vec<Entry>, doomed: F) {
+    let mut i = 0;
+    while i < entries.len() {
+        if doomed(&entries[i]) {
+            let entry = entries.swap_remove(i);
+            if let Held::Strong(ref key) = entry.key {
+                heap::write_barrier(key);
+            }
+            heap::write_barrier(&entry.value);
+        } else {
+            i += 1;
+        }
+    }
+}
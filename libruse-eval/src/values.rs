@@ -0,0 +1,158 @@
+//! Multiple return values.
+//!
+//! Most expressions have a single value, which is returned as it is. Only
+//! `values` called with some other number of arguments makes a `Values`
+//! object, holding them all, so ordinary returns never pay for the
+//! possibility of several.
+
+use error::Unwind;
+use read::parse::expr::{Env, Expr, ExprKind, ExprRef};
+use std::sync::Arc;
+use syntax::{self, is_formals};
+use {define_primitive, define_tail_primitive, Evaluator, Step};
+
+impl Evaluator {
+    /// Bind `formals` to the values an expression returned.
+    fn bind_values(
+        &mut self,
+        form: &ExprRef,
+        formals: &ExprRef,
+        values: Vec<ExprRef>,
+        env: &Arc<Env>,
+    ) -> Result<(), Unwind> {
+        let count = values.len();
+        if syntax::bind_formals(formals, values, env) {
+            return Ok(());
+        }
+
+        self.span = form.span;
+        let irritants = vec![syntax::strip(formals), Expr::integer(count as i64)];
+        Err(self.error("wrong number of values", irritants))
+    }
+
+    /// Split `((formals init) ...)` into formals and initializers.
+    fn values_bindings(
+        &mut self,
+        form: &ExprRef,
+        bindings: &ExprRef,
+    ) -> Result<Vec<(ExprRef, ExprRef)>, Unwind> {
+        let mut pairs = Vec::new();
+
+        let bindings = match bindings.to_vec() {
+            Some(bindings) => bindings,
+            None => return Err(self.syntax_error(form)),
+        };
+        for binding in bindings {
+            match binding.to_vec() {
+                Some(ref parts) if parts.len() == 2 && is_formals(&parts[0]) => {
+                    pairs.push((parts[0].clone(), parts[1].clone()));
+                }
+                _ => return Err(self.syntax_error(form)),
+            }
+        }
+
+        Ok(pairs)
+    }
+
+    /// Evaluate an expression, giving back every value it returns.
+    fn eval_values_in(&mut self, expr: ExprRef, env: Arc<Env>) -> Result<Vec<ExprRef>, Unwind> {
+        self.eval_in(expr, env).map(into_values)
+    }
+}
+
+/// Make the result of returning `values`.
+pub fn from_values(mut values: Vec<ExprRef>) -> ExprRef {
+    if values.len() == 1 {
+        values.pop().unwrap_or_else(Expr::unspecified)
+    } else {
+        Expr::new(ExprKind::Values(values)).alloc()
+    }
+}
+
+/// The values held by a result.
+pub fn into_values(result: ExprRef) -> Vec<ExprRef> {
+    match result.kind {
+        ExprKind::Values(ref values) => values.clone(),
+        _ => vec![result.clone()],
+    }
+}
+
+pub fn define_primitives(env: &Env) {
+    define_primitive(env, "values", 0, None, |_, args| Ok(from_values(args)));
+
+    define_tail_primitive(env, "call-with-values", 2, Some(2), |ev, mut args| {
+        let consumer = args.pop().unwrap_or_else(Expr::unspecified);
+        let producer = args.pop().unwrap_or_else(Expr::unspecified);
+        let result = ev.apply(producer, Vec::new())?;
+        Ok(Step::Apply(consumer, into_values(result)))
+    });
+}
+
+pub fn let_values(
+    ev: &mut Evaluator,
+    form: &ExprRef,
+    args: &[ExprRef],
+    env: &Arc<Env>,
+) -> Result<Step, Unwind> {
+    if args.len() < 2 {
+        return Err(ev.syntax_error(form));
+    }
+
+    let body_env = Env::child(&env);
+    for (formals, init) in ev.values_bindings(form, &args[0])? {
+        let values = ev.eval_values_in(init, env.clone())?;
+        ev.bind_values(form, &formals, values, &body_env)?;
+    }
+    ev.eval_body(&args[1..], &body_env)
+}
+
+pub fn let_star_values(
+    ev: &mut Evaluator,
+    form: &ExprRef,
+    args: &[ExprRef],
+    env: &Arc<Env>,
+) -> Result<Step, Unwind> {
+    if args.len() < 2 {
+        return Err(ev.syntax_error(form));
+    }
+
+    let mut body_env = env.clone();
+    for (formals, init) in ev.values_bindings(form, &args[0])? {
+        let values = ev.eval_values_in(init, body_env.clone())?;
+        let next = Env::child(&body_env);
+        ev.bind_values(form, &formals, values, &next)?;
+        body_env = next;
+    }
+    ev.eval_body(&args[1..], &body_env)
+}
+
+pub fn define_values(
+    ev: &mut Evaluator,
+    form: &ExprRef,
+    args: &[ExprRef],
+    env: &Arc<Env>,
+) -> Result<Step, Unwind> {
+    if args.len() != 2 || !is_formals(&args[0]) {
+        return Err(ev.syntax_error(form));
+    }
+
+    let values = ev.eval_values_in(args[1].clone(), env.clone())?;
+    ev.bind_values(form, &args[0], values, env)?;
+    Ok(Step::Done(Expr::unspecified()))
+}
+
+pub fn receive(
+    ev: &mut Evaluator,
+    form: &ExprRef,
+    args: &[ExprRef],
+    env: &Arc<Env>,
+) -> Result<Step, Unwind> {
+    if args.len() < 3 || !is_formals(&args[0]) {
+        return Err(ev.syntax_error(form));
+    }
+
+    let values = ev.eval_values_in(args[1].clone(), env.clone())?;
+    let body_env = Env::child(&env);
+    ev.bind_values(form, &args[0], values, &body_env)?;
+    ev.eval_body(&args[2..], &body_env)
+}
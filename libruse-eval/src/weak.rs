@@ -0,0 +1,79 @@
+//! Weak boxes, and ephemerons as in SRFI 124.
+//!
+//! Neither keeps its key alive. A weak box just gives back `#f` once its
+//! value is collected, while an ephemeron also holds a datum which is only
+//! kept alive as long as the key is, even if the datum refers to the key.
+
+use error::Unwind;
+use read::parse::expr::{Env, Ephemeron, Expr, ExprKind, ExprRef, Held, WeakBox};
+use read::parse::heap;
+use {define_primitive, Evaluator};
+
+pub fn define_primitives(env: &Env) {
+    define_primitive(env, "make-weak-box", 1, Some(1), |_, args| {
+        let weak_box = WeakBox {
+            value: Held::new(&args[0]),
+        };
+        Ok(Expr::new(ExprKind::WeakBox(weak_box)).alloc())
+    });
+    define_primitive(env, "weak-box?", 1, Some(1), |_, args| {
+        let is_weak_box = match args[0].kind {
+            ExprKind::WeakBox(..) => true,
+            _ => false,
+        };
+        Ok(Expr::boolean(is_weak_box))
+    });
+    // The value, or the default (`#f` if there isn't one) once it's gone.
+    define_primitive(env, "weak-box-value", 1, Some(2), |ev, args| {
+        let value = weak_box(ev, "weak-box-value", &args[0])?.value.get();
+        let default = args.get(1).cloned().unwrap_or_else(|| Expr::boolean(false));
+        Ok(value.unwrap_or(default))
+    });
+
+    define_primitive(env, "make-ephemeron", 2, Some(2), |_, args| {
+        let ephemeron = Ephemeron::new(&args[0], args[1].clone());
+        Ok(Expr::new(ExprKind::Ephemeron(ephemeron)).alloc())
+    });
+    define_primitive(env, "ephemeron?", 1, Some(1), |_, args| {
+        let is_ephemeron = match args[0].kind {
+            ExprKind::Ephemeron(..) => true,
+            _ => false,
+        };
+        Ok(Expr::boolean(is_ephemeron))
+    });
+    define_primitive(env, "ephemeron-broken?", 1, Some(1), |ev, args| {
+        let broken = ephemeron(ev, "ephemeron-broken?", &args[0])?.is_broken();
+        Ok(Expr::boolean(broken))
+    });
+    // A broken ephemeron's key and datum are both `#f`.
+    define_primitive(env, "ephemeron-key", 1, Some(1), |ev, args| {
+        let key = ephemeron(ev, "ephemeron-key", &args[0])?.key.borrow().get();
+        Ok(key.unwrap_or_else(|| Expr::boolean(false)))
+    });
+    define_primitive(env, "ephemeron-datum", 1, Some(1), |ev, args| {
+        let ephemeron = ephemeron(ev, "ephemeron-datum", &args[0])?;
+        if ephemeron.is_broken() {
+            return Ok(Expr::boolean(false));
+        }
+        let datum = ephemeron.datum.borrow().clone();
+        heap::read_barrier(&datum);
+        Ok(datum)
+    });
+    // Holding on to the key until here is all it takes, since it's an
+    // argument.
+    define_primitive(env, "reference-barrier", 1, Some(1), |_, _| Ok(Expr::unspecified()));
+}
+
+fn weak_box<'a>(ev: &mut Evaluator, name: &str, arg: &'a ExprRef) -> Result<&'a WeakBox, Unwind> {
+    match arg.kind {
+        ExprKind::WeakBox(ref weak_box) => Ok(weak_box),
+        _ => Err(ev.error(format!("{}: expected a weak box", name), vec![arg.clone()])),
+    }
+}
+
+fn ephemeron<'a>(ev: &mut Evaluator, name: &str, arg: &'a ExprRef) -> Result<&'a Ephemeron, Unwind> {
+    match arg.kind {
+        ExprKind::Ephemeron(ref ephemeron) => Ok(ephemeron),
+        _ => Err(ev.error(format!("{}: expected an ephemeron", name), vec![arg.clone()])),
+    }
+}
@@ -4,10 +4,287 @@
 
 extern crate libruse_read as read;
 
-use read::parse::expr::Expr;
+use read::parse::expr::{ConditionKind, Expr, ExprKind, ExprRef, NumberKind, Symbol};
+use std::collections::HashMap;
+use std::fmt::Write;
 
-// TODO: Implement this function.
 /// Print a Ruse expression.
-pub fn print(_: Expr) -> Result<String, ()> {
-    unimplemented!()
+///
+/// Pairs, vectors and records which are part of a cycle are written with
+/// datum labels, so a list whose tail leads back to its start is written
+/// like `#0=(1 2 . #0#)` rather than going round forever. However deeply a
+/// value is nested, printing it doesn't use up the stack.
+pub fn print(expr: &Expr) -> String {
+    let mut printer = Printer {
+        out: String::new(),
+        labels: find_cycles(expr),
+        next_label: 0,
+    };
+    printer.write(expr);
+    printer.out
+}
+
+/// How far the search for cycles has got with a value.
+#[derive(Clone, Copy, PartialEq)]
+enum Visit {
+    /// Its parts are still being searched, so reaching it again from one
+    /// of them means it's part of a cycle.
+    Open,
+    Done,
+}
+
+/// Find the values which can be reached from themselves. Each gets an
+/// empty slot for the label it's given when it's written.
+fn find_cycles(expr: &Expr) -> HashMap<usize, Option<usize>> {
+    let mut visits = HashMap::new();
+    let mut cycles = HashMap::new();
+
+    // Each value is searched once it's popped, pushing a marker for when
+    // its parts are done and then the parts themselves.
+    let mut stack = vec![(address(expr), parts(expr), true)];
+    while let Some((at, parts, entering)) = stack.pop() {
+        if !entering {
+            visits.insert(at, Visit::Done);
+            continue;
+        }
+        match visits.get(&at) {
+            Some(&Visit::Open) => {
+                cycles.insert(at, None);
+                continue;
+            }
+            Some(&Visit::Done) => continue,
+            None => {}
+        }
+
+        visits.insert(at, Visit::Open);
+        stack.push((at, Vec::new(), false));
+        for part in parts.into_iter().rev() {
+            let inner = self::parts(&part);
+            if !inner.is_empty() {
+                stack.push((address(&part), inner, true));
+            }
+        }
+    }
+    cycles
+}
+
+/// The values a value is written in terms of.
+fn parts(expr: &Expr) -> Vec<ExprRef> {
+    match expr.kind {
+        ExprKind::Pair(ref pair) => vec![pair.car.borrow().clone(), pair.cdr.borrow().clone()],
+        ExprKind::Vector(ref items) => items.borrow().clone(),
+        ExprKind::Record(ref record) => record.values.borrow().clone(),
+        ExprKind::Values(ref values) => values.clone(),
+        ExprKind::Identifier(ref id) => vec![id.name.clone()],
+        ExprKind::Condition(ref condition) => vec![condition.irritants.clone()],
+        _ => Vec::new(),
+    }
+}
+
+fn address(expr: &Expr) -> usize {
+    expr as *const Expr as usize
+}
+
+struct Printer {
+    out: String,
+    /// The values which are part of a cycle, with the labels of those
+    /// which have been written.
+    labels: HashMap<usize, Option<usize>>,
+    next_label: usize,
+}
+
+/// Something left to write, once whatever comes before it has been.
+enum Task {
+    Value(ExprRef),
+    /// The rest of a list, after its first item.
+    Tail(ExprRef),
+    Text(&'static str),
+    Owned(String),
+}
+
+impl Printer {
+    /// Write a value, keeping the parts of it still to be written on a
+    /// stack of their own rather than recursing into them.
+    fn write(&mut self, expr: &Expr) {
+        let mut tasks = Vec::new();
+        self.write_expr(expr, &mut tasks);
+        while let Some(task) = tasks.pop() {
+            match task {
+                Task::Value(value) => self.write_expr(&value, &mut tasks),
+                Task::Tail(rest) => self.write_tail(rest, &mut tasks),
+                Task::Text(text) => self.out.push_str(text),
+                Task::Owned(text) => self.out.push_str(&text),
+            }
+        }
+    }
+
+    /// Write the start of a value, pushing whatever it's made of onto
+    /// `tasks`, last part first.
+    fn write_expr(&mut self, expr: &Expr, tasks: &mut Vec<Task>) {
+        if let Some(label) = self.labels.get_mut(&address(expr)) {
+            match *label {
+                Some(n) => {
+                    let _ = write!(self.out, "#{}#", n);
+                    return;
+                }
+                None => {
+                    *label = Some(self.next_label);
+                    let _ = write!(self.out, "#{}=", self.next_label);
+                    self.next_label += 1;
+                }
+            }
+        }
+
+        match expr.kind {
+            ExprKind::Nil => self.out.push_str("()"),
+            ExprKind::Unspecified => {}
+            ExprKind::Bool(true) => self.out.push_str("#t"),
+            ExprKind::Bool(false) => self.out.push_str("#f"),
+            ExprKind::Char(c) => self.write_char(c),
+            ExprKind::Num(ref n) => {
+                let _ = match n.kind {
+                    NumberKind::Int(i) => write!(self.out, "{}", i),
+                    NumberKind::Real(f) => write!(self.out, "{:?}", f),
+                    NumberKind::Rational {
+                        numerator,
+                        denominator,
+                    } => write!(self.out, "{}/{}", numerator, denominator),
+                };
+            }
+            ExprKind::Pair(ref pair) => {
+                self.out.push('(');
+                tasks.push(Task::Text(")"));
+                tasks.push(Task::Tail(pair.cdr.borrow().clone()));
+                tasks.push(Task::Value(pair.car.borrow().clone()));
+            }
+            ExprKind::Closure(ref c) => self.write_procedure(*c.name.borrow()),
+            ExprKind::CaseLambda(ref c) => self.write_procedure(*c.name.borrow()),
+            ExprKind::Primitive(ref p) => {
+                let _ = write!(self.out, "#<procedure {}>", p.name);
+            }
+            ExprKind::Syntax(..) => self.out.push_str("#<syntax>"),
+            ExprKind::Symbol(ref s) => {
+                let _ = write!(self.out, "{}", s);
+            }
+            ExprKind::Identifier(ref id) => tasks.push(Task::Value(id.name.clone())),
+            ExprKind::Str(ref s) => self.write_string(&s.borrow()),
+            ExprKind::Vector(ref v) => {
+                self.out.push_str("#(");
+                tasks.push(Task::Text(")"));
+                push_separated(tasks, v.borrow().iter().cloned().collect());
+            }
+            ExprKind::ByteVector(ref v) => {
+                self.out.push_str("#u8(");
+                for (i, byte) in v.borrow().iter().enumerate() {
+                    if i > 0 {
+                        self.out.push(' ');
+                    }
+                    let _ = write!(self.out, "{}", byte);
+                }
+                self.out.push(')');
+            }
+            ExprKind::Continuation(..) => self.out.push_str("#<continuation>"),
+            ExprKind::Condition(ref c) => {
+                let name = match c.kind {
+                    ConditionKind::Error => "error",
+                    ConditionKind::Read => "read-error",
+                    ConditionKind::File => "file-error",
+                    ConditionKind::Interrupt => "interrupt",
+                };
+                let _ = write!(self.out, "#<{} ", name);
+                self.write_string(&c.message);
+                tasks.push(Task::Text(">"));
+                for irritant in c.irritants.to_vec().unwrap_or_default().into_iter().rev() {
+                    tasks.push(Task::Value(irritant));
+                    tasks.push(Task::Text(" "));
+                }
+            }
+            ExprKind::Port(..) => self.out.push_str("#<port>"),
+            ExprKind::Env(..) => self.out.push_str("#<environment>"),
+            ExprKind::RecordType(ref t) => {
+                let _ = write!(self.out, "#<record-type {}>", t.short_name());
+            }
+            ExprKind::Record(ref r) => {
+                let _ = write!(self.out, "#<{}", r.record_type.short_name());
+                tasks.push(Task::Text(">"));
+                for (name, value) in r.fields().into_iter().rev() {
+                    tasks.push(Task::Value(value));
+                    tasks.push(Task::Owned(format!(" {}: ", name)));
+                }
+            }
+            ExprKind::Values(ref values) => push_separated(tasks, values.clone()),
+            ExprKind::Promise(..) => self.out.push_str("#<promise>"),
+            ExprKind::Parameter(..) => self.out.push_str("#<parameter>"),
+            ExprKind::WeakBox(..) => self.out.push_str("#<weak-box>"),
+            ExprKind::Ephemeron(..) => self.out.push_str("#<ephemeron>"),
+            ExprKind::HashTable(..) => self.out.push_str("#<hash-table>"),
+            ExprKind::Guardian(..) => self.out.push_str("#<guardian>"),
+            ExprKind::Host(ref host) => {
+                let _ = write!(self.out, "#<host:{}>", host.type_name);
+            }
+        }
+    }
+
+    fn write_procedure(&mut self, name: Option<Symbol>) {
+        let _ = match name {
+            Some(name) => write!(self.out, "#<procedure {}>", name),
+            None => write!(self.out, "#<procedure>"),
+        };
+    }
+
+    /// Write what follows the first item of a list: nothing more if it's
+    /// the end, the next item if it goes on, or a dot and the rest if it's
+    /// improper.
+    fn write_tail(&mut self, rest: ExprRef, tasks: &mut Vec<Task>) {
+        match rest.kind {
+            ExprKind::Nil => {}
+            // A tail which is labelled is written as a value of its own,
+            // since other parts of the list may refer to it.
+            ExprKind::Pair(ref pair) if !self.labels.contains_key(&address(&rest)) => {
+                self.out.push(' ');
+                tasks.push(Task::Tail(pair.cdr.borrow().clone()));
+                tasks.push(Task::Value(pair.car.borrow().clone()));
+            }
+            _ => {
+                self.out.push_str(" . ");
+                tasks.push(Task::Value(rest.clone()));
+            }
+        }
+    }
+
+    fn write_string(&mut self, s: &str) {
+        self.out.push('"');
+        for c in s.chars() {
+            match c {
+                '"' => self.out.push_str("\\\""),
+                '\\' => self.out.push_str("\\\\"),
+                '\n' => self.out.push_str("\\n"),
+                '\t' => self.out.push_str("\\t"),
+                '\r' => self.out.push_str("\\r"),
+                _ => self.out.push(c),
+            }
+        }
+        self.out.push('"');
+    }
+
+    fn write_char(&mut self, c: char) {
+        match c {
+            ' ' => self.out.push_str("#\\space"),
+            '\n' => self.out.push_str("#\\newline"),
+            '\t' => self.out.push_str("#\\tab"),
+            _ => {
+                let _ = write!(self.out, "#\\{}", c);
+            }
+        }
+    }
+}
+
+/// Push values to be written one after another, with spaces between them.
+fn push_separated(tasks: &mut Vec<Task>, values: Vec<ExprRef>) {
+    for (i, value) in values.into_iter().enumerate().rev() {
+        tasks.push(Task::Value(value));
+        if i > 0 {
+            tasks.push(Task::Text(" "));
+        }
+    }
 }
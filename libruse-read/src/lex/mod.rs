@@ -246,5 +246,28 @@ mod tests {
         ]);
         assert_eq!(result, expected);
     }
-}
 
+    #[test]
+    fn lex_a_vector_with_an_ellipsis() {
+        let result = lex("#(a ...)");
+        let expected = Ok(vec![
+            Token::open_vector(Location::new(1, 1), Location::new(1, 3)),
+            Token::symbol("a", Location::new(1, 3)),
+            Token::symbol("...", Location::new(1, 5)),
+            Token::close_paren(Location::new(1, 8), Location::new(1, 9)),
+        ]);
+        assert_eq!(result, expected);
+    }
+
+    #[test]
+    fn lex_a_quoted_list() {
+        let result = lex("'(a)");
+        let expected = Ok(vec![
+            Token::quote(Location::new(1, 1), Location::new(1, 2)),
+            Token::open_paren(Location::new(1, 2), Location::new(1, 3)),
+            Token::symbol("a", Location::new(1, 3)),
+            Token::close_paren(Location::new(1, 4), Location::new(1, 5)),
+        ]);
+        assert_eq!(result, expected);
+    }
+}
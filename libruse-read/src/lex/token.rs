@@ -4,7 +4,9 @@ use std::convert::Into;
 #[derive(PartialEq, Clone)]
 pub enum TokenKind {
     OpenDelim(Delim),
+    OpenVector,
     CloseDelim(Delim),
+    Quote,
     Symbol(String),
     Integer(i64),
     Float(f64),
@@ -28,6 +30,7 @@ impl fmt::Debug for TokenKind {
                     Delim::Brace   => write!(f, "'{{'"),
                 }
             }
+            TokenKind::OpenVector => write!(f, "'#('"),
             TokenKind::CloseDelim(d) => {
                 match d {
                     Delim::Paren   => write!(f, "')'"),
@@ -35,6 +38,7 @@ impl fmt::Debug for TokenKind {
                     Delim::Brace   => write!(f, "'}}'"),
                 }
             }
+            TokenKind::Quote => write!(f, "'''"),
             TokenKind::Symbol(s) => {
                 write!(f, "'{}'", s.clone())
             }
@@ -77,7 +81,7 @@ pub enum Delim {
     Brace,
 }
 
-#[derive(PartialEq, Clone, Copy)]
+#[derive(PartialEq, Eq, Clone, Copy)]
 pub struct Location {
     pub line: usize,
     pub column: usize,
@@ -119,6 +123,25 @@ impl fmt::Display for Location {
     }
 }
 
+/// The stretch of source text an expression was read from.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct Span {
+    pub start: Location,
+    pub end: Location,
+}
+
+impl Span {
+    pub fn new(start: Location, end: Location) -> Span {
+        Span { start, end }
+    }
+}
+
+impl fmt::Display for Span {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.start)
+    }
+}
+
 #[derive(PartialEq)]
 pub struct Token {
     pub kind: TokenKind,
@@ -126,6 +149,12 @@ pub struct Token {
     pub end_location: Location,
 }
 
+impl Token {
+    pub fn span(&self) -> Span {
+        Span::new(self.start_location, self.end_location)
+    }
+}
+
 impl fmt::Debug for Token {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "<k:{:?}, s:{:?}, e:{:?}>", self.kind, self.start_location, self.end_location)
@@ -184,6 +213,8 @@ impl Token {
     delim_token!(close_bracket, TokenKind::CloseDelim(Delim::Bracket));
     delim_token!(open_brace, TokenKind::OpenDelim(Delim::Brace));
     delim_token!(close_brace, TokenKind::CloseDelim(Delim::Brace));
+    delim_token!(open_vector, TokenKind::OpenVector);
+    delim_token!(quote, TokenKind::Quote);
 
     stringy_token!(symbol, Symbol);
     stringy_token!(string, Str);
@@ -95,10 +95,14 @@ impl<'a> Iterator for TokenIterator<'a> {
                 ']' => return Some(lex_closed_bracket(self)),
                 '{' => return Some(lex_open_brace(self)),
                 '}' => return Some(lex_closed_brace(self)),
+                '\'' => return Some(lex_quote(self)),
+                '#' if self.char_iter.peek() == Some(&'(') => {
+                    return Some(lex_open_vector(self))
+                }
                 '#' => return Some(lex_boolean(self, character)),
                 '0'...'9' => return Some(lex_number(self, character)),
                 'a'...'z' | 'A'...'Z' | '!' | '$' | '%' | '&' | '*' | '/' | ':' | '<' | '=' |
-                    '>' | '?' | '^' | '_' | '~' | '+' | '-' => return Some(lex_symbol(self, character)),
+                    '>' | '?' | '^' | '_' | '~' | '+' | '-' | '.' => return Some(lex_symbol(self, character)),
                 '"' => return Some(lex_string(self, character)),
                 // Skip whitespace.
                 ' ' | '\t' | '\r' => self.step(EndOfLine::No, Move::No),
@@ -142,6 +146,17 @@ lex_delim!(lex_open_bracket, open_bracket, 1);
 lex_delim!(lex_closed_bracket, close_bracket, 1);
 lex_delim!(lex_open_brace, open_brace, 1);
 lex_delim!(lex_closed_brace, close_brace, 1);
+lex_delim!(lex_quote, quote, 1);
+
+fn lex_open_vector(iter: &mut TokenIterator) -> Result<Token, Error> {
+    let start_location = iter.get_location();
+
+    // Step over the '(' following the '#'.
+    iter.step(EndOfLine::No, Move::Yes);
+    iter.step(EndOfLine::No, Move::No);
+
+    Ok(Token::open_vector(start_location, iter.get_location()))
+}
 
 fn lex_number(iter: &mut TokenIterator, character: char) -> Result<Token, Error> {
     let mut result = vec![character];
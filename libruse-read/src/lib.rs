@@ -1,5 +1,7 @@
 #![feature(cell_update)]
 
+extern crate atomic_refcell;
+
 pub mod lex;
 pub mod parse;
 pub mod error;
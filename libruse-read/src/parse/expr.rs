@@ -1,49 +1,428 @@
+use atomic_refcell::AtomicRefCell;
+use lex::token::Span;
+use parse::heap::{self, Charge};
+pub use parse::symbol::Symbol;
+use std::any::{self, Any, TypeId};
 use std::fs::File;
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
 use std::default::Default;
+use std::fmt;
+use std::mem;
+use std::sync::atomic::AtomicBool;
+use std::sync::{Arc, Weak};
+
+/// A shared handle to an expression.
+///
+/// Pairs, closures and environments refer to other expressions through
+/// these, so a value can be reachable from more than one place.
+pub type ExprRef = Arc<Expr>;
 
 pub struct Expr {
     pub kind: ExprKind,
-    pub marked: bool,
+    /// Set by the collector on values it finds reachable.
+    pub marked: AtomicBool,
+    /// Cleared on the literal constants the reader finds in a program, which
+    /// the program isn't allowed to modify.
     pub mutable: bool,
+    pub span: Option<Span>,
+    /// What the value was charged by the heap it was made on.
+    pub charge: Option<Charge>,
 }
 
 impl Expr {
     pub fn new(kind: ExprKind) -> Expr {
-        let marked = false;
-        let mutable = false;
+        let marked = AtomicBool::new(false);
+        let mutable = true;
+        let span = None;
+        let charge = None;
+
+        Expr { kind, marked, mutable, span, charge }
+    }
+
+    pub fn with_span(kind: ExprKind, span: Span) -> Expr {
+        let mut expr = Expr::new(kind);
+        expr.span = Some(span);
+        expr
+    }
+
+    /// Move an expression onto the heap, where the collector can see it if
+    /// it holds references to other values.
+    pub fn alloc(mut self) -> ExprRef {
+        self.charge = heap::charge(self.footprint());
+        let expr = Arc::new(self);
+        if expr.has_references() {
+            heap::track_expr(&expr);
+        }
+        expr
+    }
+
+    /// Roughly how many bytes the value takes up on the heap, counting what
+    /// it holds itself but not the values it refers to.
+    pub fn footprint(&self) -> usize {
+        let reference = mem::size_of::<ExprRef>();
+        let held = match self.kind {
+            ExprKind::Str(ref s) => s.borrow().capacity(),
+            ExprKind::ByteVector(ref bytes) => bytes.borrow().capacity(),
+            ExprKind::Vector(ref items) => items.borrow().capacity() * reference,
+            ExprKind::Record(ref record) => record.values.borrow().capacity() * reference,
+            ExprKind::Values(ref values) => values.capacity() * reference,
+            _ => 0,
+        };
+        // The reference counts are kept alongside the value.
+        mem::size_of::<Expr>() + 2 * mem::size_of::<usize>() + held
+    }
+
+    /// Charge the value for `bytes` more than when it was made, as when a
+    /// table grows.
+    pub fn grow(&self, bytes: usize) {
+        if let Some(ref charge) = self.charge {
+            charge.grow(bytes);
+        }
+    }
+
+    /// Whether this kind of value can refer to others, and so be part of a
+    /// reference cycle. Ports count too, so they can be registered with a
+    /// guardian and closed once they're unreachable. Host objects only do if
+    /// their Rust value is traced: otherwise whatever it refers to is held
+    /// from outside the heap.
+    pub fn has_references(&self) -> bool {
+        match self.kind {
+            ExprKind::Nil
+            | ExprKind::Unspecified
+            | ExprKind::Bool(..)
+            | ExprKind::Char(..)
+            | ExprKind::Num(..)
+            | ExprKind::Symbol(..)
+            | ExprKind::Str(..)
+            | ExprKind::ByteVector(..)
+            | ExprKind::Continuation(..)
+            | ExprKind::RecordType(..)
+            | ExprKind::WeakBox(..) => false,
+            ExprKind::Host(ref object) => object.trace.is_some(),
+            _ => true,
+        }
+    }
+
+    pub fn nil() -> ExprRef {
+        Expr::new(ExprKind::Nil).alloc()
+    }
+
+    pub fn unspecified() -> ExprRef {
+        Expr::new(ExprKind::Unspecified).alloc()
+    }
+
+    pub fn boolean(b: bool) -> ExprRef {
+        Expr::new(ExprKind::Bool(b)).alloc()
+    }
+
+    pub fn integer(i: i64) -> ExprRef {
+        let value = Number {
+            kind: NumberKind::Int(i),
+            exact: true,
+        };
+        Expr::new(ExprKind::Num(value)).alloc()
+    }
+
+    pub fn real(f: f64) -> ExprRef {
+        let value = Number {
+            kind: NumberKind::Real(f),
+            exact: false,
+        };
+        Expr::new(ExprKind::Num(value)).alloc()
+    }
+
+    pub fn character(c: char) -> ExprRef {
+        Expr::new(ExprKind::Char(c)).alloc()
+    }
+
+    pub fn symbol<S: Into<Symbol>>(s: S) -> ExprRef {
+        Expr::new(ExprKind::Symbol(s.into())).alloc()
+    }
+
+    pub fn string<S: Into<String>>(s: S) -> ExprRef {
+        Expr::new(ExprKind::Str(AtomicRefCell::new(s.into()))).alloc()
+    }
+
+    pub fn bytevector(bytes: Vec<u8>) -> ExprRef {
+        Expr::new(ExprKind::ByteVector(AtomicRefCell::new(bytes))).alloc()
+    }
+
+    pub fn cons(car: ExprRef, cdr: ExprRef) -> ExprRef {
+        let pair = Pair {
+            car: AtomicRefCell::new(car),
+            cdr: AtomicRefCell::new(cdr),
+        };
+        Expr::new(ExprKind::Pair(pair)).alloc()
+    }
 
-        Expr { kind, marked, mutable }
+    pub fn vector(items: Vec<ExprRef>) -> ExprRef {
+        Expr::new(ExprKind::Vector(AtomicRefCell::new(items))).alloc()
+    }
+
+    /// Build a proper list out of the given items.
+    pub fn list(items: Vec<ExprRef>) -> ExprRef {
+        items
+            .into_iter()
+            .rev()
+            .fold(Expr::nil(), |tail, item| Expr::cons(item, tail))
+    }
+
+    /// Everything except `#f` counts as true.
+    pub fn is_true(&self) -> bool {
+        match self.kind {
+            ExprKind::Bool(false) => false,
+            _ => true,
+        }
+    }
+
+    pub fn is_nil(&self) -> bool {
+        match self.kind {
+            ExprKind::Nil => true,
+            _ => false,
+        }
+    }
+
+    pub fn is_procedure(&self) -> bool {
+        match self.kind {
+            ExprKind::Closure(..)
+            | ExprKind::CaseLambda(..)
+            | ExprKind::Primitive(..)
+            | ExprKind::Continuation(..)
+            | ExprKind::Parameter(..)
+            | ExprKind::Guardian(..) => true,
+            _ => false,
+        }
+    }
+
+    pub fn as_symbol(&self) -> Option<Symbol> {
+        match self.kind {
+            ExprKind::Symbol(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn as_record(&self) -> Option<&Record> {
+        match self.kind {
+            ExprKind::Record(ref record) => Some(record),
+            _ => None,
+        }
+    }
+
+    pub fn car(&self) -> Option<ExprRef> {
+        match self.kind {
+            ExprKind::Pair(ref pair) => Some(pair.car.borrow().clone()),
+            _ => None,
+        }
+    }
+
+    pub fn cdr(&self) -> Option<ExprRef> {
+        match self.kind {
+            ExprKind::Pair(ref pair) => Some(pair.cdr.borrow().clone()),
+            _ => None,
+        }
+    }
+
+    /// Collect the items of a proper list, or `None` if this isn't one.
+    /// Lists whose tails lead back into themselves aren't proper either.
+    pub fn to_vec(&self) -> Option<Vec<ExprRef>> {
+        let mut items = Vec::new();
+        // `behind` follows the list at half speed, so if it's circular,
+        // `next` laps it sooner or later. `None` stands for this pair.
+        let mut next: Option<ExprRef> = None;
+        let mut behind: Option<ExprRef> = None;
+        loop {
+            let rest = match next.as_ref().map_or(&self.kind, |next| &next.kind) {
+                ExprKind::Nil => return Some(items),
+                ExprKind::Pair(ref pair) => {
+                    items.push(pair.car.borrow().clone());
+                    pair.cdr.borrow().clone()
+                }
+                _ => return None,
+            };
+            if items.len() % 2 == 0 {
+                behind = behind.as_ref().map_or(self, |behind| &**behind).cdr();
+            }
+            let behind_ptr = behind.as_ref().map_or(self as *const Expr, |behind| &**behind);
+            if &*rest as *const Expr == behind_ptr {
+                return None;
+            }
+            next = Some(rest);
+        }
+    }
+
+    /// Move out the values this one holds which nothing else does, so they
+    /// can be freed one at a time rather than each freeing the next.
+    pub(crate) fn take_children(&mut self, taken: &mut Vec<ExprRef>) {
+        let mut take = |slot: &mut ExprRef| {
+            if Arc::strong_count(slot) == 1 && slot.has_references() {
+                taken.push(mem::replace(slot, Expr::nil()));
+            }
+        };
+        match self.kind {
+            ExprKind::Pair(ref mut pair) => {
+                take(pair.car.get_mut());
+                take(pair.cdr.get_mut());
+            }
+            ExprKind::Vector(ref mut items) => items.get_mut().iter_mut().for_each(take),
+            ExprKind::Record(ref mut record) => record.values.get_mut().iter_mut().for_each(take),
+            ExprKind::Values(ref mut values) => values.iter_mut().for_each(take),
+            _ => {}
+        }
+    }
+}
+
+impl Drop for Expr {
+    /// Free long lists, and other deeply nested values, without using up the
+    /// stack. Left to itself, freeing a pair frees its cdr, which frees its
+    /// cdr, and so on down the list.
+    fn drop(&mut self) {
+        let mut taken = Vec::new();
+        self.take_children(&mut taken);
+        while let Some(child) = taken.pop() {
+            if let Ok(mut child) = Arc::try_unwrap(child) {
+                child.take_children(&mut taken);
+            }
+        }
     }
 }
 
 pub enum ExprKind {
+    Nil,
+    Unspecified,
     Bool(bool),
     Char(char),
     Num(Number),
     Pair(Pair),
     Closure(Closure),
+    Primitive(Primitive),
     Syntax(Syntax),
     Symbol(Symbol),
-    Str(String),
+    Identifier(Identifier),
+    Str(AtomicRefCell<String>),
     Vector(Vector),
     ByteVector(ByteVector),
     Continuation(Continuation),
+    Condition(Condition),
     Port(Port),
-    Env(Env),
+    Env(Arc<Env>),
+    RecordType(Arc<RecordType>),
+    Record(Record),
+    /// Several values returned at once. A single value is never wrapped.
+    Values(Vec<ExprRef>),
+    Promise(Promise),
+    Parameter(Parameter),
+    CaseLambda(CaseLambda),
+    WeakBox(WeakBox),
+    Ephemeron(Ephemeron),
+    HashTable(HashTable),
+    Guardian(Guardian),
+    Host(HostObject),
 }
 
-#[allow(dead_code)]
 pub struct Env {
-    symbols: HashMap<String, Expr>,
+    pub(crate) symbols: AtomicRefCell<HashMap<Symbol, ExprRef>>,
+    pub(crate) parent: Option<Arc<Env>>,
+    /// Set by the collector on environments it finds reachable.
+    pub marked: AtomicBool,
+    /// What the environment was charged by the heap it was made on.
+    charge: Option<Charge>,
 }
 
-impl Default for Env {
-    fn default() -> Env {
+/// Roughly how many bytes each binding in an environment takes up.
+const BINDING_SIZE: usize = 2 * mem::size_of::<(Symbol, ExprRef)>();
+
+impl Env {
+    pub fn new(parent: Option<Arc<Env>>) -> Env {
         Env {
-            symbols: HashMap::new(),
+            symbols: AtomicRefCell::new(HashMap::new()),
+            parent,
+            marked: AtomicBool::new(false),
+            charge: None,
         }
     }
+
+    /// Move an environment onto the heap, where the collector can see it.
+    pub fn alloc(mut self) -> Arc<Env> {
+        let bytes = mem::size_of::<Env>() + 2 * mem::size_of::<usize>();
+        self.charge = heap::charge(bytes + self.symbols.borrow().len() * BINDING_SIZE);
+        let env = Arc::new(self);
+        heap::track_env(&env);
+        env
+    }
+
+    /// Make a new environment inside `parent`.
+    pub fn child(parent: &Arc<Env>) -> Arc<Env> {
+        Env::new(Some(parent.clone())).alloc()
+    }
+
+    /// The environment this one was made inside, if any.
+    pub fn parent(&self) -> Option<&Arc<Env>> {
+        self.parent.as_ref()
+    }
+
+    /// The bindings made in this environment, but not its parents.
+    pub fn bindings(&self) -> Vec<(Symbol, ExprRef)> {
+        let symbols = self.symbols.borrow();
+        symbols.iter().map(|(&name, value)| (name, value.clone())).collect()
+    }
+
+    /// Find the value bound to `name` in this environment or its parents.
+    pub fn lookup<S: Into<Symbol>>(&self, name: S) -> Option<ExprRef> {
+        let name = name.into();
+        if let Some(value) = self.symbols.borrow().get(&name) {
+            return Some(value.clone());
+        }
+
+        let mut env = self.parent.clone();
+        while let Some(e) = env {
+            if let Some(value) = e.symbols.borrow().get(&name) {
+                return Some(value.clone());
+            }
+            env = e.parent.clone();
+        }
+
+        None
+    }
+
+    /// Bind `name` in this environment, shadowing any outer binding.
+    pub fn define<S: Into<Symbol>>(&self, name: S, value: ExprRef) {
+        let old = self.symbols.borrow_mut().insert(name.into(), value);
+        match old {
+            Some(old) => heap::write_barrier(&old),
+            None => {
+                if let Some(ref charge) = self.charge {
+                    charge.grow(BINDING_SIZE);
+                }
+            }
+        }
+    }
+
+    /// Drop the bindings in this environment, but not its parents, whose
+    /// values `keep` turns down.
+    pub fn retain<F: FnMut(&ExprRef) -> bool>(&self, mut keep: F) {
+        self.symbols.borrow_mut().retain(|_, value| keep(value));
+    }
+
+    /// Update the innermost existing binding of `name`. Returns `false` if
+    /// there is no such binding.
+    pub fn set<S: Into<Symbol>>(&self, name: S, value: ExprRef) -> bool {
+        let name = name.into();
+        if let Some(slot) = self.symbols.borrow_mut().get_mut(&name) {
+            heap::write_barrier(&mem::replace(slot, value));
+            return true;
+        }
+
+        match self.parent {
+            Some(ref parent) => parent.set(name, value),
+            None => false,
+        }
+    }
+}
+
+impl Default for Env {
+    fn default() -> Env {
+        Env::new(None)
+    }
 }
 
 #[derive(Debug, PartialEq)]
@@ -63,35 +442,461 @@ pub enum NumberKind {
 }
 
 pub struct Pair {
-    pub car: Box<Expr>,
-    pub cdr: Box<Expr>,
+    pub car: AtomicRefCell<ExprRef>,
+    pub cdr: AtomicRefCell<ExprRef>,
 }
 
 pub struct Closure {
-    pub lambda: Box<Lambda>,
-    pub env: Env,
+    pub env: Arc<Env>,
     pub syntactic: bool,
-    pub body: Box<Expr>,
-    pub args: Box<Expr>,
+    pub body: ExprRef,
+    pub args: ExprRef,
+    pub arity: Arity,
+    /// The name the closure was defined with, for error messages.
+    pub name: AtomicRefCell<Option<Symbol>>,
+}
+
+/// A procedure made by `case-lambda`. Calling it runs the first of its
+/// clauses, each a closure, which takes that many arguments.
+pub struct CaseLambda {
+    pub clauses: Vec<ExprRef>,
+    pub name: AtomicRefCell<Option<Symbol>>,
+}
+
+/// How many arguments a closure takes: some required ones, and maybe any
+/// number more collected into a list.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct Arity {
+    pub required: usize,
+    pub rest: bool,
+}
+
+impl Arity {
+    /// The arity of a parameter list, which may be proper, dotted or a
+    /// lone name.
+    pub fn of(params: &ExprRef) -> Arity {
+        let mut required = 0;
+        let mut next = params.clone();
+        while let Some(cdr) = next.cdr() {
+            required += 1;
+            next = cdr;
+        }
+        Arity { required, rest: !next.is_nil() }
+    }
+
+    pub fn accepts(&self, count: usize) -> bool {
+        count == self.required || self.rest && count > self.required
+    }
+}
+
+impl fmt::Display for Arity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.rest {
+            write!(f, "at least {}", self.required)
+        } else {
+            write!(f, "{}", self.required)
+        }
+    }
 }
 
-pub type Lambda = Fn(Box<Expr>, Box<Env>) -> Box<Expr>;
-pub type Symbol = String;
-pub type Vector = Vec<Box<Expr>>;
-pub type ByteVector = Vec<u8>;
+/// A procedure implemented in Rust.
+///
+/// The calling convention belongs to the evaluator, so the implementation
+/// is stored opaquely here and recovered by the evaluator when called.
+pub struct Primitive {
+    pub name: String,
+    pub func: Arc<Any + Send + Sync>,
+}
+
+pub type Vector = AtomicRefCell<Vec<ExprRef>>;
+pub type ByteVector = AtomicRefCell<Vec<u8>>;
 
+/// A macro: its transformer, and the environment it was defined in.
 pub struct Syntax {
-    pub transformer: Box<Expr>,
-    pub env: Box<Env>,
+    pub transformer: ExprRef,
+    pub env: Arc<Env>,
+}
+
+/// A symbol inserted into a program by a macro expansion.
+///
+/// It means whatever `name` means in `env`, the environment the macro was
+/// defined in. If the expansion binds it instead, the binding is made under
+/// `alias`, which no other identifier shares, so it can't capture or be
+/// captured by the code around the macro use.
+pub struct Identifier {
+    pub name: ExprRef,
+    pub env: Arc<Env>,
+    pub alias: Symbol,
 }
 
-// TODO: Implement continuations.
+/// A continuation captured by `call/cc`. Continuations can only escape, so
+/// this just names the `call/cc` to return from.
 #[derive(Debug, PartialEq)]
-pub struct Continuation {}
+pub struct Continuation {
+    pub id: usize,
+}
+
+/// A parameter object, as made by `make-parameter`.
+pub struct Parameter {
+    pub value: AtomicRefCell<ExprRef>,
+    /// Applied to every value the parameter is given.
+    pub converter: Option<ExprRef>,
+}
+
+/// A reference which doesn't keep its value alive.
+///
+/// Immediate values, like numbers, characters and symbols, are compared by
+/// what they are rather than where they live, so another copy of one is as
+/// good as the original, and there's nothing to collect. Those are simply
+/// held on to. Everything else, strings and bytevectors included, is held
+/// weakly.
+pub enum Held {
+    Strong(ExprRef),
+    Weak(Weak<Expr>),
+}
+
+impl Held {
+    pub fn new(value: &ExprRef) -> Held {
+        match value.kind {
+            ExprKind::Nil
+            | ExprKind::Unspecified
+            | ExprKind::Bool(..)
+            | ExprKind::Char(..)
+            | ExprKind::Num(..)
+            | ExprKind::Symbol(..) => Held::Strong(value.clone()),
+            _ => Held::Weak(Arc::downgrade(value)),
+        }
+    }
+
+    /// A reference to a value which has already been collected.
+    pub fn broken() -> Held {
+        Held::Weak(Weak::new())
+    }
+
+    /// The value, unless it's been collected.
+    pub fn get(&self) -> Option<ExprRef> {
+        match *self {
+            Held::Strong(ref value) => Some(value.clone()),
+            Held::Weak(ref value) => {
+                let value = value.upgrade()?;
+                heap::read_barrier(&value);
+                Some(value)
+            }
+        }
+    }
+
+    /// Whether the value has been collected.
+    pub fn is_broken(&self) -> bool {
+        match *self {
+            Held::Strong(..) => false,
+            Held::Weak(ref value) => value.upgrade().is_none(),
+        }
+    }
+
+    /// Whether this refers to `value` itself.
+    pub fn is(&self, value: &ExprRef) -> bool {
+        match *self {
+            Held::Strong(ref held) => Arc::ptr_eq(held, value),
+            Held::Weak(ref held) => held.as_ptr() == &**value as *const Expr,
+        }
+    }
+}
+
+/// A weak box, as made by `make-weak-box`.
+pub struct WeakBox {
+    pub value: Held,
+}
+
+/// An ephemeron, as made by `make-ephemeron`.
+///
+/// The datum is only kept alive for as long as the key is, even if it
+/// refers to the key itself. Once the key is collected the ephemeron is
+/// broken, and lets go of both.
+pub struct Ephemeron {
+    pub key: AtomicRefCell<Held>,
+    pub datum: AtomicRefCell<ExprRef>,
+}
+
+impl Ephemeron {
+    pub fn new(key: &ExprRef, datum: ExprRef) -> Ephemeron {
+        Ephemeron {
+            key: AtomicRefCell::new(Held::new(key)),
+            datum: AtomicRefCell::new(datum),
+        }
+    }
+
+    pub fn is_broken(&self) -> bool {
+        self.key.borrow().is_broken()
+    }
+
+    /// Let go of the key and datum.
+    pub fn sever(&self) {
+        *self.key.borrow_mut() = Held::broken();
+        *self.datum.borrow_mut() = Expr::nil();
+    }
+}
+
+/// A Rust value handed to Ruse. Scripts can pass it around, but can only
+/// look inside it through the procedures registered for its type.
+///
+/// The collector only looks inside values made with `traced`, and only
+/// while no handle to the value is held outside the heap. Any values the
+/// Rust value holds otherwise are held from outside the heap, so they're
+/// kept alive for as long as it is.
+#[derive(Clone)]
+pub struct HostObject {
+    /// The name of the Rust type, without its path.
+    pub type_name: &'static str,
+    pub type_id: TypeId,
+    pub value: Arc<AtomicRefCell<Any + Send + Sync>>,
+    /// Shows the collector the values the Rust value holds, if its type can.
+    pub trace: Option<fn(&(Any + Send + Sync), &mut FnMut(&ExprRef))>,
+}
+
+impl HostObject {
+    pub fn new<T: Any + Send + Sync>(value: T) -> HostObject {
+        HostObject {
+            type_name: short_type_name::<T>(),
+            type_id: TypeId::of::<T>(),
+            value: Arc::new(AtomicRefCell::new(value)),
+            trace: None,
+        }
+    }
+
+    /// Wrap a value which holds Ruse values, for the collector to trace.
+    pub fn traced<T: Any + Send + Sync + Trace>(value: T) -> HostObject {
+        HostObject {
+            trace: Some(trace_as::<T>),
+            ..HostObject::new(value)
+        }
+    }
+
+    /// Whether the Rust value is a `T`.
+    pub fn is<T: Any>(&self) -> bool {
+        self.type_id == TypeId::of::<T>()
+    }
+}
+
+/// A Rust value which holds Ruse values, and shows them to the collector so
+/// cycles running through it can be freed.
+///
+/// The collector can't take the values back out, so it only breaks up a
+/// cycle which also runs through a Ruse value, like a list or a vector. A
+/// cycle made of host objects alone is kept.
+pub trait Trace {
+    /// Call `f` with every Ruse value this one holds.
+    fn trace(&self, f: &mut FnMut(&ExprRef));
+}
+
+fn trace_as<T: Any + Trace>(value: &(Any + Send + Sync), f: &mut FnMut(&ExprRef)) {
+    if let Some(value) = value.downcast_ref::<T>() {
+        value.trace(f);
+    }
+}
+
+/// The name of a Rust type without its path, so `std::fs::File` is just
+/// `File`. The paths of any type parameters are kept.
+pub fn short_type_name<T: ?Sized>() -> &'static str {
+    let name = any::type_name::<T>();
+    let path = &name[..name.find('<').unwrap_or(name.len())];
+    match path.rfind("::") {
+        Some(i) => &name[i + 2..],
+        None => name,
+    }
+}
+
+/// A guardian, as made by `make-guardian`.
+///
+/// Objects registered with a guardian aren't freed when nothing else can
+/// reach them. Instead the collector moves them, or the representatives
+/// they were registered with, to the guardian's queue, where the program
+/// can get them back to clean up after them. A guardian which can't be
+/// reached itself is freed along with everything registered with it.
+pub struct Guardian {
+    /// Each object registered, with its representative.
+    pub registered: AtomicRefCell<Vec<(ExprRef, ExprRef)>>,
+    pub queue: AtomicRefCell<VecDeque<ExprRef>>,
+}
+
+impl Guardian {
+    pub fn new() -> Guardian {
+        Guardian {
+            registered: AtomicRefCell::new(Vec::new()),
+            queue: AtomicRefCell::new(VecDeque::new()),
+        }
+    }
+}
+
+/// How a hash table holds on to its entries.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Weakness {
+    /// Keys and values are both kept alive.
+    Strong,
+    /// Values are kept alive, but keys aren't.
+    WeakKeys,
+    /// Each entry is an ephemeron: its value is only kept alive for as long
+    /// as its key is.
+    Ephemeral,
+}
+
+/// A hash table, as made by `make-hash-table` and friends.
+///
+/// Entries are kept in buckets by the hash of their key, which the table
+/// leaves its users to work out.
+pub struct HashTable {
+    pub weakness: Weakness,
+    pub buckets: AtomicRefCell<HashMap<u64, Vec<Entry>>>,
+}
+
+impl HashTable {
+    pub fn new(weakness: Weakness) -> HashTable {
+        HashTable {
+            weakness,
+            buckets: AtomicRefCell::new(HashMap::new()),
+        }
+    }
+}
+
+pub struct Entry {
+    pub key: Held,
+    pub value: ExprRef,
+}
+
+/// An error object, as created by `error` or raised by the runtime.
+pub struct Condition {
+    pub kind: ConditionKind,
+    pub message: String,
+    pub irritants: ExprRef,
+    pub span: Option<Span>,
+}
+
+/// A promise, as made by `delay`, `delay-force` or `make-promise`.
+///
+/// Forcing a promise made by `delay-force` hands its state over to the
+/// promise it delegates to, so the two share one state from then on and the
+/// links of a long chain can be let go as it's forced.
+pub struct Promise {
+    pub state: AtomicRefCell<Arc<AtomicRefCell<PromiseState>>>,
+}
+
+impl Promise {
+    pub fn new(state: PromiseState) -> Promise {
+        Promise {
+            state: AtomicRefCell::new(Arc::new(AtomicRefCell::new(state))),
+        }
+    }
+
+    /// The promise's current state.
+    pub fn get(&self) -> PromiseState {
+        self.state.borrow().borrow().clone()
+    }
+
+    pub fn set(&self, state: PromiseState) {
+        let shared = self.state.borrow();
+        let old = mem::replace(&mut *shared.borrow_mut(), state);
+        match old {
+            PromiseState::Done(ref value) => heap::write_barrier(value),
+            PromiseState::Delayed(ref expr, ref env) | PromiseState::DelayedForce(ref expr, ref env) => {
+                heap::write_barrier(expr);
+                heap::write_barrier_env(env);
+            }
+        }
+    }
+
+    pub fn is_done(&self) -> bool {
+        match *self.state.borrow().borrow() {
+            PromiseState::Done(..) => true,
+            _ => false,
+        }
+    }
+
+    /// Take over the state of `other`, which shares this promise's state
+    /// from then on.
+    pub fn adopt(&self, other: &Promise) {
+        self.set(other.get());
+        *other.state.borrow_mut() = self.state.borrow().clone();
+    }
+}
+
+#[derive(Clone)]
+pub enum PromiseState {
+    /// Forced, with the value it gave.
+    Done(ExprRef),
+    /// Waiting to evaluate an expression for its value.
+    Delayed(ExprRef, Arc<Env>),
+    /// Waiting to evaluate an expression giving another promise to force.
+    DelayedForce(ExprRef, Arc<Env>),
+}
+
+/// A record type, as made by `define-record-type`.
+pub struct RecordType {
+    pub name: Symbol,
+    pub fields: Vec<Symbol>,
+}
+
+impl RecordType {
+    /// The type's name without the angle brackets it's usually written
+    /// with, so `<point>` is just `point`.
+    pub fn short_name(&self) -> &str {
+        let name = self.name.name();
+        if name.len() > 2 && name.starts_with('<') && name.ends_with('>') {
+            &name[1..name.len() - 1]
+        } else {
+            name
+        }
+    }
+
+    pub fn field_index<S: Into<Symbol>>(&self, field: S) -> Option<usize> {
+        let field = field.into();
+        self.fields.iter().position(|&f| f == field)
+    }
+}
+
+/// An instance of a record type, holding a value for each of its fields.
+pub struct Record {
+    pub record_type: Arc<RecordType>,
+    pub values: AtomicRefCell<Vec<ExprRef>>,
+}
+
+impl Record {
+    /// Get the value of the field with the given name.
+    pub fn get<S: Into<Symbol>>(&self, field: S) -> Option<ExprRef> {
+        let index = self.record_type.field_index(field)?;
+        Some(self.values.borrow()[index].clone())
+    }
+
+    /// Set the value of the field with the given name. Returns `false` if
+    /// the record has no such field.
+    pub fn set<S: Into<Symbol>>(&self, field: S, value: ExprRef) -> bool {
+        match self.record_type.field_index(field) {
+            Some(index) => {
+                let old = mem::replace(&mut self.values.borrow_mut()[index], value);
+                heap::write_barrier(&old);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// The record's fields and their values, in the order they were declared.
+    pub fn fields(&self) -> Vec<(Symbol, ExprRef)> {
+        let values = self.values.borrow();
+        self.record_type.fields.iter().cloned().zip(values.iter().cloned()).collect()
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum ConditionKind {
+    Error,
+    Read,
+    File,
+    Interrupt,
+}
 
 #[derive(Debug)]
 pub struct Port {
-    pub file: File,
+    /// The file the port reads or writes, until the port is closed.
+    pub file: AtomicRefCell<Option<File>>,
     pub string: String,
     pub kind: PortKind,
     pub writable: bool,
@@ -104,3 +909,14 @@ pub enum PortKind {
     Binary,
 }
 
+impl Port {
+    pub fn is_open(&self) -> bool {
+        self.file.borrow().is_some()
+    }
+
+    /// Close the port, closing the file it holds. Closing a port twice does
+    /// nothing.
+    pub fn close(&self) {
+        self.file.borrow_mut().take();
+    }
+}
@@ -0,0 +1,1292 @@
+//! The heap values live on, and the collector which reclaims them.
+//!
+//! Values are reference counted, so most are freed as soon as the last
+//! reference to them goes away. That can't free a cycle of values referring
+//! to each other, like a closure stored in the environment it closes over,
+//! or a list whose tail was `set-cdr!` back onto itself. So the heap keeps
+//! track of every value which can hold references, and a tracing collector
+//! finds the cycles which nothing can reach any more and breaks them apart.
+//!
+//! The collector doesn't need to be told what the roots are. It counts the
+//! references each tracked value gets from other tracked values, and any
+//! value with more references than that is held from outside the heap: by
+//! the engine's global environment, a frame of the evaluator's stack, or a
+//! host object which isn't traced. Those values are the roots, and
+//! everything they reach is marked and kept. Whatever is left unmarked is
+//! garbage, referred to only by other garbage, and has its references
+//! cleared so it gets freed.
+//!
+//! By default a collection runs from start to finish in one go, pausing the
+//! program for as long as it takes. In incremental mode it's done in
+//! slices instead, with the program running in between, and each slice
+//! tries to stop once a pause target has passed. Mutations which overwrite
+//! a reference then have to go through the write barrier, so the collector
+//! doesn't lose track of values moved around behind its back.
+//!
+//! Each engine has a heap of its own, which it makes the thread's current
+//! heap while it runs, so values are tracked by the heap of the engine that
+//! made them. Values made outside of any engine, like the arguments a host
+//! builds for a procedure, go on a heap belonging to the thread.
+//!
+//! Every value made on a heap is charged roughly how many bytes it takes up,
+//! and gives them back when it's freed, so the heap knows how much memory
+//! its values are using whichever of them are tracked.
+
+use parse::expr::{Env, Expr, ExprKind, ExprRef, Held, PromiseState, Weakness};
+use std::cell::RefCell;
+use std::collections::{HashMap, VecDeque};
+use std::mem;
+use std::ops;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Weak};
+use std::time::{Duration, Instant};
+
+/// How many values are tracked before the first collection.
+const INITIAL_THRESHOLD: usize = 10_000;
+
+/// How many values are tracked between the slices of an incremental
+/// collection.
+const SLICE_INTERVAL: usize = 1_000;
+
+/// How many steps a slice of an incremental collection takes, unless it
+/// runs out of time first. Each value is looked at a handful of times, so
+/// this keeps collections well ahead of the values being tracked.
+const SLICE_STEPS: usize = 8 * SLICE_INTERVAL;
+
+/// How many steps a slice takes however short the pause target, so the
+/// collection still gets somewhere.
+const MIN_SLICE_STEPS: usize = SLICE_INTERVAL / 4;
+
+/// How many steps a slice takes between checks of the clock.
+const CHECK_INTERVAL: usize = 64;
+
+/// How many of the most recent pauses are remembered.
+const PAUSE_LOG: usize = 1_024;
+
+/// How many maps the index of a collection is split between.
+const INDEX_SHARDS: usize = 256;
+
+/// How many items each chunk of a `Chunks` holds.
+const CHUNK: usize = 4_096;
+
+thread_local! {
+    static HEAP: RefCell<Heap> = RefCell::new(Heap::new());
+}
+
+/// How the collector runs.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Mode {
+    /// Each collection runs from start to finish in one pause.
+    Full,
+    /// Collections are split into slices, run as the program allocates,
+    /// which each try to stop within the given pause target.
+    Incremental(Duration),
+}
+
+impl Default for Mode {
+    fn default() -> Mode {
+        Mode::Full
+    }
+}
+
+/// Statistics about the heap and the work the collector has done.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Stats {
+    /// Roughly how many bytes the values made on the heap take up, counting
+    /// those which can't hold references as well as those it tracks.
+    pub bytes: usize,
+    /// How many values are being tracked. Some may have been freed since
+    /// the last collection, and are only counted until the next.
+    pub tracked: usize,
+    /// How many values were still live after the last collection.
+    pub live: usize,
+    /// How many values have been tracked in all.
+    pub allocated: u64,
+    /// How many collections have run.
+    pub collections: u64,
+    /// How many values collections have found unreachable and freed.
+    pub reclaimed: u64,
+    /// How many times the collector has paused the program: once for each
+    /// full collection, or for each slice of an incremental one.
+    pub pauses: u64,
+    /// How long the last pause took.
+    pub last_pause: Duration,
+    /// How long the longest pause took.
+    pub max_pause: Duration,
+    /// How long every pause took, together.
+    pub total_pause: Duration,
+}
+
+/// A heap of values, and the collector which looks after it.
+pub struct Heap {
+    objects: Chunks<Object>,
+    /// How many bytes the values made on the heap have been charged.
+    meter: Arc<AtomicUsize>,
+    threshold: usize,
+    mode: Mode,
+    cycle: Option<Cycle>,
+    /// The last collection, kept so the next can reuse the room made for its
+    /// index rather than growing one again from nothing.
+    spare: Option<Cycle>,
+    since_slice: usize,
+    stats: Stats,
+    pauses: VecDeque<Duration>,
+}
+
+impl Heap {
+    /// Create an empty heap, collected in full.
+    pub fn new() -> Heap {
+        Heap {
+            objects: Chunks::new(),
+            meter: Arc::new(AtomicUsize::new(0)),
+            threshold: INITIAL_THRESHOLD,
+            mode: Mode::default(),
+            cycle: None,
+            spare: None,
+            since_slice: 0,
+            stats: Stats::default(),
+            pauses: VecDeque::new(),
+        }
+    }
+
+    /// The heap's statistics so far.
+    pub fn stats(&self) -> Stats {
+        let mut stats = self.stats;
+        stats.bytes = self.meter.load(Ordering::Relaxed);
+        stats.tracked = self.objects.len();
+        stats
+    }
+
+    /// How long the most recent collection pauses took, oldest first.
+    pub fn recent_pauses(&self) -> Vec<Duration> {
+        self.pauses.iter().cloned().collect()
+    }
+
+    /// How the collector is running.
+    pub fn mode(&self) -> Mode {
+        self.mode
+    }
+}
+
+impl Default for Heap {
+    fn default() -> Heap {
+        Heap::new()
+    }
+}
+
+/// Make `heap` the thread's current heap, until the `Entered` given back is
+/// left or dropped. The heap which was current before is put back then.
+pub fn enter(heap: Heap) -> Entered {
+    let outer = HEAP.with(|current| mem::replace(&mut *current.borrow_mut(), heap));
+    Entered { outer: Some(outer) }
+}
+
+/// A heap made current with `enter`.
+pub struct Entered {
+    outer: Option<Heap>,
+}
+
+impl Entered {
+    /// Put the heap which was current before back, giving back the one
+    /// which was entered.
+    pub fn leave(mut self) -> Heap {
+        let outer = self.outer.take().unwrap_or_default();
+        HEAP.with(|current| mem::replace(&mut *current.borrow_mut(), outer))
+    }
+}
+
+impl Drop for Entered {
+    /// Put the heap which was current before back if the entered one was
+    /// never left, as when a panic unwinds past it. The entered heap is
+    /// dropped, and the values it tracked go untracked.
+    fn drop(&mut self) {
+        if let Some(outer) = self.outer.take() {
+            HEAP.with(|current| *current.borrow_mut() = outer);
+        }
+    }
+}
+
+/// The bytes a value has been charged by the heap it was made on, which
+/// are given back when it's dropped.
+pub struct Charge {
+    meter: Arc<AtomicUsize>,
+    bytes: AtomicUsize,
+}
+
+impl Charge {
+    /// Charge the value for `bytes` more, as when a table it holds grows.
+    pub fn grow(&self, bytes: usize) {
+        self.bytes.fetch_add(bytes, Ordering::Relaxed);
+        self.meter.fetch_add(bytes, Ordering::Relaxed);
+    }
+}
+
+impl Drop for Charge {
+    fn drop(&mut self) {
+        self.meter.fetch_sub(self.bytes.load(Ordering::Relaxed), Ordering::Relaxed);
+    }
+}
+
+/// Charge a value being made for the `bytes` it takes up, on the current
+/// heap.
+pub fn charge(bytes: usize) -> Option<Charge> {
+    HEAP.with(|heap| {
+        let heap = heap.try_borrow().ok()?;
+        heap.meter.fetch_add(bytes, Ordering::Relaxed);
+        Some(Charge {
+            meter: heap.meter.clone(),
+            bytes: AtomicUsize::new(bytes),
+        })
+    })
+}
+
+/// Charge the current heap for `bytes` which are never given back, like the
+/// name of a symbol once it's interned.
+pub fn charge_for_good(bytes: usize) {
+    HEAP.with(|heap| {
+        if let Ok(heap) = heap.try_borrow() {
+            heap.meter.fetch_add(bytes, Ordering::Relaxed);
+        }
+    })
+}
+
+/// A collection in progress.
+///
+/// It first counts the references each value gets from the others, then
+/// marks the values held from outside the heap and everything they reach,
+/// then clears out whatever wasn't marked. In incremental mode the program
+/// runs between the steps, which is safe as long as it reports references
+/// it overwrites through the write barrier: values are only ever counted as
+/// more reachable than they are, except for references dropped after being
+/// counted, and those are marked when the barrier sees them.
+struct Cycle {
+    /// The values tracked when the collection started.
+    objects: Chunks<Object>,
+    /// Those of them which are still around.
+    nodes: Chunks<Node>,
+    index: Index,
+    /// How many references each value gets from the others.
+    internal: Chunks<usize>,
+    phase: Phase,
+    stack: Vec<usize>,
+    /// Values the write barrier has seen references to dropped.
+    greyed: Vec<usize>,
+    /// Ephemerons, and ephemeron tables, with keys that weren't marked when
+    /// they were traced.
+    waiting: Vec<usize>,
+    /// Guardians which have been marked.
+    guardians: Vec<usize>,
+    survivors: Chunks<Object>,
+    /// References swept values held, still to be let go of.
+    freeing: Vec<ExprRef>,
+    reclaimed: usize,
+}
+
+/// Where a collection is, with the index of the next value to look at.
+#[derive(Clone, Copy)]
+enum Phase {
+    Snapshot,
+    Count(usize),
+    Root(usize),
+    Mark,
+    Sweep,
+}
+
+/// How much longer a slice can go on for.
+struct Budget {
+    deadline: Option<Instant>,
+    steps: usize,
+    /// How many steps to take before the clock is next checked.
+    next_check: usize,
+}
+
+impl Budget {
+    fn new(deadline: Option<Instant>) -> Budget {
+        Budget {
+            deadline,
+            steps: 0,
+            next_check: MIN_SLICE_STEPS,
+        }
+    }
+
+    /// Count a step, giving back `true` if the slice should stop. Without a
+    /// deadline, it never does.
+    fn spent(&mut self) -> bool {
+        self.charge(1);
+        let deadline = match self.deadline {
+            Some(deadline) => deadline,
+            None => return false,
+        };
+        if self.steps >= SLICE_STEPS {
+            return true;
+        }
+        if self.steps < self.next_check {
+            return false;
+        }
+        self.next_check = self.steps + CHECK_INTERVAL;
+        Instant::now() >= deadline
+    }
+
+    /// Count the references looked at by a step, so a slice tracing a few
+    /// large values stops as soon as one tracing many small ones.
+    fn charge(&mut self, steps: usize) {
+        self.steps += steps;
+    }
+}
+
+/// Where each value a collection is looking at is in its `nodes`, by
+/// address.
+///
+/// A map moves everything in it when it grows, which for one holding every
+/// value on the heap takes longer than a slice should. So the index is split
+/// between many smaller maps, which each grow a little at a time.
+struct Index {
+    shards: Vec<HashMap<usize, usize>>,
+}
+
+impl Index {
+    fn new() -> Index {
+        Index {
+            shards: (0..INDEX_SHARDS).map(|_| HashMap::new()).collect(),
+        }
+    }
+
+    fn shard(address: usize) -> usize {
+        // Values are allocated at aligned addresses, so the low bits are
+        // mixed in with the rest before picking a shard.
+        ((address as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15) >> 32) as usize % INDEX_SHARDS
+    }
+
+    fn get(&self, address: &usize) -> Option<&usize> {
+        self.shards[Index::shard(*address)].get(address)
+    }
+
+    fn insert(&mut self, address: usize, i: usize) {
+        self.shards[Index::shard(address)].insert(address, i);
+    }
+
+    fn clear(&mut self) {
+        for shard in &mut self.shards {
+            shard.clear();
+        }
+    }
+}
+
+/// A list kept in chunks of a fixed size, rather than in one buffer.
+///
+/// Growing a buffer moves everything in it to a new one twice the size,
+/// which for a list of every value on the heap takes longer than a slice
+/// should, while a list of chunks only ever adds another. Every chunk but
+/// the last is kept full, so items can still be found by their index.
+struct Chunks<T> {
+    chunks: Vec<Vec<T>>,
+    len: usize,
+}
+
+impl<T> Chunks<T> {
+    fn new() -> Chunks<T> {
+        Chunks {
+            chunks: Vec::new(),
+            len: 0,
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.len
+    }
+
+    fn push(&mut self, item: T) {
+        let full = self.chunks.last().map_or(true, |chunk| chunk.len() == CHUNK);
+        if full {
+            self.chunks.push(Vec::with_capacity(CHUNK));
+        }
+        if let Some(chunk) = self.chunks.last_mut() {
+            chunk.push(item);
+        }
+        self.len += 1;
+    }
+
+    fn pop(&mut self) -> Option<T> {
+        let item = self.chunks.last_mut().and_then(|chunk| chunk.pop());
+        if item.is_some() {
+            self.len -= 1;
+        }
+        if self.chunks.last().map_or(false, |chunk| chunk.is_empty()) {
+            self.chunks.pop();
+        }
+        item
+    }
+
+    /// Move every item of `other` to the end of this list. Only what's in
+    /// this list's last chunk is moved item by item, to keep it full.
+    fn append(&mut self, other: &mut Chunks<T>) {
+        if self.chunks.last().map_or(false, |chunk| chunk.len() < CHUNK) {
+            while let Some(item) = self.pop() {
+                other.push(item);
+                if self.chunks.last().map_or(true, |chunk| chunk.len() == CHUNK) {
+                    break;
+                }
+            }
+        }
+        self.len += other.len;
+        self.chunks.append(&mut other.chunks);
+        other.len = 0;
+    }
+
+    fn clear(&mut self) {
+        self.chunks.clear();
+        self.len = 0;
+    }
+}
+
+impl<T> ops::Index<usize> for Chunks<T> {
+    type Output = T;
+
+    fn index(&self, i: usize) -> &T {
+        &self.chunks[i / CHUNK][i % CHUNK]
+    }
+}
+
+impl<T> ops::IndexMut<usize> for Chunks<T> {
+    fn index_mut(&mut self, i: usize) -> &mut T {
+        &mut self.chunks[i / CHUNK][i % CHUNK]
+    }
+}
+
+impl Cycle {
+    fn new() -> Cycle {
+        Cycle {
+            objects: Chunks::new(),
+            nodes: Chunks::new(),
+            index: Index::new(),
+            internal: Chunks::new(),
+            phase: Phase::Snapshot,
+            stack: Vec::new(),
+            greyed: Vec::new(),
+            waiting: Vec::new(),
+            guardians: Vec::new(),
+            survivors: Chunks::new(),
+            freeing: Vec::new(),
+            reclaimed: 0,
+        }
+    }
+
+    /// Get ready to start again, keeping the room already made.
+    fn reset(&mut self) {
+        self.objects.clear();
+        self.nodes.clear();
+        self.index.clear();
+        self.internal.clear();
+        self.phase = Phase::Snapshot;
+        self.stack.clear();
+        self.greyed.clear();
+        self.waiting.clear();
+        self.guardians.clear();
+        self.survivors.clear();
+        self.freeing.clear();
+        self.reclaimed = 0;
+    }
+
+    /// Hold onto the values to look at, so none of them can be freed while
+    /// the collection is in progress. Gives back `true` once all of them are.
+    ///
+    /// Each value is let go of by the heap as it's taken, since dropping the
+    /// last weak reference to a value frees what's left of it.
+    fn snapshot(&mut self, budget: &mut Budget) -> bool {
+        while let Some(object) = self.objects.pop() {
+            if let Some(node) = object.upgrade() {
+                self.index.insert(node.address(), self.nodes.len());
+                self.nodes.push(node);
+                self.internal.push(0);
+            }
+            drop(object);
+
+            if budget.spent() {
+                return false;
+            }
+        }
+
+        self.phase = Phase::Count(0);
+        true
+    }
+
+    /// Work until the collection is done, giving back `true`, or until the
+    /// deadline has passed.
+    fn run(&mut self, deadline: Option<Instant>) -> bool {
+        let mut budget = Budget::new(deadline);
+        if let Phase::Snapshot = self.phase {
+            if !self.snapshot(&mut budget) {
+                return false;
+            }
+        }
+
+        let Cycle {
+            ref mut nodes,
+            ref index,
+            ref mut internal,
+            ref mut phase,
+            ref mut stack,
+            ref mut greyed,
+            ref mut waiting,
+            ref mut guardians,
+            ref mut survivors,
+            ref mut freeing,
+            ref mut reclaimed,
+            ..
+        } = *self;
+        let count = nodes.len();
+
+        loop {
+            if budget.spent() {
+                return false;
+            }
+
+            *phase = match *phase {
+                Phase::Snapshot => Phase::Count(0),
+                Phase::Count(i) if i < count => {
+                    nodes[i].references(|address| {
+                        budget.charge(1);
+                        if let Some(&j) = index.get(&address) {
+                            internal[j] += 1;
+                        }
+                    });
+                    Phase::Count(i + 1)
+                }
+                Phase::Count(_) => Phase::Root(0),
+                Phase::Root(i) if i < count => {
+                    // Whatever holds the rest of the references is outside
+                    // the heap. `nodes` holds one more of its own.
+                    let outside = nodes[i].strong_count().saturating_sub(internal[i] + 1);
+                    if outside > 0 && nodes[i].mark() {
+                        stack.push(i);
+                    }
+                    Phase::Root(i + 1)
+                }
+                Phase::Root(_) => Phase::Mark,
+                // Whatever the write barrier has seen is marked first, one
+                // value a step, since the program may have greyed plenty.
+                Phase::Mark if !greyed.is_empty() => {
+                    if let Some(&j) = greyed.pop().and_then(|address| index.get(&address)) {
+                        if nodes[j].mark() {
+                            stack.push(j);
+                        }
+                    }
+                    Phase::Mark
+                }
+                Phase::Mark => {
+                    match stack.pop() {
+                        Some(i) => {
+                            if !trace(i, nodes, index, stack, &mut budget) {
+                                waiting.push(i);
+                            }
+                            if nodes[i].is_guardian() {
+                                guardians.push(i);
+                            }
+                            Phase::Mark
+                        }
+                        None => {
+                            // Ephemerons whose keys have been marked since
+                            // can be traced now, which may mark more keys.
+                            for i in mem::replace(waiting, Vec::new()) {
+                                if !trace(i, nodes, index, stack, &mut budget) {
+                                    waiting.push(i);
+                                }
+                            }
+                            // Whatever guardians hold that wasn't marked by
+                            // now is unreachable, and goes in their queues.
+                            // Objects registered more than once are queued
+                            // each time, so nothing is marked until they all
+                            // have been.
+                            if stack.is_empty() {
+                                let mut revived = Vec::new();
+                                for &i in guardians.iter() {
+                                    resurrect(&nodes[i], nodes, index, &mut revived);
+                                }
+                                for value in revived {
+                                    if let Some(&j) = index.get(&expr_address(&value)) {
+                                        if nodes[j].mark() {
+                                            stack.push(j);
+                                        }
+                                    }
+                                }
+                            }
+                            if stack.is_empty() {
+                                for i in waiting.drain(..) {
+                                    sever(&nodes[i], nodes, index);
+                                }
+                                Phase::Sweep
+                            } else {
+                                Phase::Mark
+                            }
+                        }
+                    }
+                }
+                // Values are let go of as they're swept, a few at a time, so
+                // freeing them is spread out over the slices too. Freeing one
+                // can free a long list of others, and those are only taken
+                // apart a step at a time as well.
+                Phase::Sweep => {
+                    if let Some(value) = freeing.pop() {
+                        release(value, freeing);
+                        continue;
+                    }
+                    let node = match nodes.pop() {
+                        Some(node) => node,
+                        None => return true,
+                    };
+                    if node.is_marked() {
+                        node.unmark();
+                        survivors.push(node.downgrade());
+                    } else {
+                        node.clear(freeing);
+                        if let Node::Expr(expr) = node {
+                            freeing.push(expr);
+                        }
+                        *reclaimed += 1;
+                    }
+                    Phase::Sweep
+                }
+            };
+        }
+    }
+}
+
+/// Let go of a reference a swept value held. If it was the last, whatever
+/// the value holds in turn is left for the steps to come.
+fn release(value: ExprRef, freeing: &mut Vec<ExprRef>) {
+    if let Ok(mut value) = Arc::try_unwrap(value) {
+        value.take_children(freeing);
+    }
+}
+
+/// Mark everything a value refers to, pushing whatever wasn't marked yet
+/// onto the stack. Gives back `false` if some of it is held by an ephemeron
+/// whose key isn't marked, and was left alone for now.
+fn trace(i: usize, nodes: &Chunks<Node>, index: &Index, stack: &mut Vec<usize>, budget: &mut Budget) -> bool {
+    let mut visit = |address| {
+        budget.charge(1);
+        if let Some(&j) = index.get(&address) {
+            if nodes[j].mark() {
+                stack.push(j);
+            }
+        }
+    };
+
+    if let Node::Expr(ref expr) = nodes[i] {
+        match expr.kind {
+            ExprKind::Ephemeron(ref ephemeron) => {
+                let marked = match ephemeron.key.try_borrow() {
+                    Ok(key) => is_marked(&key, nodes, index),
+                    Err(..) => true,
+                };
+                if !marked {
+                    return false;
+                }
+            }
+            // Registering an object with a guardian doesn't keep it alive,
+            // but its representative is.
+            ExprKind::Guardian(ref guardian) => {
+                if let Ok(registered) = guardian.registered.try_borrow() {
+                    for &(ref object, ref representative) in registered.iter() {
+                        if !Arc::ptr_eq(object, representative) {
+                            visit(expr_address(representative));
+                        }
+                    }
+                }
+                if let Ok(queue) = guardian.queue.try_borrow() {
+                    for value in queue.iter() {
+                        visit(expr_address(value));
+                    }
+                }
+                return true;
+            }
+            ExprKind::HashTable(ref table) if table.weakness == Weakness::Ephemeral => {
+                let mut done = true;
+                if let Ok(buckets) = table.buckets.try_borrow() {
+                    for entry in buckets.values().flat_map(|entries| entries.iter()) {
+                        if is_marked(&entry.key, nodes, index) {
+                            visit(expr_address(&entry.value));
+                        } else {
+                            done = false;
+                        }
+                    }
+                }
+                return done;
+            }
+            _ => {}
+        }
+    }
+
+    nodes[i].references(visit);
+    true
+}
+
+/// Move the objects registered with a guardian which haven't been marked
+/// to its queue, adding what it queues to `revived` to be marked and kept.
+fn resurrect(node: &Node, nodes: &Chunks<Node>, index: &Index, revived: &mut Vec<ExprRef>) {
+    let guardian = match *node {
+        Node::Expr(ref expr) => match expr.kind {
+            ExprKind::Guardian(ref guardian) => guardian,
+            _ => return,
+        },
+        Node::Env(..) => return,
+    };
+    let (mut registered, mut queue) = match (guardian.registered.try_borrow_mut(), guardian.queue.try_borrow_mut()) {
+        (Ok(registered), Ok(queue)) => (registered, queue),
+        _ => return,
+    };
+
+    let mut i = 0;
+    while i < registered.len() {
+        let marked = match index.get(&expr_address(&registered[i].0)) {
+            Some(&j) => nodes[j].is_marked(),
+            None => true,
+        };
+        if marked {
+            i += 1;
+            continue;
+        }
+
+        let (_, representative) = registered.swap_remove(i);
+        revived.push(representative.clone());
+        queue.push_back(representative);
+    }
+}
+
+/// Break an ephemeron, or drop the entries of an ephemeron table, whose key
+/// wasn't marked.
+fn sever(node: &Node, nodes: &Chunks<Node>, index: &Index) {
+    if let Node::Expr(ref expr) = *node {
+        match expr.kind {
+            ExprKind::Ephemeron(ref ephemeron) => {
+                let marked = match ephemeron.key.try_borrow() {
+                    Ok(key) => is_marked(&key, nodes, index),
+                    Err(..) => true,
+                };
+                if !marked {
+                    ephemeron.sever();
+                }
+            }
+            ExprKind::HashTable(ref table) => {
+                if let Ok(mut buckets) = table.buckets.try_borrow_mut() {
+                    for entries in buckets.values_mut() {
+                        entries.retain(|entry| is_marked(&entry.key, nodes, index));
+                    }
+                    buckets.retain(|_, entries| !entries.is_empty());
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Whether a weakly held value has been marked. Values tracked since the
+/// collection started haven't been looked at, and count as marked.
+fn is_marked(held: &Held, nodes: &Chunks<Node>, index: &Index) -> bool {
+    match *held {
+        Held::Strong(..) => true,
+        Held::Weak(ref value) => match value.upgrade() {
+            Some(value) => match index.get(&expr_address(&value)) {
+                Some(&j) => nodes[j].is_marked(),
+                None => true,
+            },
+            None => false,
+        },
+    }
+}
+
+/// A tracked value, which the heap mustn't keep alive itself.
+enum Object {
+    Expr(Weak<Expr>),
+    Env(Weak<Env>),
+}
+
+impl Object {
+    fn upgrade(&self) -> Option<Node> {
+        match *self {
+            Object::Expr(ref expr) => expr.upgrade().map(Node::Expr),
+            Object::Env(ref env) => env.upgrade().map(Node::Env),
+        }
+    }
+}
+
+/// A tracked value being looked at by a collection.
+enum Node {
+    Expr(ExprRef),
+    Env(Arc<Env>),
+}
+
+impl Node {
+    fn downgrade(&self) -> Object {
+        match *self {
+            Node::Expr(ref expr) => Object::Expr(Arc::downgrade(expr)),
+            Node::Env(ref env) => Object::Env(Arc::downgrade(env)),
+        }
+    }
+
+    fn address(&self) -> usize {
+        match *self {
+            Node::Expr(ref expr) => expr_address(expr),
+            Node::Env(ref env) => env_address(env),
+        }
+    }
+
+    fn strong_count(&self) -> usize {
+        match *self {
+            Node::Expr(ref expr) => Arc::strong_count(expr),
+            Node::Env(ref env) => Arc::strong_count(env),
+        }
+    }
+
+    /// Mark the value, returning `false` if it was already marked.
+    fn mark(&self) -> bool {
+        let marked = match *self {
+            Node::Expr(ref expr) => &expr.marked,
+            Node::Env(ref env) => &env.marked,
+        };
+        !marked.swap(true, Ordering::Relaxed)
+    }
+
+    fn is_marked(&self) -> bool {
+        match *self {
+            Node::Expr(ref expr) => expr.marked.load(Ordering::Relaxed),
+            Node::Env(ref env) => env.marked.load(Ordering::Relaxed),
+        }
+    }
+
+    fn is_guardian(&self) -> bool {
+        match *self {
+            Node::Expr(ref expr) => match expr.kind {
+                ExprKind::Guardian(..) => true,
+                _ => false,
+            },
+            Node::Env(..) => false,
+        }
+    }
+
+    fn unmark(&self) {
+        match *self {
+            Node::Expr(ref expr) => expr.marked.store(false, Ordering::Relaxed),
+            Node::Env(ref env) => env.marked.store(false, Ordering::Relaxed),
+        }
+    }
+
+    /// Call `f` with the address of every value this one holds a reference
+    /// to, once for each reference it holds.
+    fn references<F: FnMut(usize)>(&self, mut f: F) {
+        match *self {
+            Node::Env(ref env) => {
+                if let Ok(symbols) = env.symbols.try_borrow() {
+                    for value in symbols.values() {
+                        f(expr_address(value));
+                    }
+                }
+                if let Some(ref parent) = env.parent {
+                    f(env_address(parent));
+                }
+            }
+            Node::Expr(ref expr) => expr_references(expr, &mut f),
+        }
+    }
+
+    /// Take every reference the value holds that can be taken, which is
+    /// enough to break up any cycle it's part of, moving them to `freeing`
+    /// to be let go of.
+    fn clear(&self, freeing: &mut Vec<ExprRef>) {
+        match *self {
+            Node::Env(ref env) => {
+                if let Ok(mut symbols) = env.symbols.try_borrow_mut() {
+                    freeing.extend(symbols.drain().map(|(_, value)| value));
+                }
+            }
+            Node::Expr(ref expr) => match expr.kind {
+                ExprKind::Pair(ref pair) => {
+                    if let Ok(mut car) = pair.car.try_borrow_mut() {
+                        freeing.push(mem::replace(&mut *car, Expr::nil()));
+                    }
+                    if let Ok(mut cdr) = pair.cdr.try_borrow_mut() {
+                        freeing.push(mem::replace(&mut *cdr, Expr::nil()));
+                    }
+                }
+                ExprKind::Record(ref record) => {
+                    if let Ok(mut values) = record.values.try_borrow_mut() {
+                        freeing.extend(values.drain(..));
+                    }
+                }
+                ExprKind::Vector(ref items) => {
+                    if let Ok(mut items) = items.try_borrow_mut() {
+                        freeing.extend(items.drain(..));
+                    }
+                }
+                ExprKind::Promise(ref promise) => {
+                    if let Ok(state) = promise.state.try_borrow() {
+                        if let Ok(mut state) = state.try_borrow_mut() {
+                            match mem::replace(&mut *state, PromiseState::Done(Expr::nil())) {
+                                PromiseState::Done(value) => freeing.push(value),
+                                PromiseState::Delayed(expr, _) | PromiseState::DelayedForce(expr, _) => {
+                                    freeing.push(expr)
+                                }
+                            }
+                        }
+                    }
+                }
+                ExprKind::Parameter(ref parameter) => {
+                    if let Ok(mut value) = parameter.value.try_borrow_mut() {
+                        freeing.push(mem::replace(&mut *value, Expr::nil()));
+                    }
+                }
+                ExprKind::Ephemeron(ref ephemeron) => {
+                    if let Ok(mut datum) = ephemeron.datum.try_borrow_mut() {
+                        freeing.push(mem::replace(&mut *datum, Expr::nil()));
+                    }
+                }
+                ExprKind::HashTable(ref table) => {
+                    if let Ok(mut buckets) = table.buckets.try_borrow_mut() {
+                        for entry in buckets.drain().flat_map(|(_, entries)| entries) {
+                            if let Held::Strong(key) = entry.key {
+                                freeing.push(key);
+                            }
+                            freeing.push(entry.value);
+                        }
+                    }
+                }
+                ExprKind::Guardian(ref guardian) => {
+                    if let Ok(mut registered) = guardian.registered.try_borrow_mut() {
+                        for (object, representative) in registered.drain(..) {
+                            freeing.push(object);
+                            freeing.push(representative);
+                        }
+                    }
+                    if let Ok(mut queue) = guardian.queue.try_borrow_mut() {
+                        freeing.extend(queue.drain(..));
+                    }
+                }
+                _ => {}
+            },
+        }
+    }
+}
+
+fn expr_address(expr: &ExprRef) -> usize {
+    &**expr as *const Expr as usize
+}
+
+fn env_address(env: &Arc<Env>) -> usize {
+    &**env as *const Env as usize
+}
+
+// Values held in an `AtomicRefCell` are skipped if it's borrowed, which only
+// keeps whatever they refer to alive a little longer.
+fn expr_references<F: FnMut(usize)>(expr: &Expr, f: &mut F) {
+    match expr.kind {
+        ExprKind::Pair(ref pair) => {
+            if let Ok(car) = pair.car.try_borrow() {
+                f(expr_address(&car));
+            }
+            if let Ok(cdr) = pair.cdr.try_borrow() {
+                f(expr_address(&cdr));
+            }
+        }
+        ExprKind::Closure(ref closure) => {
+            f(env_address(&closure.env));
+            f(expr_address(&closure.body));
+            f(expr_address(&closure.args));
+        }
+        ExprKind::CaseLambda(ref case) => {
+            for clause in &case.clauses {
+                f(expr_address(clause));
+            }
+        }
+        ExprKind::Syntax(ref syntax) => {
+            f(expr_address(&syntax.transformer));
+            f(env_address(&syntax.env));
+        }
+        ExprKind::Identifier(ref id) => {
+            f(expr_address(&id.name));
+            f(env_address(&id.env));
+        }
+        ExprKind::Vector(ref items) => {
+            if let Ok(items) = items.try_borrow() {
+                for item in items.iter() {
+                    f(expr_address(item));
+                }
+            }
+        }
+        ExprKind::Values(ref items) => {
+            for item in items {
+                f(expr_address(item));
+            }
+        }
+        ExprKind::Condition(ref condition) => f(expr_address(&condition.irritants)),
+        ExprKind::Env(ref env) => f(env_address(env)),
+        ExprKind::Record(ref record) => {
+            if let Ok(values) = record.values.try_borrow() {
+                for value in values.iter() {
+                    f(expr_address(value));
+                }
+            }
+        }
+        ExprKind::Promise(ref promise) => {
+            // A promise's state may be shared with the other promises of a
+            // `delay-force` chain, and then it's left alone: whatever it
+            // refers to just looks like it's held from outside the heap.
+            if let Ok(state) = promise.state.try_borrow() {
+                if Arc::strong_count(&state) == 1 {
+                    if let Ok(state) = state.try_borrow() {
+                        match *state {
+                            PromiseState::Done(ref value) => f(expr_address(value)),
+                            PromiseState::Delayed(ref expr, ref env)
+                            | PromiseState::DelayedForce(ref expr, ref env) => {
+                                f(expr_address(expr));
+                                f(env_address(env));
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        ExprKind::Parameter(ref parameter) => {
+            if let Ok(value) = parameter.value.try_borrow() {
+                f(expr_address(&value));
+            }
+            if let Some(ref converter) = parameter.converter {
+                f(expr_address(converter));
+            }
+        }
+        // Weakly held values aren't references as far as the collector is
+        // concerned, and don't keep anything alive.
+        ExprKind::Ephemeron(ref ephemeron) => {
+            if let Ok(key) = ephemeron.key.try_borrow() {
+                if let Held::Strong(ref key) = *key {
+                    f(expr_address(key));
+                }
+            }
+            if let Ok(datum) = ephemeron.datum.try_borrow() {
+                f(expr_address(&datum));
+            }
+        }
+        ExprKind::HashTable(ref table) => {
+            if let Ok(buckets) = table.buckets.try_borrow() {
+                for entry in buckets.values().flat_map(|entries| entries.iter()) {
+                    if let Held::Strong(ref key) = entry.key {
+                        f(expr_address(key));
+                    }
+                    f(expr_address(&entry.value));
+                }
+            }
+        }
+        ExprKind::Guardian(ref guardian) => {
+            if let Ok(registered) = guardian.registered.try_borrow() {
+                for &(ref object, ref representative) in registered.iter() {
+                    f(expr_address(object));
+                    f(expr_address(representative));
+                }
+            }
+            if let Ok(queue) = guardian.queue.try_borrow() {
+                for value in queue.iter() {
+                    f(expr_address(value));
+                }
+            }
+        }
+        // Like a promise's state, a Rust value also held from elsewhere is
+        // left alone, and what it refers to looks held from outside.
+        ExprKind::Host(ref object) => {
+            if let Some(trace) = object.trace {
+                if Arc::strong_count(&object.value) == 1 {
+                    if let Ok(value) = object.value.try_borrow() {
+                        trace(&*value, &mut |held| f(expr_address(held)));
+                    }
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Start tracking a value which can hold references.
+pub fn track_expr(expr: &ExprRef) {
+    track(Object::Expr(Arc::downgrade(expr)));
+}
+
+/// Start tracking an environment.
+pub fn track_env(env: &Arc<Env>) {
+    track(Object::Env(Arc::downgrade(env)));
+}
+
+fn track(object: Object) {
+    HEAP.with(|heap| {
+        let mut heap = heap.borrow_mut();
+        heap.objects.push(object);
+        heap.stats.allocated += 1;
+        heap.since_slice += 1;
+    });
+}
+
+/// Tell the collector a reference to `old` is being overwritten.
+///
+/// This is the write barrier, and every mutation which drops a reference
+/// from one value to another must call it first. An incremental collection
+/// treats the old value as reachable, since it was when the collection
+/// started, and may have been counted as such.
+pub fn write_barrier(old: &ExprRef) {
+    grey(expr_address(old));
+}
+
+/// Tell the collector a value has been fetched through a weak reference,
+/// or out of an ephemeron. An incremental collection may not have marked
+/// it, but it's reachable now.
+pub fn read_barrier(value: &ExprRef) {
+    grey(expr_address(value));
+}
+
+/// The write barrier for overwriting a reference to an environment.
+pub fn write_barrier_env(old: &Arc<Env>) {
+    grey(env_address(old));
+}
+
+fn grey(address: usize) {
+    HEAP.with(|heap| {
+        if let Ok(mut heap) = heap.try_borrow_mut() {
+            if let Some(ref mut cycle) = heap.cycle {
+                cycle.greyed.push(address);
+            }
+        }
+    });
+}
+
+/// The current heap's statistics so far.
+pub fn stats() -> Stats {
+    HEAP.with(|heap| heap.borrow().stats())
+}
+
+/// How long the current heap's most recent collection pauses took, oldest
+/// first. Each slice of an incremental collection is a pause of its own.
+pub fn recent_pauses() -> Vec<Duration> {
+    HEAP.with(|heap| heap.borrow().recent_pauses())
+}
+
+/// Choose how the current heap's collector runs. Any collection in progress
+/// is finished first.
+pub fn set_mode(mode: Mode) {
+    finish_cycle();
+    HEAP.with(|heap| heap.borrow_mut().mode = mode);
+}
+
+/// How the current heap's collector is running.
+pub fn mode() -> Mode {
+    HEAP.with(|heap| heap.borrow().mode())
+}
+
+/// Collect garbage if it's due, a full collection or a slice of an
+/// incremental one as the mode says. The evaluator calls this between
+/// steps, where nothing is borrowed for long.
+pub fn safe_point() {
+    let due = HEAP.with(|heap| {
+        let heap = heap.borrow();
+        match heap.cycle {
+            Some(..) => heap.since_slice >= SLICE_INTERVAL,
+            None => heap.objects.len() >= heap.threshold,
+        }
+    });
+    if !due {
+        return;
+    }
+
+    match mode() {
+        Mode::Full => {
+            collect();
+        }
+        Mode::Incremental(target) => {
+            slice(Some(target));
+        }
+    }
+}
+
+/// Free every value on the current heap which nothing outside it can reach,
+/// finishing any incremental collection first, and give back how many were
+/// freed.
+pub fn collect() -> usize {
+    let finished = finish_cycle();
+    start_cycle();
+    finished + finish_cycle()
+}
+
+fn start_cycle() {
+    HEAP.with(|heap| {
+        let mut heap = heap.borrow_mut();
+        let mut cycle = heap.spare.take().unwrap_or_else(Cycle::new);
+        cycle.reset();
+        // The heap is left with just the values tracked from here on, while
+        // the cycle holds onto the ones it's looking at.
+        mem::swap(&mut heap.objects, &mut cycle.objects);
+        heap.cycle = Some(cycle);
+    });
+}
+
+fn finish_cycle() -> usize {
+    let mut reclaimed = 0;
+    while HEAP.with(|heap| heap.borrow().cycle.is_some()) {
+        reclaimed += slice(None);
+    }
+    reclaimed
+}
+
+/// Do a slice of work on the collection in progress, starting one if need
+/// be, until it's done or `target` has passed. Gives back how many values
+/// were freed if the collection finished.
+fn slice(target: Option<Duration>) -> usize {
+    let start = Instant::now();
+
+    let cycle = HEAP.with(|heap| heap.borrow_mut().cycle.take());
+    let mut cycle = match cycle {
+        Some(cycle) => cycle,
+        None => {
+            start_cycle();
+            match HEAP.with(|heap| heap.borrow_mut().cycle.take()) {
+                Some(cycle) => cycle,
+                None => return 0,
+            }
+        }
+    };
+
+    let deadline = target.map(|target| start + target);
+    let done = cycle.run(deadline);
+    let pause = start.elapsed();
+
+    HEAP.with(|heap| {
+        let mut heap = heap.borrow_mut();
+        heap.since_slice = 0;
+
+        let stats = &mut heap.stats;
+        stats.pauses += 1;
+        stats.last_pause = pause;
+        stats.max_pause = stats.max_pause.max(pause);
+        stats.total_pause += pause;
+
+        if heap.pauses.len() == PAUSE_LOG {
+            heap.pauses.pop_front();
+        }
+        heap.pauses.push_back(pause);
+    });
+
+    if !done {
+        HEAP.with(|heap| heap.borrow_mut().cycle = Some(cycle));
+        return 0;
+    }
+
+    let reclaimed = cycle.reclaimed;
+    let live = mem::replace(&mut cycle.survivors, Chunks::new());
+
+    HEAP.with(|heap| {
+        let mut heap = heap.borrow_mut();
+        heap.threshold = INITIAL_THRESHOLD.max(live.len() * 2);
+        heap.stats.live = live.len();
+        heap.stats.collections += 1;
+        heap.stats.reclaimed += reclaimed as u64;
+        let mut tracked_since = mem::replace(&mut heap.objects, live);
+        heap.objects.append(&mut tracked_since);
+        heap.spare = Some(cycle);
+    });
+
+    reclaimed
+}
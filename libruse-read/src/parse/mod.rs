@@ -2,19 +2,71 @@
 
 pub mod error;
 pub mod expr;
+pub mod heap;
+pub mod symbol;
 
+use atomic_refcell::AtomicRefCell;
 pub use parse::error::{Response, Result};
 use parse::expr::*;
-use lex::token::{Token, TokenKind};
+use lex::token::{Delim, Location, Span, Token, TokenKind};
 use parse::expr::Expr;
-use std::slice::Iter;
+use parse::symbol::keywords;
 use std::iter::Peekable;
+use std::result;
+use std::slice::Iter;
 
 pub fn parse<V: AsRef<[Token]>>(v: V) -> Result {
-    // TODO: Incorporate environment into parsing.
-    let _env = Env::default();
     let mut i = v.as_ref().iter().peekable();
-    parse_expr(&mut i)
+    let mut exprs = Vec::new();
+
+    while i.peek().is_some() {
+        exprs.push(parse_expr(&mut i)?);
+    }
+
+    // A program with several top-level forms is read as if its forms were
+    // wrapped in a `begin`, so it still reads as one expression.
+    match exprs.len() {
+        0 => Err(Response::EmptyProgram),
+        1 => Ok(exprs.remove(0)),
+        _ => {
+            let start = exprs[0].span.map(|s| s.start).unwrap_or_default();
+            let end = exprs[exprs.len() - 1].span.map(|s| s.end).unwrap_or_default();
+            let mut items = vec![Expr::symbol(keywords::BEGIN)];
+            items.extend(exprs.into_iter().map(Expr::alloc));
+            Ok(spanned_list(items, Expr::nil(), Span::new(start, end)))
+        }
+    }
+}
+
+// Build a list ending in `tail` whose outermost pair carries the span of the
+// whole list.
+fn spanned_list(items: Vec<ExprRef>, tail: ExprRef, span: Span) -> Expr {
+    let mut items = items.into_iter();
+
+    let kind = match items.next() {
+        Some(first) => {
+            let rest = items.rev().fold(tail, |tail, item| literal_pair(item, tail));
+            ExprKind::Pair(Pair {
+                car: AtomicRefCell::new(first),
+                cdr: AtomicRefCell::new(rest),
+            })
+        }
+        None => ExprKind::Nil,
+    };
+
+    Expr::with_span(kind, span)
+}
+
+// Make one of the pairs of a list read from the program. Like every other
+// literal constant, it can't be modified.
+fn literal_pair(car: ExprRef, cdr: ExprRef) -> ExprRef {
+    let pair = Pair {
+        car: AtomicRefCell::new(car),
+        cdr: AtomicRefCell::new(cdr),
+    };
+    let mut expr = Expr::new(ExprKind::Pair(pair));
+    expr.mutable = false;
+    expr.alloc()
 }
 
 macro_rules! unwrap_or_return {
@@ -28,7 +80,7 @@ macro_rules! unwrap_or_return {
 
 macro_rules! peek_or_stop {
     ( $e:expr ) => {
-        unwrap_or_return!($e.peek(), Err(Response::EndOfProgram));
+        unwrap_or_return!($e.peek(), Err(Response::EndOfProgram))
     }
 }
 
@@ -38,7 +90,93 @@ macro_rules! peek_or_stop {
 // forward in the case of a failed parse.
 type Tokens<'a> = Peekable<Iter<'a, Token>>;
 
+// Everything the reader gives back is a literal constant, which the program
+// can read but not modify.
+//
+// Lists, vectors and quotes can nest as deeply as the program likes, so
+// rather than reading what they hold by calling itself, the reader keeps
+// the ones it's in the middle of on a stack of its own. Each datum it
+// finishes is handed to the innermost of them.
 fn parse_expr(v: &mut Tokens) -> Result {
+    let mut open: Vec<Open> = Vec::new();
+
+    loop {
+        if let Some(&mut Open::List(ref mut list)) = open.last_mut() {
+            // A lone `.` makes the list improper: exactly one expression
+            // follows it, and becomes the tail of the list.
+            if !list.dotted && is_dot(peek_or_stop!(v)) {
+                if list.items.is_empty() {
+                    return Err(Response::InvalidProgram);
+                }
+                list.dotted = true;
+                v.next();
+                continue;
+            }
+        }
+
+        let mut datum = match parse_atom(v) {
+            Ok(atom) => atom,
+            Err(Response::EndOfProgram) => return Err(Response::EndOfProgram),
+            Err(..) => {
+                if let Some(finished) = open_or_close(v, &mut open)? {
+                    finished
+                } else {
+                    continue;
+                }
+            }
+        };
+
+        loop {
+            datum.mutable = false;
+            match open.last_mut() {
+                None => return Ok(datum),
+                Some(&mut Open::Quote(start)) => {
+                    // `'datum` is short for `(quote datum)`.
+                    let end = datum.span.map(|span| span.end).unwrap_or(start.end);
+                    open.pop();
+                    let items = vec![Expr::symbol(keywords::QUOTE), datum.alloc()];
+                    datum = spanned_list(items, Expr::nil(), Span::new(start.start, end));
+                }
+                Some(&mut Open::List(ref mut list)) => {
+                    if list.dotted {
+                        // Exactly one expression follows a `.`, and it has
+                        // to end the list.
+                        match peek_or_stop!(v).kind {
+                            TokenKind::CloseDelim(..) => {}
+                            _ => return Err(Response::InvalidProgram),
+                        }
+                        list.tail = Some(datum.alloc());
+                    } else {
+                        list.items.push(datum.alloc());
+                    }
+                    break;
+                }
+                Some(&mut Open::Vector(_, ref mut items)) => {
+                    items.push(datum.alloc());
+                    break;
+                }
+            }
+        }
+    }
+}
+
+/// A list, vector or quote the reader is partway through.
+enum Open {
+    List(OpenList),
+    Vector(Location, Vec<ExprRef>),
+    Quote(Span),
+}
+
+struct OpenList {
+    delim: Delim,
+    start: Location,
+    items: Vec<ExprRef>,
+    /// Whether a `.` has been read, making the list improper.
+    dotted: bool,
+    tail: Option<ExprRef>,
+}
+
+fn parse_atom(v: &mut Tokens) -> Result {
     if let Ok(a) = parse_symbol(v) {
         return Ok(a);
     }
@@ -59,19 +197,62 @@ fn parse_expr(v: &mut Tokens) -> Result {
         return Ok(a);
     }
 
-    if let Ok(a) = parse_list(v) {
-        return Ok(a);
+    peek_or_stop!(v);
+    Err(Response::InvalidProgram)
+}
+
+// Read a token which opens or closes a list, a vector or a quote. Closing
+// one gives back what it made.
+fn open_or_close(v: &mut Tokens, open: &mut Vec<Open>) -> result::Result<Option<Expr>, Response> {
+    let t = peek_or_stop!(v);
+
+    match t.kind {
+        TokenKind::Quote => open.push(Open::Quote(t.span())),
+        TokenKind::OpenDelim(ref delim) => open.push(Open::List(OpenList {
+            delim: delim.clone(),
+            start: t.start_location,
+            items: Vec::new(),
+            dotted: false,
+            tail: None,
+        })),
+        TokenKind::OpenVector => open.push(Open::Vector(t.start_location, Vec::new())),
+        TokenKind::CloseDelim(ref close) => {
+            let end = t.end_location;
+            let finished = match open.pop() {
+                Some(Open::List(list)) => {
+                    // A `.` with nothing after it leaves the list unfinished.
+                    if *close != list.delim || (list.dotted && list.tail.is_none()) {
+                        return Err(Response::InvalidProgram);
+                    }
+                    let tail = list.tail.unwrap_or_else(Expr::nil);
+                    spanned_list(list.items, tail, Span::new(list.start, end))
+                }
+                Some(Open::Vector(start, items)) => {
+                    if *close != Delim::Paren {
+                        return Err(Response::InvalidProgram);
+                    }
+                    let kind = ExprKind::Vector(AtomicRefCell::new(items));
+                    Expr::with_span(kind, Span::new(start, end))
+                }
+                _ => return Err(Response::InvalidProgram),
+            };
+            v.next();
+            return Ok(Some(finished));
+        }
+        _ => return Err(Response::InvalidProgram),
     }
 
-    Err(Response::InvalidProgram)
+    v.next();
+    Ok(None)
 }
 
 fn parse_symbol(v: &mut Tokens) -> Result {
     let t = peek_or_stop!(v);
 
     if let TokenKind::Symbol(ref s) = t.kind {
-        let kind = ExprKind::Symbol(s.clone());
-        let expr = Expr::new(kind);
+        let kind = ExprKind::Symbol(Symbol::intern(s));
+        let expr = Expr::with_span(kind, t.span());
+        v.next();
         return Ok(expr);
     }
 
@@ -87,7 +268,8 @@ fn parse_number(v: &mut Tokens) -> Result {
             exact: true,
         };
         let kind = ExprKind::Num(value);
-        let expr = Expr::new(kind);
+        let expr = Expr::with_span(kind, t.span());
+        v.next();
         return Ok(expr);
     }
 
@@ -103,7 +285,8 @@ fn parse_float(v: &mut Tokens) -> Result {
             exact: true,
         };
         let kind = ExprKind::Num(value);
-        let expr = Expr::new(kind);
+        let expr = Expr::with_span(kind, t.span());
+        v.next();
         return Ok(expr);
     }
 
@@ -114,8 +297,9 @@ fn parse_string(v: &mut Tokens) -> Result {
     let t = peek_or_stop!(v);
 
     if let TokenKind::Str(ref s) = t.kind {
-        let kind = ExprKind::Str(s.clone());
-        let expr = Expr::new(kind);
+        let kind = ExprKind::Str(AtomicRefCell::new(s.clone()));
+        let expr = Expr::with_span(kind, t.span());
+        v.next();
         return Ok(expr);
     }
 
@@ -127,23 +311,17 @@ fn parse_bool(v: &mut Tokens) -> Result {
 
     if let TokenKind::Bool(b) = t.kind {
         let kind = ExprKind::Bool(b);
-        let expr = Expr::new(kind);
+        let expr = Expr::with_span(kind, t.span());
+        v.next();
         return Ok(expr);
     }
 
     Err(Response::InvalidProgram)
 }
 
-fn parse_list(v: &mut Tokens) -> Result {
-    // Parse an opening delimiter, then a series of Ruse expressions
-    // until you hit the matching closing delimiter. If you hit
-    // a non-matching closing delimiter first, error out.
-    let t = peek_or_stop!(v);
-
-    if !t.kind.is_open_delim() {
-        return Err(Response::InvalidProgram);
+fn is_dot(t: &Token) -> bool {
+    match t.kind {
+        TokenKind::Symbol(ref s) => s == ".",
+        _ => false,
     }
-
-    unimplemented!()
 }
-
@@ -0,0 +1,191 @@
+//! Symbols, interned into a table shared by every thread.
+//!
+//! Interning a name always gives back the same symbol, so symbols are
+//! compared and hashed as small numbers rather than by their text. Names
+//! stay in the table for as long as the program runs.
+//!
+//! Symbols made by `gensym`, and the aliases macro expansions bind, aren't
+//! interned. They share the name of an interned symbol but carry a number
+//! as well, which tells them apart from it and from each other. The numbers
+//! come from one counter for the whole process, so symbols made by
+//! different engines, or on different threads, never clash either.
+//!
+//! The names of the special forms, and the keywords used inside them, are
+//! interned before anything else. Their symbols are constants in
+//! `keywords`, so the evaluator can recognise them without going through
+//! the table, which every thread shares behind a lock.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::mem;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, MutexGuard, OnceLock};
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Symbol {
+    id: u32,
+    /// Zero for interned symbols.
+    number: u64,
+}
+
+macro_rules! keywords {
+    ($($name:ident = $text:expr,)*) => {
+        #[allow(non_camel_case_types)]
+        enum Keyword {
+            $($name,)*
+        }
+
+        /// The names of the keywords, in the order they're interned.
+        const KEYWORDS: &[&str] = &[$($text,)*];
+
+        /// The symbols naming the special forms, and the keywords used
+        /// inside them.
+        pub mod keywords {
+            use super::{Keyword, Symbol};
+
+            $(pub const $name: Symbol = Symbol { id: Keyword::$name as u32, number: 0 };)*
+        }
+    };
+}
+
+keywords! {
+    QUOTE = "quote",
+    IF = "if",
+    DEFINE = "define",
+    SET = "set!",
+    LAMBDA = "lambda",
+    CASE_LAMBDA = "case-lambda",
+    BEGIN = "begin",
+    LET = "let",
+    LET_STAR = "let*",
+    LETREC = "letrec",
+    LETREC_STAR = "letrec*",
+    COND = "cond",
+    AND = "and",
+    OR = "or",
+    WHEN = "when",
+    UNLESS = "unless",
+    GUARD = "guard",
+    DEFINE_SYNTAX = "define-syntax",
+    LET_SYNTAX = "let-syntax",
+    LETREC_SYNTAX = "letrec-syntax",
+    SYNTAX_RULES = "syntax-rules",
+    DEFINE_RECORD_TYPE = "define-record-type",
+    DELAY = "delay",
+    DELAY_FORCE = "delay-force",
+    PARAMETERIZE = "parameterize",
+    LET_VALUES = "let-values",
+    LET_STAR_VALUES = "let*-values",
+    DEFINE_VALUES = "define-values",
+    RECEIVE = "receive",
+    ELSE = "else",
+    ARROW = "=>",
+    ELLIPSIS = "...",
+    UNDERSCORE = "_",
+}
+
+struct Table {
+    names: Vec<&'static str>,
+    ids: HashMap<&'static str, u32>,
+}
+
+fn table() -> MutexGuard<'static, Table> {
+    static TABLE: OnceLock<Mutex<Table>> = OnceLock::new();
+
+    let table = TABLE.get_or_init(|| {
+        Mutex::new(Table {
+            names: KEYWORDS.to_vec(),
+            ids: KEYWORDS.iter().enumerate().map(|(id, &name)| (name, id as u32)).collect(),
+        })
+    });
+    // The table is never left half-updated, so it's still fine to use if a
+    // thread panicked while holding it.
+    table.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+impl Symbol {
+    /// The symbol with the given name, which is the same every time.
+    pub fn intern(name: &str) -> Symbol {
+        let mut table = table();
+        if let Some(&id) = table.ids.get(name) {
+            return Symbol { id, number: 0 };
+        }
+
+        let id = table.names.len() as u32;
+        let name: &'static str = Box::leak(name.to_string().into_boxed_str());
+        table.names.push(name);
+        table.ids.insert(name, id);
+        Symbol { id, number: 0 }
+    }
+
+    /// The symbol with the given name, if it's been interned already.
+    pub fn interned(name: &str) -> Option<Symbol> {
+        table().ids.get(name).map(|&id| Symbol { id, number: 0 })
+    }
+
+    /// How many bytes interning `name` takes, for as long as the program
+    /// runs.
+    pub fn interning_cost(name: &str) -> usize {
+        name.len() + mem::size_of::<(&str, u32)>() + mem::size_of::<&str>()
+    }
+
+    /// A symbol which interning can't give back, and which no other symbol
+    /// is equal to, written as the name of `base` followed by `#number`.
+    /// There's no symbol to give back once the numbers run out, though
+    /// making one a nanosecond would take centuries to get there.
+    pub fn fresh(base: Symbol) -> Option<Symbol> {
+        static NEXT: AtomicU64 = AtomicU64::new(1);
+
+        let number = NEXT
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |next| next.checked_add(1))
+            .ok()?;
+        Some(Symbol { id: base.id, number })
+    }
+
+    pub fn is_interned(self) -> bool {
+        self.number == 0
+    }
+
+    /// The interned symbol this one was made from, or the symbol itself if
+    /// it's interned.
+    pub fn base(self) -> Symbol {
+        Symbol { id: self.id, number: 0 }
+    }
+
+    /// The name the symbol was interned under. Symbols which aren't interned
+    /// give the name of their base.
+    pub fn name(self) -> &'static str {
+        match KEYWORDS.get(self.id as usize) {
+            Some(name) => name,
+            None => table().names[self.id as usize],
+        }
+    }
+}
+
+impl<'a> From<&'a str> for Symbol {
+    fn from(name: &'a str) -> Symbol {
+        Symbol::intern(name)
+    }
+}
+
+impl From<String> for Symbol {
+    fn from(name: String) -> Symbol {
+        Symbol::intern(&name)
+    }
+}
+
+impl fmt::Display for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.is_interned() {
+            write!(f, "{}", self.name())
+        } else {
+            write!(f, "{}#{}", self.name(), self.number)
+        }
+    }
+}
+
+impl fmt::Debug for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self)
+    }
+}
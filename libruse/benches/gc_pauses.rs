@@ -0,0 +1,72 @@
+//! Shows how long the collector pauses the program for, in each mode.
+//!
+//! Run with `cargo bench --bench gc_pauses`. The workload keeps a large
+//! structure alive while churning through short-lived cycles, so every
+//! collection has plenty to mark and plenty to free.
+
+extern crate libruse;
+
+use libruse::{Engine, GcMode};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const SETUP: &str = "
+(define keep
+  (let loop ((i 0) (acc (quote ())))
+    (if (= i 50000) acc (loop (+ i 1) (cons (vector i (list i)) acc)))))
+(define slots (make-vector 1000 #f))
+(define (churn n i)
+  (cond ((= n 0) (quote done))
+        ((= i 1000) (churn n 0))
+        (else
+         (let () (define (self) self) self)
+         (vector-set! slots i (list n))
+         (churn (- n 1) (+ i 1)))))
+";
+
+fn main() {
+    report("full", GcMode::Full);
+    for &target in &[1, 4, 16] {
+        let name = format!("incremental ({}ms)", target);
+        report(&name, GcMode::Incremental(Duration::from_millis(target)));
+    }
+}
+
+/// Run the workload on a thread of its own, so it gets a fresh heap.
+fn report(name: &str, mode: GcMode) {
+    let name = name.to_string();
+    thread::spawn(move || run(&name, mode)).join().expect("benchmark panicked");
+}
+
+fn run(name: &str, mode: GcMode) {
+    let mut engine = Engine::new();
+    engine.set_gc_mode(mode);
+    engine.run(SETUP).expect("setup failed");
+
+    let start = Instant::now();
+    engine.run("(churn 500000 0)").expect("workload failed");
+    let elapsed = start.elapsed();
+
+    let mut pauses = engine.gc_pauses();
+    pauses.sort();
+    let stats = engine.gc_stats();
+
+    println!("{}", name);
+    println!("  run time:     {:?}", elapsed);
+    println!("  collections:  {}", stats.collections);
+    println!("  pauses:       {} (last {} shown below)", stats.pauses, pauses.len());
+    println!("  total paused: {:?}", stats.total_pause);
+    if pauses.is_empty() {
+        return;
+    }
+    for &(label, p) in &[("p50", 0.5), ("p90", 0.9), ("p99", 0.99)] {
+        println!("  {}:          {:?}", label, percentile(&pauses, p));
+    }
+    println!("  max:          {:?}", stats.max_pause);
+}
+
+/// The pause at the given fraction of the way through the sorted pauses.
+fn percentile(sorted: &[Duration], p: f64) -> Duration {
+    let index = ((sorted.len() - 1) as f64 * p).round() as usize;
+    sorted[index]
+}
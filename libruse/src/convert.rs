@@ -0,0 +1,426 @@
+//! Converting values between Rust and Ruse.
+//!
+//! Besides numbers, booleans, characters and strings, which map to the Ruse
+//! values of the same kind:
+//!
+//! - `Vec<T>` is a list, and can also be made from a vector.
+//! - `HashMap<K, V>` is an association list, `((key . value) ...)`, and can
+//!   also be made from a hash table.
+//! - `Option<T>` is `#f` for `None`, and the value itself for `Some`. So
+//!   `Some(false)` comes back as `None`.
+//! - Tuples are lists of the same length.
+//! - `Result<T, E>` is a pair tagged with `ok` or `error`, like `(ok . 1)`.
+//!
+//! `#[derive(FromRuse, IntoRuse)]` makes structs into association lists with
+//! a symbol for each field, or into records with `#[ruse(record)]`. Enums
+//! become the name of their variant as a symbol, followed by the variant's
+//! fields if it has any: `(circle 1.5)` or `(rect (width . 2) (height . 3))`.
+//! Names are written the Ruse way, so `first_name` and `DarkRed` become
+//! `first-name` and `dark-red`.
+
+use atomic_refcell::AtomicRefCell;
+use print::print;
+use read::parse::expr::{Expr, ExprKind, ExprRef, Number, NumberKind, Record, RecordType, Symbol};
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::error;
+use std::fmt;
+use std::hash::Hash;
+use std::sync::{Arc, Mutex, OnceLock};
+
+/// A Rust type which can be made from a Ruse value.
+pub trait FromRuse: Sized {
+    /// Convert a Ruse value, or say what kind of value was wanted instead.
+    fn from_ruse(value: &ExprRef) -> Result<Self, ConversionError>;
+}
+
+/// A Rust type which can be turned into a Ruse value.
+pub trait IntoRuse {
+    /// Make the Ruse value standing for this one.
+    fn into_ruse(self) -> ExprRef;
+}
+
+/// Indicates that a Ruse value couldn't be converted to a Rust type.
+#[derive(Debug, PartialEq, Clone)]
+pub struct ConversionError {
+    /// The kind of value which was wanted, like "an integer".
+    pub expected: String,
+    /// The value which was found instead, as Ruse would write it.
+    pub found: String,
+}
+
+impl ConversionError {
+    /// Say that `value` isn't the kind of value which was `expected`.
+    pub fn new<S: Into<String>>(expected: S, value: &ExprRef) -> ConversionError {
+        ConversionError {
+            expected: expected.into(),
+            found: print(value),
+        }
+    }
+}
+
+impl fmt::Display for ConversionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "expected {}, got {}", self.expected, self.found)
+    }
+}
+
+impl error::Error for ConversionError {
+    fn description(&self) -> &str {
+        "a Ruse value had the wrong type"
+    }
+}
+
+impl FromRuse for ExprRef {
+    fn from_ruse(value: &ExprRef) -> Result<ExprRef, ConversionError> {
+        Ok(value.clone())
+    }
+}
+
+impl IntoRuse for ExprRef {
+    fn into_ruse(self) -> ExprRef {
+        self
+    }
+}
+
+macro_rules! integer {
+    ($($t:ty),*) => {
+        $(
+            impl FromRuse for $t {
+                fn from_ruse(value: &ExprRef) -> Result<$t, ConversionError> {
+                    let converted = match value.kind {
+                        ExprKind::Num(Number { kind: NumberKind::Int(i), .. }) => <$t>::try_from(i).ok(),
+                        _ => None,
+                    };
+                    converted.ok_or_else(|| {
+                        ConversionError::new(concat!("an integer which fits in ", stringify!($t)), value)
+                    })
+                }
+            }
+
+            impl IntoRuse for $t {
+                fn into_ruse(self) -> ExprRef {
+                    match i64::try_from(self) {
+                        Ok(i) => Expr::integer(i),
+                        // Only the largest unsigned integers don't fit.
+                        Err(..) => Expr::real(self as f64),
+                    }
+                }
+            }
+        )*
+    };
+}
+
+integer!(i8, i16, i32, i64, isize, u8, u16, u32, u64, usize);
+
+impl FromRuse for f64 {
+    fn from_ruse(value: &ExprRef) -> Result<f64, ConversionError> {
+        match value.kind {
+            ExprKind::Num(ref n) => match n.kind {
+                NumberKind::Int(i) => Ok(i as f64),
+                NumberKind::Real(f) => Ok(f),
+                NumberKind::Rational { numerator, denominator } => {
+                    Ok(numerator as f64 / denominator as f64)
+                }
+            },
+            _ => Err(ConversionError::new("a number", value)),
+        }
+    }
+}
+
+impl IntoRuse for f64 {
+    fn into_ruse(self) -> ExprRef {
+        Expr::real(self)
+    }
+}
+
+impl FromRuse for f32 {
+    fn from_ruse(value: &ExprRef) -> Result<f32, ConversionError> {
+        f64::from_ruse(value).map(|f| f as f32)
+    }
+}
+
+impl IntoRuse for f32 {
+    fn into_ruse(self) -> ExprRef {
+        Expr::real(f64::from(self))
+    }
+}
+
+impl FromRuse for bool {
+    fn from_ruse(value: &ExprRef) -> Result<bool, ConversionError> {
+        match value.kind {
+            ExprKind::Bool(b) => Ok(b),
+            _ => Err(ConversionError::new("a boolean", value)),
+        }
+    }
+}
+
+impl IntoRuse for bool {
+    fn into_ruse(self) -> ExprRef {
+        Expr::boolean(self)
+    }
+}
+
+impl FromRuse for char {
+    fn from_ruse(value: &ExprRef) -> Result<char, ConversionError> {
+        match value.kind {
+            ExprKind::Char(c) => Ok(c),
+            _ => Err(ConversionError::new("a character", value)),
+        }
+    }
+}
+
+impl IntoRuse for char {
+    fn into_ruse(self) -> ExprRef {
+        Expr::new(ExprKind::Char(self)).alloc()
+    }
+}
+
+impl FromRuse for String {
+    fn from_ruse(value: &ExprRef) -> Result<String, ConversionError> {
+        match value.kind {
+            ExprKind::Str(ref s) => Ok(s.borrow().clone()),
+            _ => Err(ConversionError::new("a string", value)),
+        }
+    }
+}
+
+impl IntoRuse for String {
+    fn into_ruse(self) -> ExprRef {
+        Expr::string(self)
+    }
+}
+
+impl<'a> IntoRuse for &'a str {
+    fn into_ruse(self) -> ExprRef {
+        Expr::string(self)
+    }
+}
+
+/// Functions with nothing to give back return an unspecified value.
+impl IntoRuse for () {
+    fn into_ruse(self) -> ExprRef {
+        Expr::unspecified()
+    }
+}
+
+impl<T: FromRuse> FromRuse for Vec<T> {
+    fn from_ruse(value: &ExprRef) -> Result<Vec<T>, ConversionError> {
+        let items = match value.kind {
+            ExprKind::Vector(ref items) => items.borrow().clone(),
+            _ => value.to_vec().ok_or_else(|| ConversionError::new("a list", value))?,
+        };
+        items.iter().map(T::from_ruse).collect()
+    }
+}
+
+impl<T: IntoRuse> IntoRuse for Vec<T> {
+    fn into_ruse(self) -> ExprRef {
+        Expr::list(self.into_iter().map(IntoRuse::into_ruse).collect())
+    }
+}
+
+impl<K: FromRuse + Eq + Hash, V: FromRuse> FromRuse for HashMap<K, V> {
+    fn from_ruse(value: &ExprRef) -> Result<HashMap<K, V>, ConversionError> {
+        map_entries(value)?
+            .iter()
+            .map(|&(ref key, ref value)| Ok((K::from_ruse(key)?, V::from_ruse(value)?)))
+            .collect()
+    }
+}
+
+impl<K: IntoRuse, V: IntoRuse> IntoRuse for HashMap<K, V> {
+    fn into_ruse(self) -> ExprRef {
+        let entries = self
+            .into_iter()
+            .map(|(key, value)| Expr::cons(key.into_ruse(), value.into_ruse()))
+            .collect();
+        Expr::list(entries)
+    }
+}
+
+impl<T: FromRuse> FromRuse for Option<T> {
+    fn from_ruse(value: &ExprRef) -> Result<Option<T>, ConversionError> {
+        match value.kind {
+            ExprKind::Bool(false) => Ok(None),
+            _ => T::from_ruse(value).map(Some),
+        }
+    }
+}
+
+impl<T: IntoRuse> IntoRuse for Option<T> {
+    fn into_ruse(self) -> ExprRef {
+        match self {
+            Some(value) => value.into_ruse(),
+            None => Expr::boolean(false),
+        }
+    }
+}
+
+impl<T: FromRuse, E: FromRuse> FromRuse for Result<T, E> {
+    fn from_ruse(value: &ExprRef) -> Result<Result<T, E>, ConversionError> {
+        let (tag, rest) = match (value.car(), value.cdr()) {
+            (Some(tag), Some(rest)) => (tag, rest),
+            _ => return Err(ConversionError::new("a pair tagged ok or error", value)),
+        };
+        match tag.as_symbol() {
+            Some(tag) if tag == Symbol::intern("ok") => T::from_ruse(&rest).map(Ok),
+            Some(tag) if tag == Symbol::intern("error") => E::from_ruse(&rest).map(Err),
+            _ => Err(ConversionError::new("a pair tagged ok or error", value)),
+        }
+    }
+}
+
+impl<T: IntoRuse, E: IntoRuse> IntoRuse for Result<T, E> {
+    fn into_ruse(self) -> ExprRef {
+        match self {
+            Ok(value) => Expr::cons(Expr::symbol("ok"), value.into_ruse()),
+            Err(error) => Expr::cons(Expr::symbol("error"), error.into_ruse()),
+        }
+    }
+}
+
+macro_rules! tuple {
+    ($count:expr; $($item:ident),*) => {
+        impl<$($item: FromRuse),*> FromRuse for ($($item,)*) {
+            fn from_ruse(value: &ExprRef) -> Result<($($item,)*), ConversionError> {
+                let items = list_of(value, $count)?;
+                let mut items = items.iter();
+                Ok(($(match items.next() {
+                    Some(item) => $item::from_ruse(item)?,
+                    None => unreachable!("the length was checked"),
+                },)*))
+            }
+        }
+
+        impl<$($item: IntoRuse),*> IntoRuse for ($($item,)*) {
+            #[allow(non_snake_case)]
+            fn into_ruse(self) -> ExprRef {
+                let ($($item,)*) = self;
+                Expr::list(vec![$($item.into_ruse()),*])
+            }
+        }
+    };
+}
+
+tuple!(1; A);
+tuple!(2; A, B);
+tuple!(3; A, B, C);
+tuple!(4; A, B, C, D);
+tuple!(5; A, B, C, D, E);
+tuple!(6; A, B, C, D, E, G);
+tuple!(7; A, B, C, D, E, G, H);
+tuple!(8; A, B, C, D, E, G, H, I);
+
+/// The entries of a hash table or an association list.
+pub(crate) fn map_entries(value: &ExprRef) -> Result<Vec<(ExprRef, ExprRef)>, ConversionError> {
+    match value.kind {
+        ExprKind::HashTable(ref table) => Ok(table
+            .buckets
+            .borrow()
+            .values()
+            .flat_map(|bucket| bucket.iter())
+            .filter_map(|entry| entry.key.get().map(|key| (key, entry.value.clone())))
+            .collect()),
+        _ => association_list(value),
+    }
+}
+
+/// The entries of an association list.
+fn association_list(value: &ExprRef) -> Result<Vec<(ExprRef, ExprRef)>, ConversionError> {
+    let items = value.to_vec().ok_or_else(|| ConversionError::new("an association list", value))?;
+    items
+        .iter()
+        .map(|item| match (item.car(), item.cdr()) {
+            (Some(key), Some(value)) => Ok((key, value)),
+            _ => Err(ConversionError::new("an association list", value)),
+        })
+        .collect()
+}
+
+/// Make an association list with a symbol for each field, as derived
+/// conversions do for structs.
+pub fn fields_into_alist(fields: Vec<(&str, ExprRef)>) -> ExprRef {
+    let entries = fields
+        .into_iter()
+        .map(|(name, value)| Expr::cons(Expr::symbol(name), value))
+        .collect();
+    Expr::list(entries)
+}
+
+/// Make a record with the given fields, as derived conversions do for
+/// structs marked `#[ruse(record)]`. Records made for the same name and
+/// fields share a record type, whichever thread makes them.
+pub fn fields_into_record(name: &str, fields: Vec<(&str, ExprRef)>) -> ExprRef {
+    static TYPES: OnceLock<Mutex<HashMap<(String, Vec<String>), Arc<RecordType>>>> = OnceLock::new();
+
+    let names: Vec<String> = fields.iter().map(|&(name, _)| name.to_string()).collect();
+    let types = TYPES.get_or_init(|| Mutex::new(HashMap::new()));
+    // Nothing is left half-done if a thread panics while holding the map.
+    let record_type = types
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+        .entry((name.to_string(), names.clone()))
+        .or_insert_with(|| {
+            Arc::new(RecordType {
+                name: name.into(),
+                fields: names.iter().map(|name| name.as_str().into()).collect(),
+            })
+        })
+        .clone();
+
+    let record = Record {
+        record_type,
+        values: AtomicRefCell::new(fields.into_iter().map(|(_, value)| value).collect()),
+    };
+    Expr::new(ExprKind::Record(record)).alloc()
+}
+
+/// Convert the field `name` of a record or association list, as derived
+/// conversions do for structs. Any record with the field will do, whatever
+/// its type.
+pub fn field<T: FromRuse>(value: &ExprRef, name: &str) -> Result<T, ConversionError> {
+    let found = match value.kind {
+        ExprKind::Record(ref record) => record.get(name),
+        _ => association_list(value)?
+            .into_iter()
+            .find(|&(ref key, _)| key.as_symbol() == Some(Symbol::intern(name)))
+            .map(|(_, value)| value),
+    };
+    match found {
+        Some(field) => T::from_ruse(&field),
+        None => Err(ConversionError::new(format!("a field named {}", name), value)),
+    }
+}
+
+/// Split a value made by a derived conversion for an enum into the name of
+/// its variant and its fields, if it has any.
+pub fn variant(value: &ExprRef) -> Result<(&'static str, ExprRef), ConversionError> {
+    let (tag, rest) = match value.kind {
+        ExprKind::Pair(..) => (value.car(), value.cdr().unwrap_or_else(Expr::nil)),
+        _ => (Some(value.clone()), Expr::nil()),
+    };
+    match tag.and_then(|tag| tag.as_symbol()) {
+        Some(tag) if tag.is_interned() => Ok((tag.name(), rest)),
+        _ => Err(ConversionError::new("a symbol or a list starting with one", value)),
+    }
+}
+
+/// The items of a list which must be exactly `count` long, as tuples and
+/// the fields of derived tuple structs are.
+pub fn list_of(value: &ExprRef, count: usize) -> Result<Vec<ExprRef>, ConversionError> {
+    match value.to_vec() {
+        Some(ref items) if items.len() == count => Ok(items.clone()),
+        _ => Err(ConversionError::new(format!("a list of {}", count), value)),
+    }
+}
+
+/// Make the value standing for a variant of an enum, as derived conversions
+/// do: just its name if it has no fields, otherwise a list of its name and
+/// its fields.
+pub fn variant_into_ruse(name: &str, fields: Option<ExprRef>) -> ExprRef {
+    match fields {
+        Some(fields) => Expr::cons(Expr::symbol(name), fields),
+        None => Expr::symbol(name),
+    }
+}
@@ -0,0 +1,664 @@
+//! Converting `serde` types to and from Ruse data, with the `serde` feature.
+//!
+//! `to_datum` turns anything which is `Serialize` into a Ruse value, and
+//! `from_datum` turns a Ruse value back into anything which is
+//! `Deserialize`. Numbers, booleans, characters and strings map to the Ruse
+//! values of the same kind, as they do for `FromRuse` and `IntoRuse`, and:
+//!
+//! - Sequences and tuples are lists, and can also be read from vectors.
+//! - Byte arrays are bytevectors, when they're serialized as bytes, and
+//!   bytevectors can be read as sequences of `u8`.
+//! - Maps are association lists, `((key . value) ...)`, with keys converted
+//!   like any other value. They can also be read from hash tables.
+//! - Structs are association lists with a symbol for each field, like
+//!   `((name . "ada") (age . 36))`, and can also be read from records.
+//!   Field names are used as they are, so `#[serde(rename_all =
+//!   "kebab-case")]` gives them the usual Ruse spelling.
+//! - Newtype structs are the value they wrap, and unit and unit structs are
+//!   the empty list.
+//! - `None` is `#f`, and `Some` is the value itself.
+//! - Enums are tagged the same way derived conversions tag them: the name
+//!   of the variant as a symbol, followed by its fields if it has any, so
+//!   `Shape::Circle(1.5)` is `(Circle 1.5)`.
+
+use convert::{list_of, map_entries, variant, variant_into_ruse, ConversionError, IntoRuse};
+use read::parse::expr::{Expr, ExprKind, ExprRef, NumberKind};
+use serde::de::{self, DeserializeOwned, DeserializeSeed, IntoDeserializer, Visitor};
+use serde::ser::{self, Serialize};
+use std::error;
+use std::fmt;
+use std::vec;
+
+/// Convert a value into a Ruse datum.
+pub fn to_datum<T: Serialize + ?Sized>(value: &T) -> Result<ExprRef, Error> {
+    value.serialize(Serializer)
+}
+
+/// Convert a Ruse datum into a value.
+pub fn from_datum<T: DeserializeOwned>(datum: &ExprRef) -> Result<T, Error> {
+    T::deserialize(Deserializer::new(datum.clone()))
+}
+
+/// Indicates that a value couldn't be converted to or from a Ruse datum.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Error {
+    message: String,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl error::Error for Error {
+    fn description(&self) -> &str {
+        &self.message
+    }
+}
+
+impl ser::Error for Error {
+    fn custom<T: fmt::Display>(message: T) -> Error {
+        Error {
+            message: message.to_string(),
+        }
+    }
+}
+
+impl de::Error for Error {
+    fn custom<T: fmt::Display>(message: T) -> Error {
+        Error {
+            message: message.to_string(),
+        }
+    }
+}
+
+impl From<ConversionError> for Error {
+    fn from(error: ConversionError) -> Error {
+        Error {
+            message: error.to_string(),
+        }
+    }
+}
+
+/// Say that `datum` isn't the kind of datum which was `expected`.
+fn unexpected(expected: &str, datum: &ExprRef) -> Error {
+    ConversionError::new(expected, datum).into()
+}
+
+/// Makes Ruse data out of `Serialize` values.
+pub struct Serializer;
+
+impl ser::Serializer for Serializer {
+    type Ok = ExprRef;
+    type Error = Error;
+    type SerializeSeq = SerializeList;
+    type SerializeTuple = SerializeList;
+    type SerializeTupleStruct = SerializeList;
+    type SerializeTupleVariant = SerializeList;
+    type SerializeMap = SerializeAlist;
+    type SerializeStruct = SerializeAlist;
+    type SerializeStructVariant = SerializeAlist;
+
+    fn serialize_bool(self, v: bool) -> Result<ExprRef, Error> {
+        Ok(v.into_ruse())
+    }
+
+    fn serialize_i8(self, v: i8) -> Result<ExprRef, Error> {
+        Ok(v.into_ruse())
+    }
+
+    fn serialize_i16(self, v: i16) -> Result<ExprRef, Error> {
+        Ok(v.into_ruse())
+    }
+
+    fn serialize_i32(self, v: i32) -> Result<ExprRef, Error> {
+        Ok(v.into_ruse())
+    }
+
+    fn serialize_i64(self, v: i64) -> Result<ExprRef, Error> {
+        Ok(v.into_ruse())
+    }
+
+    fn serialize_u8(self, v: u8) -> Result<ExprRef, Error> {
+        Ok(v.into_ruse())
+    }
+
+    fn serialize_u16(self, v: u16) -> Result<ExprRef, Error> {
+        Ok(v.into_ruse())
+    }
+
+    fn serialize_u32(self, v: u32) -> Result<ExprRef, Error> {
+        Ok(v.into_ruse())
+    }
+
+    fn serialize_u64(self, v: u64) -> Result<ExprRef, Error> {
+        Ok(v.into_ruse())
+    }
+
+    fn serialize_f32(self, v: f32) -> Result<ExprRef, Error> {
+        Ok(v.into_ruse())
+    }
+
+    fn serialize_f64(self, v: f64) -> Result<ExprRef, Error> {
+        Ok(v.into_ruse())
+    }
+
+    fn serialize_char(self, v: char) -> Result<ExprRef, Error> {
+        Ok(v.into_ruse())
+    }
+
+    fn serialize_str(self, v: &str) -> Result<ExprRef, Error> {
+        Ok(v.into_ruse())
+    }
+
+    fn serialize_bytes(self, v: &[u8]) -> Result<ExprRef, Error> {
+        Ok(Expr::bytevector(v.to_vec()))
+    }
+
+    fn serialize_none(self) -> Result<ExprRef, Error> {
+        Ok(Expr::boolean(false))
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<ExprRef, Error> {
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<ExprRef, Error> {
+        Ok(Expr::nil())
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<ExprRef, Error> {
+        Ok(Expr::nil())
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+    ) -> Result<ExprRef, Error> {
+        Ok(variant_into_ruse(variant, None))
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        value: &T,
+    ) -> Result<ExprRef, Error> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        value: &T,
+    ) -> Result<ExprRef, Error> {
+        let fields = Expr::list(vec![value.serialize(self)?]);
+        Ok(variant_into_ruse(variant, Some(fields)))
+    }
+
+    fn serialize_seq(self, len: Option<usize>) -> Result<SerializeList, Error> {
+        Ok(SerializeList::new(None, len.unwrap_or(0)))
+    }
+
+    fn serialize_tuple(self, len: usize) -> Result<SerializeList, Error> {
+        Ok(SerializeList::new(None, len))
+    }
+
+    fn serialize_tuple_struct(self, _name: &'static str, len: usize) -> Result<SerializeList, Error> {
+        Ok(SerializeList::new(None, len))
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> Result<SerializeList, Error> {
+        Ok(SerializeList::new(Some(variant), len))
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> Result<SerializeAlist, Error> {
+        Ok(SerializeAlist::new(None))
+    }
+
+    fn serialize_struct(self, _name: &'static str, _len: usize) -> Result<SerializeAlist, Error> {
+        Ok(SerializeAlist::new(None))
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        _len: usize,
+    ) -> Result<SerializeAlist, Error> {
+        Ok(SerializeAlist::new(Some(variant)))
+    }
+}
+
+/// Builds the list for a sequence, a tuple, or a tuple variant.
+pub struct SerializeList {
+    /// The name of the variant, for tuple variants.
+    variant: Option<&'static str>,
+    items: Vec<ExprRef>,
+}
+
+impl SerializeList {
+    fn new(variant: Option<&'static str>, len: usize) -> SerializeList {
+        SerializeList {
+            variant,
+            items: Vec::with_capacity(len),
+        }
+    }
+
+    fn push<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        self.items.push(value.serialize(Serializer)?);
+        Ok(())
+    }
+
+    fn finish(self) -> Result<ExprRef, Error> {
+        let list = Expr::list(self.items);
+        Ok(match self.variant {
+            Some(variant) => variant_into_ruse(variant, Some(list)),
+            None => list,
+        })
+    }
+}
+
+impl ser::SerializeSeq for SerializeList {
+    type Ok = ExprRef;
+    type Error = Error;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<ExprRef, Error> {
+        self.finish()
+    }
+}
+
+impl ser::SerializeTuple for SerializeList {
+    type Ok = ExprRef;
+    type Error = Error;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<ExprRef, Error> {
+        self.finish()
+    }
+}
+
+impl ser::SerializeTupleStruct for SerializeList {
+    type Ok = ExprRef;
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<ExprRef, Error> {
+        self.finish()
+    }
+}
+
+impl ser::SerializeTupleVariant for SerializeList {
+    type Ok = ExprRef;
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<ExprRef, Error> {
+        self.finish()
+    }
+}
+
+/// Builds the association list for a map, a struct, or a struct variant.
+pub struct SerializeAlist {
+    /// The name of the variant, for struct variants.
+    variant: Option<&'static str>,
+    entries: Vec<ExprRef>,
+    /// The key of the entry whose value comes next, for maps.
+    key: Option<ExprRef>,
+}
+
+impl SerializeAlist {
+    fn new(variant: Option<&'static str>) -> SerializeAlist {
+        SerializeAlist {
+            variant,
+            entries: Vec::new(),
+            key: None,
+        }
+    }
+
+    fn finish(self) -> Result<ExprRef, Error> {
+        let alist = Expr::list(self.entries);
+        Ok(match self.variant {
+            Some(variant) => variant_into_ruse(variant, Some(alist)),
+            None => alist,
+        })
+    }
+}
+
+impl ser::SerializeMap for SerializeAlist {
+    type Ok = ExprRef;
+    type Error = Error;
+
+    fn serialize_key<T: Serialize + ?Sized>(&mut self, key: &T) -> Result<(), Error> {
+        self.key = Some(key.serialize(Serializer)?);
+        Ok(())
+    }
+
+    fn serialize_value<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        let key = match self.key.take() {
+            Some(key) => key,
+            None => return Err(ser::Error::custom("a map value was given without a key")),
+        };
+        self.entries.push(Expr::cons(key, value.serialize(Serializer)?));
+        Ok(())
+    }
+
+    fn end(self) -> Result<ExprRef, Error> {
+        self.finish()
+    }
+}
+
+impl ser::SerializeStruct for SerializeAlist {
+    type Ok = ExprRef;
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<(), Error> {
+        let value = value.serialize(Serializer)?;
+        self.entries.push(Expr::cons(Expr::symbol(key), value));
+        Ok(())
+    }
+
+    fn end(self) -> Result<ExprRef, Error> {
+        self.finish()
+    }
+}
+
+impl ser::SerializeStructVariant for SerializeAlist {
+    type Ok = ExprRef;
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<(), Error> {
+        ser::SerializeStruct::serialize_field(self, key, value)
+    }
+
+    fn end(self) -> Result<ExprRef, Error> {
+        self.finish()
+    }
+}
+
+/// Reads `Deserialize` values out of a Ruse datum.
+pub struct Deserializer {
+    datum: ExprRef,
+}
+
+impl Deserializer {
+    /// Read from the given datum.
+    pub fn new(datum: ExprRef) -> Deserializer {
+        Deserializer { datum }
+    }
+
+    /// The items of a list, vector or bytevector.
+    fn items(&self) -> Result<Vec<ExprRef>, Error> {
+        match self.datum.kind {
+            ExprKind::Vector(ref items) => Ok(items.borrow().clone()),
+            ExprKind::ByteVector(ref bytes) => Ok(bytes.borrow().iter().map(|&b| b.into_ruse()).collect()),
+            _ => self.datum.to_vec().ok_or_else(|| unexpected("a list", &self.datum)),
+        }
+    }
+
+    /// The entries of a record, hash table or association list.
+    fn entries(&self) -> Result<Vec<(ExprRef, ExprRef)>, Error> {
+        match self.datum.kind {
+            ExprKind::Record(ref record) => Ok(record
+                .fields()
+                .into_iter()
+                .map(|(name, value)| (Expr::symbol(name), value))
+                .collect()),
+            _ => Ok(map_entries(&self.datum)?),
+        }
+    }
+}
+
+impl<'de> de::Deserializer<'de> for Deserializer {
+    type Error = Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        match self.datum.kind {
+            ExprKind::Nil | ExprKind::Unspecified => visitor.visit_unit(),
+            ExprKind::Bool(b) => visitor.visit_bool(b),
+            ExprKind::Char(c) => visitor.visit_char(c),
+            ExprKind::Num(ref n) => match n.kind {
+                NumberKind::Int(i) => visitor.visit_i64(i),
+                NumberKind::Real(f) => visitor.visit_f64(f),
+                NumberKind::Rational { numerator, denominator } => {
+                    visitor.visit_f64(numerator as f64 / denominator as f64)
+                }
+            },
+            ExprKind::Str(ref s) => visitor.visit_string(s.borrow().clone()),
+            ExprKind::Symbol(s) => visitor.visit_string(s.to_string()),
+            ExprKind::ByteVector(ref bytes) => visitor.visit_byte_buf(bytes.borrow().clone()),
+            ExprKind::Pair(..) | ExprKind::Vector(..) => self.deserialize_seq(visitor),
+            ExprKind::Record(..) | ExprKind::HashTable(..) => self.deserialize_map(visitor),
+            _ => Err(unexpected("a datum", &self.datum)),
+        }
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        match self.datum.kind {
+            ExprKind::Bool(false) => visitor.visit_none(),
+            _ => visitor.visit_some(self),
+        }
+    }
+
+    fn deserialize_unit<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        match self.datum.kind {
+            ExprKind::Nil | ExprKind::Unspecified => visitor.visit_unit(),
+            _ => Err(unexpected("the empty list", &self.datum)),
+        }
+    }
+
+    fn deserialize_unit_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        self.deserialize_unit(visitor)
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_bytes<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        match self.datum.kind {
+            ExprKind::ByteVector(ref bytes) => visitor.visit_byte_buf(bytes.borrow().clone()),
+            _ => self.deserialize_seq(visitor),
+        }
+    }
+
+    fn deserialize_byte_buf<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        self.deserialize_bytes(visitor)
+    }
+
+    fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        let items = self.items()?;
+        let len = items.len();
+        let mut seq = Seq { items: items.into_iter() };
+        let value = visitor.visit_seq(&mut seq)?;
+        match seq.items.len() {
+            0 => Ok(value),
+            left => Err(unexpected(&format!("a list of {}", len - left), &self.datum)),
+        }
+    }
+
+    fn deserialize_tuple<V: Visitor<'de>>(self, _len: usize, visitor: V) -> Result<V::Value, Error> {
+        self.deserialize_seq(visitor)
+    }
+
+    fn deserialize_tuple_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _len: usize,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        self.deserialize_seq(visitor)
+    }
+
+    fn deserialize_map<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        let entries = self.entries()?;
+        visitor.visit_map(Map {
+            entries: entries.into_iter(),
+            value: None,
+        })
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        self.deserialize_map(visitor)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        let (name, fields) = variant(&self.datum)?;
+        visitor.visit_enum(Enum {
+            variant: name,
+            fields: Deserializer::new(fields),
+        })
+    }
+
+    forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        identifier ignored_any
+    }
+}
+
+/// The items of a list being read.
+struct Seq {
+    items: vec::IntoIter<ExprRef>,
+}
+
+impl<'de> de::SeqAccess<'de> for Seq {
+    type Error = Error;
+
+    fn next_element_seed<T: DeserializeSeed<'de>>(&mut self, seed: T) -> Result<Option<T::Value>, Error> {
+        match self.items.next() {
+            Some(item) => seed.deserialize(Deserializer::new(item)).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.items.len())
+    }
+}
+
+/// The entries of an association list, hash table or record being read.
+struct Map {
+    entries: vec::IntoIter<(ExprRef, ExprRef)>,
+    /// The value of the entry whose key was just read.
+    value: Option<ExprRef>,
+}
+
+impl<'de> de::MapAccess<'de> for Map {
+    type Error = Error;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(&mut self, seed: K) -> Result<Option<K::Value>, Error> {
+        match self.entries.next() {
+            Some((key, value)) => {
+                self.value = Some(value);
+                seed.deserialize(Deserializer::new(key)).map(Some)
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(&mut self, seed: V) -> Result<V::Value, Error> {
+        match self.value.take() {
+            Some(value) => seed.deserialize(Deserializer::new(value)),
+            None => Err(de::Error::custom("a map value was read without a key")),
+        }
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.entries.len())
+    }
+}
+
+/// A variant of an enum being read, and whatever follows its name.
+struct Enum {
+    variant: &'static str,
+    fields: Deserializer,
+}
+
+impl<'de> de::EnumAccess<'de> for Enum {
+    type Error = Error;
+    type Variant = Deserializer;
+
+    fn variant_seed<V: DeserializeSeed<'de>>(self, seed: V) -> Result<(V::Value, Deserializer), Error> {
+        let variant: de::value::StrDeserializer<Error> = self.variant.into_deserializer();
+        Ok((seed.deserialize(variant)?, self.fields))
+    }
+}
+
+impl<'de> de::VariantAccess<'de> for Deserializer {
+    type Error = Error;
+
+    fn unit_variant(self) -> Result<(), Error> {
+        if self.datum.is_nil() {
+            Ok(())
+        } else {
+            Err(unexpected("no fields", &self.datum))
+        }
+    }
+
+    fn newtype_variant_seed<T: DeserializeSeed<'de>>(self, seed: T) -> Result<T::Value, Error> {
+        let field = list_of(&self.datum, 1)?;
+        seed.deserialize(Deserializer::new(field[0].clone()))
+    }
+
+    fn tuple_variant<V: Visitor<'de>>(self, _len: usize, visitor: V) -> Result<V::Value, Error> {
+        de::Deserializer::deserialize_seq(self, visitor)
+    }
+
+    fn struct_variant<V: Visitor<'de>>(
+        self,
+        _fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        de::Deserializer::deserialize_map(self, visitor)
+    }
+}
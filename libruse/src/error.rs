@@ -1,18 +1,102 @@
 //! Types for handling errors in the ruse engine.
 
+use convert::ConversionError;
+use eval;
+use eval::Limit;
+use print::print;
 use read;
+use read::lex::token::Span;
+use read::parse::expr::ExprKind;
 use std::error;
 use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
 use std::result;
 
 /// The result of reading a string.
 pub type Result = result::Result<String, Error>;
 
-/// Indicates an error in lexing or parsing.
+/// Indicates an error in lexing or parsing, an exception that the program
+/// never handled, a failure to read the program, a limit it ran into, an
+/// interrupt, or an engine which couldn't be forked.
 #[derive(Debug, PartialEq)]
 pub enum Error {
     /// Indicates an error in reading.
     ReadError(read::error::Error),
+    /// Indicates an exception raised during evaluation that was never caught.
+    Uncaught(Exception),
+    /// Indicates a value given back to Rust wasn't of the type asked for.
+    Conversion(ConversionError),
+    /// Indicates a program couldn't be read from a file.
+    Io(IoError),
+    /// Indicates a program ran into one of the engine's limits, and was
+    /// stopped.
+    Limit(Limit),
+    /// Indicates a program was stopped through an `InterruptHandle`, or
+    /// didn't catch the interrupt condition it raised.
+    Interrupted,
+    /// Indicates an engine couldn't be forked, because it can reach the
+    /// given open port, as Ruse would write it.
+    Unforkable(String),
+}
+
+/// Indicates a file couldn't be read.
+#[derive(Debug, PartialEq, Clone)]
+pub struct IoError {
+    /// The file which couldn't be read.
+    pub path: PathBuf,
+    /// The kind of failure.
+    pub kind: io::ErrorKind,
+    /// What the operating system said went wrong.
+    pub message: String,
+}
+
+impl IoError {
+    /// Describe the failure to read `path`.
+    pub fn new(path: &Path, error: &io::Error) -> IoError {
+        IoError {
+            path: path.to_path_buf(),
+            kind: error.kind(),
+            message: error.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for IoError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "could not read {}: {}", self.path.display(), self.message)
+    }
+}
+
+impl error::Error for IoError {
+    fn description(&self) -> &str {
+        "a file couldn't be read"
+    }
+}
+
+/// An exception raised by a Ruse program, in a form Rust can inspect.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Exception {
+    /// The message the error was created with.
+    pub message: String,
+    /// The irritants the error was created with, as Ruse would write them.
+    pub irritants: Vec<String>,
+    /// Where in the program the exception was raised.
+    pub span: Option<Span>,
+}
+
+impl fmt::Display for Exception {
+    /// Print the message, irritants and location, much as Ruse would.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.message)?;
+        for irritant in &self.irritants {
+            write!(f, " {}", irritant)?;
+        }
+        if let Some(span) = self.span {
+            write!(f, " (at {})", span)?;
+        }
+        Ok(())
+    }
 }
 
 impl error::Error for Error {
@@ -20,6 +104,12 @@ impl error::Error for Error {
     fn description(&self) -> &str {
         match *self {
             Error::ReadError(..) => "an error occured during reading",
+            Error::Uncaught(..) => "an uncaught exception occured during evaluation",
+            Error::Conversion(..) => "a value couldn't be converted to a Rust type",
+            Error::Io(..) => "a file couldn't be read",
+            Error::Limit(..) => "a program ran into one of the engine's limits",
+            Error::Interrupted => "a program was interrupted",
+            Error::Unforkable(..) => "an engine holding an open port couldn't be forked",
         }
     }
 
@@ -27,6 +117,12 @@ impl error::Error for Error {
     fn cause(&self) -> Option<&error::Error> {
         match *self {
             Error::ReadError(ref error) => Some(error),
+            Error::Uncaught(..) => None,
+            Error::Conversion(ref error) => Some(error),
+            Error::Io(ref error) => Some(error),
+            Error::Limit(..) => None,
+            Error::Interrupted => None,
+            Error::Unforkable(..) => None,
         }
     }
 }
@@ -36,6 +132,12 @@ impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
             Error::ReadError(ref error) => write!(f, "{}", error),
+            Error::Uncaught(ref exception) => write!(f, "{}", exception),
+            Error::Conversion(ref error) => write!(f, "{}", error),
+            Error::Io(ref error) => write!(f, "{}", error),
+            Error::Limit(limit) => write!(f, "{}", limit),
+            Error::Interrupted => write!(f, "the program was interrupted"),
+            Error::Unforkable(ref port) => write!(f, "can't fork an engine holding an open port: {}", port),
         }
     }
 }
@@ -46,3 +148,52 @@ impl From<read::error::Error> for Error {
         Error::ReadError(err)
     }
 }
+
+impl From<ConversionError> for Error {
+    /// Convert from a failed conversion into a top-level ruse Error.
+    fn from(err: ConversionError) -> Error {
+        Error::Conversion(err)
+    }
+}
+
+impl From<IoError> for Error {
+    /// Convert from a failure to read a file into a top-level ruse Error.
+    fn from(err: IoError) -> Error {
+        Error::Io(err)
+    }
+}
+
+impl From<eval::Error> for Error {
+    /// Describe an uncaught exception. Error objects give their message and
+    /// irritants; anything else raised becomes the lone irritant.
+    fn from(err: eval::Error) -> Error {
+        match err {
+            eval::Error::Uncaught { payload, span } => {
+                let write = |expr| print(expr);
+
+                let exception = match payload.kind {
+                    ExprKind::Condition(ref condition) => Exception {
+                        message: condition.message.clone(),
+                        irritants: condition
+                            .irritants
+                            .to_vec()
+                            .unwrap_or_default()
+                            .iter()
+                            .map(|irritant| write(irritant))
+                            .collect(),
+                        span: condition.span.or(span),
+                    },
+                    _ => Exception {
+                        message: "uncaught exception".to_string(),
+                        irritants: vec![write(&payload)],
+                        span,
+                    },
+                };
+
+                Error::Uncaught(exception)
+            }
+            eval::Error::Limit(limit) => Error::Limit(limit),
+            eval::Error::Interrupted => Error::Interrupted,
+        }
+    }
+}
@@ -0,0 +1,136 @@
+//! Calling functions across the boundary: Rust functions from Ruse, and
+//! Ruse procedures from Rust.
+
+use convert::{ConversionError, FromRuse, IntoRuse};
+use error::Error;
+use eval::Failure;
+use read::parse::expr::ExprRef;
+use Engine;
+
+/// A Rust function which can be registered as a Ruse procedure.
+///
+/// This is implemented for functions and closures of up to eight arguments,
+/// where each argument type can be converted from Ruse and the return type
+/// can be converted into Ruse. `Args` is the tuple of argument types, which
+/// only serves to tell the implementations apart. Engines can move between
+/// threads, so the functions registered with them have to be `Send` and
+/// `Sync` as well.
+pub trait HostFunction<Args>: Send + Sync + 'static {
+    /// How many arguments the function takes.
+    fn arity(&self) -> usize;
+
+    /// Convert the arguments, call the function, and convert what it gives
+    /// back. `name` is what the procedure is known as, for error messages.
+    fn call(&self, name: &str, args: Vec<ExprRef>) -> Result<ExprRef, Failure>;
+}
+
+/// Convert an argument, or describe why it couldn't be.
+fn argument<T: FromRuse>(name: &str, arg: &ExprRef) -> Result<T, Failure> {
+    T::from_ruse(arg).map_err(|error| Failure {
+        message: format!("{}: expected {}", name, error.expected),
+        irritants: vec![arg.clone()],
+    })
+}
+
+macro_rules! host_function {
+    ($count:expr; $($arg:ident),*) => {
+        impl<F, R, $($arg),*> HostFunction<($($arg,)*)> for F
+        where
+            F: Fn($($arg),*) -> R + Send + Sync + 'static,
+            R: IntoRuse,
+            $($arg: FromRuse),*
+        {
+            fn arity(&self) -> usize {
+                $count
+            }
+
+            #[allow(non_snake_case, unused_variables, unused_mut)]
+            fn call(&self, name: &str, args: Vec<ExprRef>) -> Result<ExprRef, Failure> {
+                let mut args = args.iter();
+                $(
+                    let $arg = match args.next() {
+                        Some(arg) => argument::<$arg>(name, arg)?,
+                        None => unreachable!("the evaluator checks arity"),
+                    };
+                )*
+                Ok(self($($arg),*).into_ruse())
+            }
+        }
+    };
+}
+
+host_function!(0;);
+host_function!(1; A);
+host_function!(2; A, B);
+host_function!(3; A, B, C);
+host_function!(4; A, B, C, D);
+host_function!(5; A, B, C, D, E);
+host_function!(6; A, B, C, D, E, G);
+host_function!(7; A, B, C, D, E, G, H);
+host_function!(8; A, B, C, D, E, G, H, I);
+
+/// A Ruse procedure, which Rust can hold on to and call as often as it
+/// likes. Holding it keeps the procedure alive.
+#[derive(Clone)]
+pub struct Procedure {
+    value: ExprRef,
+}
+
+impl Procedure {
+    /// Call the procedure with a tuple of arguments, converting what it
+    /// gives back to `R`. Exceptions the procedure doesn't handle come back
+    /// as errors.
+    pub fn call<R, A>(&self, engine: &mut Engine, args: A) -> Result<R, Error>
+    where
+        R: FromRuse,
+        A: IntoArgs,
+    {
+        let result = engine.evaluator.call(self.value.clone(), args.into_args())?;
+        Ok(R::from_ruse(&result)?)
+    }
+}
+
+impl FromRuse for Procedure {
+    fn from_ruse(value: &ExprRef) -> Result<Procedure, ConversionError> {
+        if value.is_procedure() {
+            Ok(Procedure { value: value.clone() })
+        } else {
+            Err(ConversionError::new("a procedure", value))
+        }
+    }
+}
+
+impl IntoRuse for Procedure {
+    fn into_ruse(self) -> ExprRef {
+        self.value
+    }
+}
+
+/// The arguments of a call from Rust: a tuple of up to eight values which
+/// can each be converted into Ruse.
+pub trait IntoArgs {
+    /// Convert each of the arguments.
+    fn into_args(self) -> Vec<ExprRef>;
+}
+
+macro_rules! into_args {
+    ($($arg:ident),*) => {
+        impl<$($arg: IntoRuse),*> IntoArgs for ($($arg,)*) {
+            #[allow(non_snake_case)]
+            fn into_args(self) -> Vec<ExprRef> {
+                let ($($arg,)*) = self;
+                vec![$($arg.into_ruse()),*]
+            }
+        }
+    };
+}
+
+into_args!();
+into_args!(A);
+into_args!(A, B);
+into_args!(A, B, C);
+into_args!(A, B, C, D);
+into_args!(A, B, C, D, E);
+into_args!(A, B, C, D, E, G);
+into_args!(A, B, C, D, E, G, H);
+into_args!(A, B, C, D, E, G, H, I);
@@ -0,0 +1,106 @@
+//! Handing Rust values to Ruse as host objects.
+//!
+//! A host object wraps a Rust value, like a database connection or a game
+//! entity, so scripts can hold on to it and pass it back to Rust. Scripts
+//! can't look inside it themselves. Instead the Rust side registers
+//! procedures which take a `Host<T>` as one of their arguments, and works
+//! on the value through it.
+//!
+//! A Rust value can hold Ruse values too, like callbacks a script handed
+//! over. Made with `Host::traced`, it shows them to the collector, so a
+//! cycle running from the value to a Ruse value and back can be freed once
+//! the script lets go of it. Otherwise the collector treats what it holds
+//! as held from outside the heap, and such a cycle is never freed.
+
+use atomic_refcell::{AtomicRef, AtomicRefMut};
+use convert::{ConversionError, FromRuse, IntoRuse};
+use read::parse::expr::{short_type_name, Expr, ExprKind, ExprRef, HostObject, Trace};
+use std::any::Any;
+use std::marker::PhantomData;
+
+/// A Rust value shared with Ruse.
+///
+/// Cloning a `Host` gives another handle to the same value, and converting
+/// it into Ruse gives a value that is `eq?` to any other made from it. The
+/// value is borrowed like a `RefCell`, so borrowing it mutably while it's
+/// already borrowed panics, even from another thread. Since the value can
+/// end up on any thread an engine moves to, it has to be `Send` and `Sync`.
+pub struct Host<T> {
+    object: HostObject,
+    value: PhantomData<T>,
+}
+
+impl<T: Any + Send + Sync> Host<T> {
+    /// Wrap a value up to be handed to Ruse.
+    pub fn new(value: T) -> Host<T> {
+        Host {
+            object: HostObject::new(value),
+            value: PhantomData,
+        }
+    }
+
+    /// Wrap up a value which holds Ruse values, letting the collector look
+    /// inside it. It does so only while no `Host` handle to the value is
+    /// kept on the Rust side, since that would keep the value alive too.
+    pub fn traced(value: T) -> Host<T>
+    where
+        T: Trace,
+    {
+        Host {
+            object: HostObject::traced(value),
+            value: PhantomData,
+        }
+    }
+
+    /// Borrow the value.
+    pub fn borrow(&self) -> AtomicRef<T> {
+        AtomicRef::map(self.object.value.borrow(), |value| {
+            value.downcast_ref().expect("a host object holds the type it was made with")
+        })
+    }
+
+    /// Borrow the value mutably.
+    pub fn borrow_mut(&self) -> AtomicRefMut<T> {
+        AtomicRefMut::map(self.object.value.borrow_mut(), |value| {
+            value.downcast_mut().expect("a host object holds the type it was made with")
+        })
+    }
+}
+
+impl<T> Clone for Host<T> {
+    fn clone(&self) -> Host<T> {
+        Host {
+            object: self.object.clone(),
+            value: PhantomData,
+        }
+    }
+}
+
+impl<T: Any + Send + Sync> FromRuse for Host<T> {
+    fn from_ruse(value: &ExprRef) -> Result<Host<T>, ConversionError> {
+        match value.kind {
+            ExprKind::Host(ref object) if object.is::<T>() => Ok(Host {
+                object: object.clone(),
+                value: PhantomData,
+            }),
+            _ => {
+                let expected = format!("a host {}", short_type_name::<T>());
+                Err(ConversionError::new(expected, value))
+            }
+        }
+    }
+}
+
+impl<T: Any + Send + Sync> IntoRuse for Host<T> {
+    fn into_ruse(self) -> ExprRef {
+        Expr::new(ExprKind::Host(self.object)).alloc()
+    }
+}
+
+/// Whether `value` is a host object holding a `T`.
+pub fn is_host<T: Any>(value: &ExprRef) -> bool {
+    match value.kind {
+        ExprKind::Host(ref object) => object.is::<T>(),
+        _ => false,
+    }
+}
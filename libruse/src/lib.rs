@@ -12,19 +12,45 @@
 
 #![deny(missing_docs)]
 
+extern crate atomic_refcell;
 extern crate libruse_read as read;
 extern crate libruse_eval as eval;
 extern crate libruse_print as print;
+extern crate libruse_derive;
+#[cfg(feature = "serde")]
+#[macro_use]
+extern crate serde;
+// Lets the derives, which name `::libruse`, be used inside the crate too.
+extern crate self as libruse;
 
+pub mod convert;
+#[cfg(feature = "serde")]
+pub mod datum;
 pub mod error;
+pub mod function;
+pub mod host;
+pub mod pool;
 
+pub use convert::{ConversionError, FromRuse, IntoRuse};
+pub use function::{HostFunction, IntoArgs, Procedure};
+pub use host::Host;
+pub use pool::{EnginePool, PooledEngine};
+pub use libruse_derive::{FromRuse, IntoRuse};
+pub use read::parse::expr::{Expr, ExprKind, ExprRef, Trace};
+pub use eval::{InterruptHandle, Library, Limit, Limits};
+pub use read::parse::heap::Mode as GcMode;
+pub use read::parse::heap::Stats as GcStats;
+
+use std::any::Any;
 use std::path::Path;
 use std::fs::File;
 use std::io::Read;
+use std::time::Duration;
 
-use error::Result;
+use error::{Error, IoError, Result};
+use read::parse::symbol::keywords;
 use read::read;
-use eval::eval;
+use eval::Evaluator;
 use print::print;
 
 /// The entry point for running Ruse programs.
@@ -32,12 +58,18 @@ use print::print;
 /// The engine is the main interface between Ruse and Rust. It is where
 /// Rust functions are registered, and where Ruse functions are run.
 ///
-/// Eventually the Engine will store all Rust-side function bindings, and
-/// provide a way for the user to register new bindings. There will also
-/// be a utility to register bindings on the Ruse side, to be callable
-/// from the Rust side.
+/// Rust functions are registered with `register_fn`, and Ruse procedures
+/// are called from Rust with `call`, or kept as a `Procedure` to call later.
+/// Rust values are handed to Ruse wrapped in a `Host`.
+///
+/// An engine can be moved to another thread, heap and all, but can only be
+/// used by one thread at a time. Hosts which run programs on many threads
+/// can share an `EnginePool` between them, which hands each one an engine
+/// of its own.
 #[derive(Default)]
-pub struct Engine {}
+pub struct Engine {
+    evaluator: Evaluator,
+}
 
 impl Engine {
     /// Create a new Engine.
@@ -45,21 +77,2050 @@ impl Engine {
         Default::default()
     }
 
+    /// Create an engine for running untrusted programs, which can only use
+    /// the procedures of the given libraries, those allowed with `allow`,
+    /// and those registered from Rust. A program which refers to anything
+    /// else fails with an unbound variable error before any of it runs.
+    pub fn with_libraries(libraries: &[Library]) -> Engine {
+        Engine {
+            evaluator: Evaluator::with_libraries(libraries),
+        }
+    }
+
+    /// Let programs use one standard procedure, like `hash-table-set!`,
+    /// without the rest of its library. Returns `false` if there's no
+    /// standard procedure called `name`.
+    pub fn allow(&mut self, name: &str) -> bool {
+        self.evaluator.allow(name)
+    }
+
+    /// Make a Rust function available to Ruse programs as a procedure.
+    ///
+    /// Its arguments are converted from Ruse values and its result into one,
+    /// so `engine.register_fn("add", |a: i64, b: i64| a + b)` makes `add`
+    /// take two integers. Calling it with the wrong number of arguments, or
+    /// with arguments that don't convert, raises an error in Ruse.
+    pub fn register_fn<Args, F: HostFunction<Args>>(&mut self, name: &str, f: F) {
+        let arity = f.arity();
+        let procedure = name.to_string();
+        self.evaluator
+            .define_procedure(name, arity, Some(arity), move |args| f.call(&procedure, args));
+    }
+
+    /// Let Ruse programs tell host objects holding a `T` apart from other
+    /// values, with the predicate `name?`. Procedures working on them are
+    /// registered with `register_fn`, taking a `Host<T>` argument. If a `T`
+    /// holds Ruse values, make it with `Host::traced`, or a cycle through
+    /// it is never collected.
+    pub fn register_type<T: Any>(&mut self, name: &str) {
+        self.register_fn(&format!("{}?", name), |value: ExprRef| host::is_host::<T>(&value));
+    }
+
+    /// Call the global procedure `name` with a tuple of arguments, and
+    /// convert what it gives back to `R`, as in
+    /// `engine.call::<i64, _>("add", (1, 2))`.
+    pub fn call<R: FromRuse, A: IntoArgs>(
+        &mut self,
+        name: &str,
+        args: A,
+    ) -> std::result::Result<R, Error> {
+        self.procedure(name)?.call(self, args)
+    }
+
+    /// The global procedure `name`, to be called later.
+    pub fn procedure(&mut self, name: &str) -> std::result::Result<Procedure, Error> {
+        let value = self.evaluator.procedure(name)?;
+        Ok(Procedure::from_ruse(&value)?)
+    }
+
     /// Run the engine on a specific program.
     pub fn run<S: AsRef<str>>(&mut self, s: S) -> Result {
-        let r = read(s).unwrap();
-        let e = eval(r).unwrap();
-        let p = print(e).unwrap();
-        Ok(p)
+        let e = self.eval(s)?;
+        Ok(print(&e))
     }
 
-    /// Run the engine on a program from a file.
+    /// Run the engine on a program, giving back the value it returns rather
+    /// than writing it out. If it returns several values, they come back
+    /// together as one.
+    pub fn eval<S: AsRef<str>>(&mut self, s: S) -> std::result::Result<ExprRef, Error> {
+        let r = read(s)?;
+        Ok(self.evaluator.eval(r)?)
+    }
+
+    /// Run the engine on a program, printing each of the values it returns.
+    pub fn run_values<S: AsRef<str>>(
+        &mut self,
+        s: S,
+    ) -> std::result::Result<Vec<String>, Error> {
+        let r = read(s)?;
+        let values = self.evaluator.eval_values(r)?;
+        Ok(values.iter().map(|value| print(value)).collect())
+    }
+
+    /// The current value of a parameter, as Ruse would write it.
+    pub fn parameter(&mut self, name: &str) -> Result {
+        let value = self.evaluator.parameter(name)?;
+        Ok(print(&value))
+    }
+
+    /// Set a parameter to the value of a Ruse expression, as the
+    /// parameter's converter sees it.
+    pub fn set_parameter<S: AsRef<str>>(
+        &mut self,
+        name: &str,
+        value: S,
+    ) -> std::result::Result<(), Error> {
+        let r = read(value)?;
+        let value = self.evaluator.eval(r)?;
+        self.evaluator.set_parameter(name, value)?;
+        Ok(())
+    }
+
+    /// Limit the resources programs run by the engine can use: how many
+    /// steps they take, how deeply their evaluation nests, how big the heap
+    /// gets, and how long they run for. A program which runs into a limit
+    /// is stopped with `Error::Limit`, and the engine can go on to run
+    /// something else.
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.evaluator.set_limits(limits)
+    }
+
+    /// A handle for interrupting the programs the engine runs, from any
+    /// thread. `interrupt` raises a condition which the program can catch
+    /// with `interrupt-condition?`, while `stop` ends it straight away.
+    /// Either way, a program which doesn't carry on fails with
+    /// `Error::Interrupted`.
+    pub fn interrupt_handle(&self) -> InterruptHandle {
+        self.evaluator.interrupt_handle()
+    }
+
+    /// Make a copy of the engine, with a heap of its own. It has every
+    /// definition, registered function and setting the engine has, but what
+    /// either does from then on isn't seen by the other. Host objects aren't
+    /// copied, and are shared between the two. An engine which can reach an
+    /// open port can't be forked, and fails with `Error::Unforkable`.
+    pub fn fork(&self) -> std::result::Result<Engine, Error> {
+        match self.evaluator.fork() {
+            Ok(evaluator) => Ok(Engine { evaluator }),
+            Err(port) => Err(Error::Unforkable(print(&port))),
+        }
+    }
+
+    /// Choose whether modifying a literal constant, like the list read from
+    /// `'(1 2)` or the vector read from `#(1 2)`, raises an error. It does by
+    /// default, as R7RS asks; turning the check off lets older programs
+    /// which modify their constants keep running.
+    pub fn set_literal_checks(&mut self, enabled: bool) {
+        self.evaluator.set_literal_checks(enabled)
+    }
+
+    /// Statistics about the engine's heap and its collector.
+    pub fn gc_stats(&self) -> GcStats {
+        self.evaluator.gc_stats()
+    }
+
+    /// Collect garbage now, rather than waiting for the heap to grow, and
+    /// give back how many values were freed.
+    pub fn collect_garbage(&mut self) -> usize {
+        self.evaluator.collect_garbage()
+    }
+
+    /// Choose how the collector runs: in one pause per collection, or in
+    /// slices which each try to stay within a pause target.
+    pub fn set_gc_mode(&mut self, mode: GcMode) {
+        self.evaluator.set_gc_mode(mode)
+    }
+
+    /// How long the collector's most recent pauses took, oldest first.
+    pub fn gc_pauses(&self) -> Vec<Duration> {
+        self.evaluator.gc_pauses()
+    }
+
+    /// Expand the macros in a program without running it.
     ///
-    /// TODO: Remove the unwrapping from this function.
+    /// Each top-level form of the expanded program is written on a line of
+    /// its own, after a comment saying where it came from in the source.
+    pub fn expand<S: AsRef<str>>(&mut self, s: S) -> Result {
+        let r = read(s)?;
+        let e = self.evaluator.expand(r)?;
+
+        let forms = match e.car() {
+            Some(ref head) if head.as_symbol() == Some(keywords::BEGIN) => {
+                e.to_vec().unwrap_or_default().split_off(1)
+            }
+            _ => vec![e],
+        };
+
+        let mut out = String::new();
+        for form in forms {
+            let p = print(&form);
+            if p.is_empty() {
+                continue;
+            }
+            if let Some(span) = form.span {
+                out.push_str(&format!("; {}\n", span));
+            }
+            out.push_str(&p);
+            out.push('\n');
+        }
+        Ok(out)
+    }
+
+    /// Run the engine on a program from a file.
     pub fn run_file<S: AsRef<Path>>(&mut self, s: S) -> Result {
-        let mut f = File::open(s).unwrap();
+        let path = s.as_ref();
         let mut buffer = String::new();
-        f.read_to_string(&mut buffer).expect("could not read file");
+        File::open(path)
+            .and_then(|mut f| f.read_to_string(&mut buffer))
+            .map_err(|err| IoError::new(path, &err))?;
         self.run(buffer)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use error::{Error, Exception, IoError};
+    use eval::Evaluator;
+    use print::print;
+    use read::read;
+    use read::lex::token::{Location, Span};
+    use std::collections::HashMap;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::time::Duration;
+    use std::{env, fs, io, thread};
+    use {ConversionError, Engine, Expr, ExprKind, ExprRef, FromRuse, GcMode, Host, IntoRuse};
+    use {EnginePool, Library, Limit, Limits, Trace};
+    #[cfg(feature = "serde")]
+    use datum::{from_datum, to_datum};
+    #[cfg(feature = "serde")]
+    use serde::{Deserialize, Serialize, Serializer};
+    #[cfg(feature = "serde")]
+    use std::collections::BTreeMap;
+
+    fn run(program: &str) -> Result<String, Error> {
+        Engine::new().run(program)
+    }
+
+    // Number the fresh symbols in printed output from 1, in the order they
+    // first appear. The numbers themselves depend on what else the process
+    // has made symbols for.
+    fn renumbered(printed: &str) -> String {
+        let mut numbers: HashMap<String, usize> = HashMap::new();
+        let mut result = String::new();
+        let mut chars = printed.chars().peekable();
+        let mut previous = ' ';
+        while let Some(c) = chars.next() {
+            result.push(c);
+            let in_symbol = !previous.is_whitespace() && previous != '(' && previous != ')';
+            previous = c;
+            if c != '#' || !in_symbol {
+                continue;
+            }
+            let mut digits = String::new();
+            while let Some(&d) = chars.peek().filter(|d| d.is_ascii_digit()) {
+                digits.push(d);
+                chars.next();
+            }
+            if !digits.is_empty() {
+                let next = numbers.len() + 1;
+                result.push_str(&numbers.entry(digits).or_insert(next).to_string());
+                previous = '0';
+            }
+        }
+        result
+    }
+
+    #[test]
+    fn guard_catches_a_raised_symbol() {
+        let result = run("(guard (e ((symbol? e) (quote caught))) (raise (quote oops)))");
+        assert_eq!(result, Ok("caught".to_string()));
+    }
+
+    #[test]
+    fn guard_passes_the_test_to_an_arrow_clause() {
+        let result = run(
+            "(guard (e ((cdr e) => car))
+               (raise (cons 1 (list 2 3))))",
+        );
+        assert_eq!(result, Ok("2".to_string()));
+    }
+
+    #[test]
+    fn guard_reraises_when_no_clause_matches() {
+        let result = run(
+            "(guard (outer (#t (list (quote outer) outer)))
+               (guard (inner ((string? inner) inner))
+                 (raise 42)))",
+        );
+        assert_eq!(result, Ok("(outer 42)".to_string()));
+    }
+
+    #[test]
+    fn raise_continuable_returns_the_handler_result() {
+        let result = run(
+            "(with-exception-handler
+               (lambda (c) 42)
+               (lambda () (+ (raise-continuable (quote oops)) 1)))",
+        );
+        assert_eq!(result, Ok("43".to_string()));
+    }
+
+    #[test]
+    fn error_objects_carry_their_irritants() {
+        let result = run(
+            "(guard (e (#t (error-object-irritants e)))
+               (error \"bad\" 1 (quote two) \"three\"))",
+        );
+        assert_eq!(result, Ok("(1 two \"three\")".to_string()));
+    }
+
+    #[test]
+    fn runtime_errors_are_error_objects() {
+        let result = run("(guard (e ((error-object? e) (error-object-message e))) (car 1))");
+        assert_eq!(result, Ok("\"car: expected a pair\"".to_string()));
+    }
+
+    #[test]
+    fn uncaught_errors_reach_rust_with_their_span() {
+        let result = run("(+ 1 2)\n(error \"bad thing\" 1 (quote x))");
+        let expected = Err(Error::Uncaught(Exception {
+            message: "bad thing".to_string(),
+            irritants: vec!["1".to_string(), "x".to_string()],
+            span: Some(Span::new(Location::new(2, 1), Location::new(2, 32))),
+        }));
+        assert_eq!(result, expected);
+    }
+
+    #[test]
+    fn uncaught_non_error_objects_become_irritants() {
+        let result = run("(raise (quote oops))");
+        match result {
+            Err(Error::Uncaught(exception)) => {
+                assert_eq!(exception.irritants, vec!["oops".to_string()]);
+            }
+            other => panic!("expected an uncaught exception, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn returning_from_a_raise_handler_is_an_error() {
+        let result = run("(with-exception-handler (lambda (c) 0) (lambda () (raise 1)))");
+        match result {
+            Err(Error::Uncaught(exception)) => {
+                assert_eq!(exception.irritants, vec!["1".to_string()]);
+            }
+            other => panic!("expected an uncaught exception, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn syntax_rules_expands_with_ellipses() {
+        let result = run(
+            "(define-syntax my-let
+               (syntax-rules ()
+                 ((_ ((name val) ...) body1 body2 ...)
+                  ((lambda (name ...) body1 body2 ...) val ...))))
+             (my-let ((a 1) (b 2)) (+ a b))",
+        );
+        assert_eq!(result, Ok("3".to_string()));
+    }
+
+    #[test]
+    fn macro_introduced_bindings_do_not_capture_user_variables() {
+        let result = run(
+            "(define-syntax my-or
+               (syntax-rules ()
+                 ((_) #f)
+                 ((_ e) e)
+                 ((_ e r ...) (let ((t e)) (if t t (my-or r ...))))))
+             (define t 5)
+             (my-or #f t)",
+        );
+        assert_eq!(result, Ok("5".to_string()));
+    }
+
+    #[test]
+    fn macro_references_ignore_local_rebindings_at_the_use_site() {
+        let result = run(
+            "(define-syntax my-if
+               (syntax-rules ()
+                 ((_ c a b) (cond (c a) (else b)))))
+             (let ((if list) (else #f))
+               (my-if #f 1 2))",
+        );
+        assert_eq!(result, Ok("2".to_string()));
+    }
+
+    #[test]
+    fn literals_match_only_their_own_keyword() {
+        let result = run(
+            "(define-syntax arrow
+               (syntax-rules (=>)
+                 ((_ a => b) (list a b))
+                 ((_ a b c) (quote no-arrow))))
+             (list (arrow 1 => 2) (arrow 1 2 3))",
+        );
+        assert_eq!(result, Ok("((1 2) no-arrow)".to_string()));
+    }
+
+    #[test]
+    fn nested_ellipses_follow_their_pattern_depth() {
+        let result = run(
+            "(define-syntax flatten
+               (syntax-rules ()
+                 ((_ (a b ...) ...) (quote (a ... b ... ...)))))
+             (flatten (1 2 3) (4 5))",
+        );
+        assert_eq!(result, Ok("(1 4 2 3 5)".to_string()));
+    }
+
+    #[test]
+    fn patterns_can_continue_after_an_ellipsis_and_match_vectors() {
+        let result = run(
+            "(define-syntax ends
+               (syntax-rules ()
+                 ((_ #(first middle ... last)) (list first last))))
+             (ends #(1 2 3 4))",
+        );
+        assert_eq!(result, Ok("(1 4)".to_string()));
+    }
+
+    #[test]
+    fn custom_ellipses_and_escaped_ellipses_are_supported() {
+        let result = run(
+            "(define-syntax my-list
+               (syntax-rules ::: ()
+                 ((_ x :::) (list x :::))))
+             (define-syntax quoted-dots
+               (syntax-rules ()
+                 ((_) (quote (... ...)))))
+             (list (my-list 1 2 3) (quoted-dots))",
+        );
+        assert_eq!(result, Ok("((1 2 3) ...)".to_string()));
+    }
+
+    #[test]
+    fn macro_temporaries_do_not_clash_with_user_variables_of_the_same_name() {
+        let result = run(
+            "(define-syntax swap!
+               (syntax-rules ()
+                 ((_ a b) (let ((tmp a)) (set! a b) (set! b tmp)))))
+             (define tmp 1)
+             (define other 2)
+             (swap! tmp other)
+             (list tmp other)",
+        );
+        assert_eq!(result, Ok("(2 1)".to_string()));
+    }
+
+    #[test]
+    fn letrec_syntax_macros_can_refer_to_each_other() {
+        let result = run(
+            "(letrec-syntax
+                 ((ev? (syntax-rules () ((_) #t) ((_ x . r) (od? . r))))
+                  (od? (syntax-rules () ((_) #f) ((_ x . r) (ev? . r)))))
+               (list (ev? 1 2 3 4) (od? 1 2 3 4)))",
+        );
+        assert_eq!(result, Ok("(#t #f)".to_string()));
+    }
+
+    #[test]
+    fn let_syntax_macros_see_the_outer_environment() {
+        let result = run(
+            "(define x (quote outer))
+             (let ((x (quote inner)))
+               (let-syntax ((get-x (syntax-rules () ((_) x))))
+                 (let ((x (quote shadowed)))
+                   (get-x))))",
+        );
+        assert_eq!(result, Ok("inner".to_string()));
+    }
+
+    #[test]
+    fn explicit_renaming_macros_stay_hygienic() {
+        let result = run(
+            "(define-syntax swap!
+               (er-macro-transformer
+                 (lambda (form rename compare)
+                   (let ((a (car (cdr form)))
+                         (b (car (cdr (cdr form)))))
+                     (list (rename (quote let)) (list (list (rename (quote tmp)) a))
+                           (list (rename (quote set!)) a b)
+                           (list (rename (quote set!)) b (rename (quote tmp))))))))
+             (define tmp 1)
+             (define other 2)
+             (swap! tmp other)
+             (list tmp other)",
+        );
+        assert_eq!(result, Ok("(2 1)".to_string()));
+    }
+
+    #[test]
+    fn explicit_renaming_macros_compare_keywords_by_binding() {
+        let result = run(
+            "(define-syntax which
+               (er-macro-transformer
+                 (lambda (form rename compare)
+                   (if (compare (car (cdr form)) (rename (quote else)))
+                       (quote (quote else))
+                       (quote (quote other))))))
+             (list (which else) (let ((else 1)) (which else)))",
+        );
+        assert_eq!(result, Ok("(else other)".to_string()));
+    }
+
+    #[test]
+    fn implicit_renaming_macros_can_inject_bindings() {
+        let result = run(
+            "(define-syntax aif
+               (ir-macro-transformer
+                 (lambda (form inject compare)
+                   (let ((test (car (cdr form)))
+                         (then (car (cdr (cdr form)))))
+                     (list (quote let) (list (list (inject (quote it)) test))
+                           (list (quote if) (inject (quote it)) then #f))))))
+             (let ((let list))
+               (aif (car (let 42)) (+ it 1)))",
+        );
+        assert_eq!(result, Ok("43".to_string()));
+    }
+
+    #[test]
+    fn expand_shows_the_core_forms_with_their_spans() {
+        let result = Engine::new().expand(
+            "(define-syntax my-or
+               (syntax-rules ()
+                 ((_ a b) (let ((t a)) (if t t b)))))
+             (define (f t) (my-or #f t))",
+        );
+        let expected = "; line 4, column 14\n\
+                        (define (f t) (let ((t#1 #f)) (if t#1 t#1 t)))\n";
+        assert_eq!(result.map(|expanded| renumbered(&expanded)), Ok(expected.to_string()));
+    }
+
+    #[test]
+    fn expand_once_leaves_nested_macro_uses_alone() {
+        let mut evaluator = Evaluator::new();
+        let definition = read(
+            "(define-syntax my-or
+               (syntax-rules ()
+                 ((_) #f)
+                 ((_ e r ...) (if e e (my-or r ...)))))",
+        );
+        evaluator.eval(definition.unwrap()).unwrap();
+
+        let expansion = evaluator.expand_once(read("(my-or 1 2)").unwrap()).unwrap();
+        assert_eq!(print(&expansion), "(if 1 1 (my-or 2))");
+    }
+
+    #[test]
+    fn macros_expanded_in_an_environment_run_on_the_callers_evaluator() {
+        let mut evaluator = Evaluator::new();
+        let program = read(
+            "(let ()
+               (define-syntax forever
+                 (er-macro-transformer
+                   (lambda (form rename compare) (let loop () (loop)))))
+               (lambda () #f))",
+        );
+        let closure = evaluator.eval(program.unwrap()).unwrap();
+        let scope = match closure.kind {
+            ExprKind::Closure(ref closure) => closure.env.clone(),
+            _ => panic!("expected a closure, got {}", print(&closure)),
+        };
+
+        evaluator.set_limits(Limits {
+            fuel: Some(10_000),
+            ..Limits::default()
+        });
+        match eval::expand(&mut evaluator, read("(forever)").unwrap(), &scope) {
+            Err(eval::Error::Limit(Limit::Fuel)) => {}
+            Err(other) => panic!("expected to run out of fuel, got {:?}", other),
+            Ok(expansion) => panic!("expected to run out of fuel, got {}", print(&expansion)),
+        }
+    }
+
+    #[test]
+    fn records_print_their_fields() {
+        let result = run(
+            "(define-record-type <point>
+               (make-point x y)
+               point?
+               (x point-x set-point-x!)
+               (y point-y))
+             (define p (make-point 1 2))
+             (set-point-x! p 10)
+             (list p (point? p) (point? 5) (point-x p) (point-y p))",
+        );
+        assert_eq!(result, Ok("(#<point x: 10 y: 2> #t #f 10 2)".to_string()));
+    }
+
+    #[test]
+    fn record_accessors_reject_other_types() {
+        let result = run(
+            "(define-record-type <point> (make-point x y) point? (x point-x) (y point-y))
+             (define-record-type <pare> (kons x y) pare? (x kar) (y kdr))
+             (guard (e (#t (error-object-message e)))
+               (point-x (kons 1 2)))",
+        );
+        assert_eq!(result, Ok("\"point-x: expected a point\"".to_string()));
+    }
+
+    #[test]
+    fn records_are_inspectable_by_field_name() {
+        let mut evaluator = Evaluator::new();
+        let program = read(
+            "(define-record-type <account> (make-account owner balance) account?
+               (owner account-owner) (balance account-balance))
+             (make-account \"ada\" 100)",
+        );
+        let account = evaluator.eval(program.unwrap()).unwrap();
+
+        let record = account.as_record().unwrap();
+        assert_eq!(record.record_type.short_name(), "account");
+        assert_eq!(print(&record.get("balance").unwrap()), "100");
+        assert!(record.get("missing").is_none());
+    }
+
+    #[test]
+    fn call_with_values_spreads_values_over_the_consumer() {
+        let result = run("(call-with-values (lambda () (values 1 2 3)) list)");
+        assert_eq!(result, Ok("(1 2 3)".to_string()));
+    }
+
+    #[test]
+    fn let_values_binds_proper_dotted_and_lone_formals() {
+        let result = run(
+            "(let-values (((a b) (values 1 2)) ((c . d) (values 3 4 5)) (e (values 6 7)))
+               (list a b c d e))",
+        );
+        assert_eq!(result, Ok("(1 2 3 (4 5) (6 7))".to_string()));
+    }
+
+    #[test]
+    fn let_star_values_sees_earlier_bindings() {
+        let result = run(
+            "(let*-values (((a b) (values 1 2)) ((c) (values (+ a b))))
+               c)",
+        );
+        assert_eq!(result, Ok("3".to_string()));
+    }
+
+    #[test]
+    fn define_values_and_receive_bind_several_names() {
+        let result = run(
+            "(define-values (q r) (values 7 2))
+             (receive (x . rest) (values q r 9) (list x rest))",
+        );
+        assert_eq!(result, Ok("(7 (2 9))".to_string()));
+    }
+
+    #[test]
+    fn let_values_reports_a_mismatched_count() {
+        let result = run(
+            "(guard (e (#t (error-object-message e)))
+               (let-values (((a b) (values 1))) a))",
+        );
+        assert_eq!(result, Ok("\"wrong number of values\"".to_string()));
+    }
+
+    #[test]
+    fn run_values_gives_back_every_value() {
+        let result = Engine::new().run_values("(values 1 (quote two) \"three\")");
+        let expected = vec!["1".to_string(), "two".to_string(), "\"three\"".to_string()];
+        assert_eq!(result, Ok(expected));
+    }
+
+    #[test]
+    fn a_single_value_is_not_wrapped() {
+        let mut evaluator = Evaluator::new();
+        let one = evaluator.eval(read("(values 1)").unwrap()).unwrap();
+        assert_eq!(print(&one), "1");
+
+        let none = evaluator.eval_values(read("(values)").unwrap()).unwrap();
+        assert!(none.is_empty());
+    }
+
+    #[test]
+    fn expand_binds_the_formals_of_let_values() {
+        let result = Engine::new().expand(
+            "(define-syntax swap-values
+               (syntax-rules ()
+                 ((_ e) (let-values (((a b) e)) (values b a)))))
+             (swap-values (values a 2))",
+        );
+        assert_eq!(
+            result.map(|expanded| renumbered(&expanded)),
+            Ok("; line 4, column 14\n(let-values (((a#1 b#2) (values a 2))) (values b#2 a#1))\n".to_string())
+        );
+    }
+
+    #[test]
+    fn force_evaluates_a_delayed_expression_once() {
+        let result = run(
+            "(define count 0)
+             (define p (delay (begin (set! count (+ count 1)) count)))
+             (list (promise? p) (force p) (force p) count)",
+        );
+        assert_eq!(result, Ok("(#t 1 1 1)".to_string()));
+    }
+
+    #[test]
+    fn a_promise_forced_while_being_forced_keeps_its_first_value() {
+        let result = run(
+            "(define count 0)
+             (define p
+               (delay (begin (set! count (+ count 1))
+                             (if (> count x) count (force p)))))
+             (define x 5)
+             (define first (force p))
+             (set! x 10)
+             (list first (force p))",
+        );
+        assert_eq!(result, Ok("(6 6)".to_string()));
+    }
+
+    #[test]
+    fn forcing_a_long_delay_force_chain_runs_in_constant_space() {
+        let result = run(
+            "(define (countdown n)
+               (delay-force (if (= n 0) (delay (quote done)) (countdown (- n 1)))))
+             (force (countdown 100000))",
+        );
+        assert_eq!(result, Ok("done".to_string()));
+    }
+
+    #[test]
+    fn make_promise_wraps_values_but_not_promises() {
+        let result = run(
+            "(define p (make-promise 1))
+             (list (force p) (eq? p (make-promise p)) (force 2) (promise? 2))",
+        );
+        assert_eq!(result, Ok("(1 #t 2 #f)".to_string()));
+    }
+
+    #[test]
+    fn parameterize_rebinds_a_parameter_for_its_body() {
+        let result = run(
+            "(define level (make-parameter 1))
+             (define (show) (level))
+             (list (show) (parameterize ((level 2)) (show)) (show))",
+        );
+        assert_eq!(result, Ok("(1 2 1)".to_string()));
+    }
+
+    #[test]
+    fn parameter_converters_see_every_new_value() {
+        let result = run(
+            "(define width (make-parameter 10 (lambda (x) (* x 2))))
+             (list (width) (parameterize ((width 3)) (width)))",
+        );
+        assert_eq!(result, Ok("(20 6)".to_string()));
+    }
+
+    #[test]
+    fn escaping_a_parameterize_restores_the_parameter() {
+        let result = run(
+            "(define p (make-parameter 1))
+             (define seen (list))
+             (define (note) (set! seen (cons (p) seen)))
+             (define escaped
+               (call/cc
+                 (lambda (k)
+                   (parameterize ((p 2))
+                     (dynamic-wind note (lambda () (k (p))) note)))))
+             (list escaped (p) seen)",
+        );
+        assert_eq!(result, Ok("(2 1 (2 2))".to_string()));
+    }
+
+    #[test]
+    fn raising_out_of_a_parameterize_restores_the_parameter() {
+        let result = run(
+            "(define p (make-parameter 1))
+             (guard (e (#t (list e (p))))
+               (parameterize ((p 2)) (raise (p))))",
+        );
+        assert_eq!(result, Ok("(2 1)".to_string()));
+    }
+
+    #[test]
+    fn call_cc_returns_normally_when_the_continuation_is_unused() {
+        let result = run("(+ 1 (call-with-current-continuation (lambda (k) 2)))");
+        assert_eq!(result, Ok("3".to_string()));
+    }
+
+    #[test]
+    fn continuations_cannot_be_called_after_they_return() {
+        let result = run(
+            "(define saved #f)
+             (call/cc (lambda (k) (set! saved k)))
+             (guard (e (#t (error-object-message e))) (saved 1))",
+        );
+        assert_eq!(result, Ok("\"continuation called after its extent ended\"".to_string()));
+    }
+
+    #[test]
+    fn the_engine_reads_and_sets_parameters() {
+        let mut engine = Engine::new();
+        engine.run("(define log-level (make-parameter 1 (lambda (x) (* x 10))))").unwrap();
+        assert_eq!(engine.parameter("log-level"), Ok("10".to_string()));
+
+        engine.set_parameter("log-level", "(+ 2 3)").unwrap();
+        assert_eq!(engine.run("(log-level)"), Ok("50".to_string()));
+
+        let error = engine.parameter("car").unwrap_err();
+        assert_eq!(error.to_string(), "not a parameter car");
+    }
+
+    #[test]
+    fn lambdas_collect_rest_arguments() {
+        let result = run(
+            "(define (tail a . rest) (list a rest))
+             (list (tail 1) (tail 1 2 3) ((lambda args args) 4 5))",
+        );
+        assert_eq!(result, Ok("((1 ()) (1 (2 3)) (4 5))".to_string()));
+    }
+
+    #[test]
+    fn case_lambda_dispatches_on_argument_count() {
+        let result = run(
+            "(define area
+               (case-lambda
+                 ((side) (* side side))
+                 ((width height) (* width height))
+                 ((width height . more) (list width height more))))
+             (list (area 3) (area 2 5) (area 1 2 3 4))",
+        );
+        assert_eq!(result, Ok("(9 10 (1 2 (3 4)))".to_string()));
+    }
+
+    #[test]
+    fn arity_errors_name_the_procedure_and_the_expected_count() {
+        let message = |program: &str| match run(program) {
+            Err(Error::Uncaught(exception)) => exception.message,
+            other => panic!("expected an error, got {:?}", other),
+        };
+
+        assert_eq!(
+            message("(define (pair a b) (cons a b)) (pair 1)"),
+            "wrong number of arguments to pair: expected 2, got 1"
+        );
+        assert_eq!(
+            message("(define f (lambda (a . rest) a)) (f)"),
+            "wrong number of arguments to f: expected at least 1, got 0"
+        );
+        assert_eq!(
+            message("(define g (case-lambda ((a) a) ((a b c) c))) (g 1 2)"),
+            "wrong number of arguments to g: expected 1 or 3, got 2"
+        );
+        assert_eq!(message("(car)"), "wrong number of arguments to car: expected 1, got 0");
+    }
+
+    #[test]
+    fn defined_procedures_print_with_their_names() {
+        let result = run("(define (f) 1) (define g (lambda () 2)) (list f g (lambda () 3))");
+        assert_eq!(result, Ok("(#<procedure f> #<procedure g> #<procedure>)".to_string()));
+    }
+
+    #[test]
+    fn collecting_garbage_frees_unreachable_cycles() {
+        let mut engine = Engine::new();
+        engine
+            .run(
+                "(define (churn n)
+                   (if (= n 0)
+                       0
+                       (begin (let () (define (self) self) self) (churn (- n 1)))))",
+            )
+            .unwrap();
+        engine.collect_garbage();
+        let before = engine.gc_stats();
+
+        engine.run("(churn 500)").unwrap();
+        let reclaimed = engine.collect_garbage();
+
+        // Each round leaves an environment and the closure it holds, which
+        // refer to each other.
+        assert!(reclaimed >= 1000);
+        assert_eq!(engine.gc_stats().live, before.live);
+    }
+
+    #[test]
+    fn collecting_garbage_keeps_reachable_cycles() {
+        let mut engine = Engine::new();
+        engine
+            .run(
+                "(define ring (list 1 2 3))
+                 (set-cdr! (cdr (cdr ring)) ring)
+                 (define (count-to n) (let loop ((i 0)) (if (= i n) i (loop (+ i 1)))))",
+            )
+            .unwrap();
+        engine.collect_garbage();
+
+        let result = engine.run("(list (car (cdr (cdr (cdr ring)))) (count-to 5))");
+        assert_eq!(result, Ok("(1 5)".to_string()));
+    }
+
+    #[test]
+    fn the_collector_runs_as_the_heap_grows() {
+        let mut engine = Engine::new();
+        engine
+            .run(
+                "(define (churn n)
+                   (if (= n 0)
+                       (quote done)
+                       (begin (let () (define (self) self) self) (churn (- n 1)))))
+                 (churn 20000)",
+            )
+            .unwrap();
+
+        let stats = engine.gc_stats();
+        assert!(stats.collections > 0);
+        assert!(stats.reclaimed > 0);
+        assert!(stats.live < 10000);
+    }
+
+    #[test]
+    fn long_lists_are_freed_without_using_up_the_stack() {
+        let result = run(
+            "(define (build n tail) (if (= n 0) tail (build (- n 1) (cons n tail))))
+             (define l (build 100000 '()))
+             (define v (vector l))
+             (set! l #f)
+             (set! v #f)
+             (quote freed)",
+        );
+        assert_eq!(result, Ok("freed".to_string()));
+    }
+
+    #[test]
+    fn lists_with_cycles_print_with_datum_labels() {
+        let tail = "(define l (list 1 2)) (set-cdr! (cdr l) l) l";
+        assert_eq!(run(tail), Ok("#0=(1 2 . #0#)".to_string()));
+        let head = "(define l (list 1 2)) (set-car! l l) l";
+        assert_eq!(run(head), Ok("#0=(#0# 2)".to_string()));
+        let inner = "(define l (list 1 2 3)) (set-cdr! (cdr (cdr l)) (cdr l)) (list l (vector l))";
+        assert_eq!(run(inner), Ok("((1 . #0=(2 3 . #0#)) #((1 . #0#)))".to_string()));
+        let vector = "(define v (vector 1 2)) (vector-set! v 1 v) v";
+        assert_eq!(run(vector), Ok("#0=#(1 #0#)".to_string()));
+    }
+
+    #[test]
+    fn deeply_nested_values_print_without_overflowing_the_stack() {
+        let result = run(
+            "(let loop ((i 0) (x '()))
+               (if (= i 100000) (vector x) (loop (+ i 1) (list x))))",
+        );
+        let expected = format!("#({}{})", "(".repeat(100001), ")".repeat(100001));
+        assert_eq!(result, Ok(expected));
+    }
+
+    #[test]
+    fn deeply_nested_values_are_compared_without_overflowing_the_stack() {
+        let result = run(
+            "(define (nest depth leaf)
+               (let loop ((i 0) (x leaf))
+                 (if (= i depth) x (loop (+ i 1) (list x)))))
+             (define a (nest 100000 1))
+             (define b (nest 100000 1))
+             (define c (nest 100000 2))
+             (list (equal? a b) (equal? a c) (equal? (vector a) (vector b)))",
+        );
+        assert_eq!(result, Ok("(#t #f #t)".to_string()));
+    }
+
+    #[test]
+    fn circular_values_are_compared_by_what_they_unfold_to() {
+        let result = run(
+            "(define (ring . items)
+               (let loop ((last items))
+                 (if (null? (cdr last)) (set-cdr! last items) (loop (cdr last))))
+               items)
+             (define v (vector 1 #f))
+             (vector-set! v 1 v)
+             (define w (vector 1 (vector 1 #f)))
+             (vector-set! (vector-ref w 1) 1 w)
+             (list (equal? (ring 1 2) (ring 1 2 1 2))
+                   (equal? (ring 1 2) (ring 1 3))
+                   (equal? v w))",
+        );
+        assert_eq!(result, Ok("(#t #f #t)".to_string()));
+    }
+
+    #[test]
+    fn lists_with_cycles_arent_proper_lists() {
+        let message = |program: &str| match run(program) {
+            Err(Error::Uncaught(exception)) => exception.message,
+            other => panic!("expected an error, got {:?}", other),
+        };
+        assert_eq!(
+            message("(define l (list 1 2)) (set-cdr! (cdr l) l) (length l)"),
+            "length: expected a list"
+        );
+        assert_eq!(
+            message("(define l (list 1)) (set-cdr! l l) (list->vector l)"),
+            "list->vector: expected a list"
+        );
+    }
+
+    #[test]
+    fn incremental_collection_frees_unreachable_cycles() {
+        let mut engine = Engine::new();
+        engine.set_gc_mode(GcMode::Incremental(Duration::from_millis(1)));
+        engine
+            .run(
+                "(define (churn n)
+                   (if (= n 0)
+                       (quote done)
+                       (begin (let () (define (self) self) self) (churn (- n 1)))))
+                 (churn 50000)",
+            )
+            .unwrap();
+
+        let stats = engine.gc_stats();
+        assert!(stats.collections > 0);
+        assert!(stats.pauses > stats.collections);
+        assert!(stats.live < 10000);
+    }
+
+    #[test]
+    fn incremental_collection_keeps_what_is_moved_while_it_runs() {
+        let mut engine = Engine::new();
+        engine.set_gc_mode(GcMode::Incremental(Duration::from_millis(0)));
+        // Lists are moved out of `from` into `to` as the collector runs.
+        // `to` is held by the evaluator's stack, so it's marked first, and
+        // the padding it starts with keeps the collector busy with it for a
+        // while. Without the write barrier, a list moved in the meantime
+        // would be missed both in `to`, which has already been marked, and
+        // in `from`, which no longer holds it.
+        let result = engine.run(
+            "(define size 2000)
+             (define from (make-vector size #f))
+             (define (fill i)
+               (if (< i size)
+                   (begin (vector-set! from i (list i (list i))) (fill (+ i 1)))))
+             (define (move to i)
+               (if (< i size)
+                   (begin
+                     (vector-set! to i (vector-ref from i))
+                     (vector-set! from i #f)
+                     (let () (define (self) self) self)
+                     (move to (+ i 1)))))
+             (define (sum v i total)
+               (if (= i size)
+                   total
+                   (let ((item (vector-ref v i)))
+                     (sum v (+ i 1) (+ total (car item) (car (car (cdr item))))))))
+             (define (round)
+               (fill 0)
+               (let ((to (make-vector size (list 0 (list 0) 0))))
+                 (let pad ((i 0))
+                   (if (< i size) (begin (vector-set! to i (list 0 (list 0) 0)) (pad (+ i 1)))))
+                 (move to 0)
+                 (sum to 0 0)))
+             (list (round) (round) (round) (round) (round))",
+        );
+        let total = "3998000";
+        let expected = format!("({} {} {} {} {})", total, total, total, total, total);
+        assert_eq!(result, Ok(expected));
+    }
+
+    #[test]
+    fn incremental_pauses_stay_near_the_target() {
+        let mut engine = Engine::new();
+        engine.set_gc_mode(GcMode::Incremental(Duration::from_millis(2)));
+        engine
+            .run(
+                "(define keep
+                   (let loop ((i 0) (acc (quote ())))
+                     (if (= i 20000) acc (loop (+ i 1) (cons (vector i) acc)))))
+                 (define (churn n)
+                   (if (= n 0)
+                       (quote done)
+                       (begin (let () (define (self) self) self) (churn (- n 1)))))
+                 (churn 50000)",
+            )
+            .unwrap();
+
+        let pauses = engine.gc_pauses();
+        assert!(!pauses.is_empty());
+        // Slices only look at the clock every so often, and the machine
+        // running the tests may be busy, so allow plenty of slack.
+        let over = pauses.iter().filter(|&&p| p > Duration::from_millis(20)).count();
+        assert!(over * 10 < pauses.len());
+    }
+
+    #[test]
+    fn vectors_can_be_built_read_and_updated() {
+        let result = run(
+            "(define v (make-vector 3 0))
+             (vector-set! v 1 (quote x))
+             (list v (vector-ref v 1) (vector-length v) (vector? v)
+                   (vector->list (vector 1 2)) (list->vector (list 3)))",
+        );
+        assert_eq!(result, Ok("(#(0 x 0) x 3 #t (1 2) #(3))".to_string()));
+    }
+
+    #[test]
+    fn hash_tables_compare_keys_with_eqv() {
+        let result = run(
+            "(define t (make-hash-table))
+             (define key (list 1))
+             (hash-table-set! t 1 (quote one))
+             (hash-table-set! t (quote b) (quote bee))
+             (hash-table-set! t key (quote list))
+             (hash-table-set! t 1 (quote uno))
+             (hash-table-delete! t (quote b))
+             (list (hash-table-ref/default t 1 #f)
+                   (hash-table-ref/default t key #f)
+                   (hash-table-ref/default t (list 1) #f)
+                   (hash-table-contains? t (quote b))
+                   (hash-table-count t))",
+        );
+        assert_eq!(result, Ok("(uno list #f #f 2)".to_string()));
+    }
+
+    #[test]
+    fn weak_boxes_let_go_of_their_values() {
+        let result = run(
+            "(define kept (list 1))
+             (define weak (make-weak-box kept))
+             (define lost (make-weak-box (list 2)))
+             (list (weak-box-value weak) (weak-box-value lost) (weak-box-value lost 0)
+                   (weak-box-value (make-weak-box 3)))",
+        );
+        assert_eq!(result, Ok("((1) #f 0 3)".to_string()));
+    }
+
+    #[test]
+    fn weak_boxes_let_go_of_strings_and_bytevectors() {
+        let mut engine = Engine::new();
+        engine
+            .run(
+                "(define text (make-weak-box (make-string 2)))
+                 (define bytes (make-weak-box (make-bytevector 2 0)))
+                 (define kept (make-string 2))
+                 (define held (make-weak-box kept))",
+            )
+            .unwrap();
+
+        engine.collect_garbage();
+        let result = engine.run("(list (weak-box-value text) (weak-box-value bytes) (weak-box-value held))");
+        assert_eq!(result, Ok("(#f #f \"  \")".to_string()));
+    }
+
+    #[test]
+    fn ephemerons_break_when_only_their_datum_holds_the_key() {
+        let mut engine = Engine::new();
+        engine
+            .run(
+                "(define kept (list 1))
+                 (define live (make-ephemeron kept (cons kept 2)))
+                 (define dead (let ((key (list 3))) (make-ephemeron key (cons key 4))))",
+            )
+            .unwrap();
+        assert_eq!(engine.run("(ephemeron-broken? dead)"), Ok("#f".to_string()));
+
+        engine.collect_garbage();
+        let result = engine.run(
+            "(list (ephemeron-broken? live) (ephemeron-datum live)
+                   (ephemeron-broken? dead) (ephemeron-key dead) (ephemeron-datum dead))",
+        );
+        assert_eq!(result, Ok("(#f ((1) . 2) #t #f #f)".to_string()));
+    }
+
+    #[test]
+    fn ephemeron_tables_drop_entries_whose_values_hold_their_keys() {
+        let mut engine = Engine::new();
+        engine
+            .run(
+                "(define weak (make-weak-hash-table))
+                 (define ephemeral (make-ephemeron-hash-table))
+                 (define (cache! table)
+                   (let ((key (list (quote key))))
+                     (hash-table-set! table key (cons key (quote derived)))))
+                 (cache! weak)
+                 (cache! ephemeral)
+                 (define kept (list (quote kept)))
+                 (hash-table-set! weak kept 1)
+                 (hash-table-set! ephemeral kept 1)",
+            )
+            .unwrap();
+
+        engine.collect_garbage();
+        // A weak table's values keep their keys alive, if they refer to them.
+        let result = engine.run(
+            "(list (hash-table-count weak) (hash-table-count ephemeral)
+                   (hash-table-ref/default ephemeral kept #f))",
+        );
+        assert_eq!(result, Ok("(2 1 1)".to_string()));
+    }
+
+    #[test]
+    fn guardians_get_back_unreachable_objects() {
+        let mut engine = Engine::new();
+        engine
+            .run(
+                "(define g (make-guardian))
+                 (define kept (list 1))
+                 (g kept)
+                 (g (let ((ring (list 2))) (set-cdr! ring ring) ring))
+                 (g (list 3) (quote three))",
+            )
+            .unwrap();
+        assert_eq!(engine.run("(g)"), Ok("#f".to_string()));
+
+        engine.collect_garbage();
+        let result = engine.run("(let* ((a (g)) (b (g))) (list (g) (if (symbol? a) a b)))");
+        assert_eq!(result, Ok("(#f three)".to_string()));
+    }
+
+    #[test]
+    fn guardians_can_close_unreachable_ports() {
+        let path = env::temp_dir().join("ruse-guardian-test.txt");
+        let mut engine = Engine::new();
+        engine
+            .run(format!(
+                "(define g (make-guardian))
+                 (define (open!) (g (open-output-file {:?})))
+                 (open!)",
+                path
+            ))
+            .unwrap();
+
+        engine.collect_garbage();
+        let result = engine.run(
+            "(let ((port (g)))
+               (close-port port)
+               (list (output-port? port) (output-port-open? port) (g)))",
+        );
+        let _ = fs::remove_file(&path);
+        assert_eq!(result, Ok("(#t #f #f)".to_string()));
+    }
+
+    #[test]
+    fn quote_can_be_abbreviated() {
+        let result = run("(list 'a '(b . c) '#(1 \"d\") ''e)");
+        assert_eq!(result, Ok("(a (b . c) #(1 \"d\") (quote e))".to_string()));
+    }
+
+    #[test]
+    fn literal_constants_cannot_be_modified() {
+        let result = run(
+            "(define (attempt thunk)
+               (guard (e ((error-object? e) (error-object-message e))) (thunk)))
+             (define fresh (list 1 2))
+             (set-car! fresh 0)
+             (list (attempt (lambda () (set-car! '(1 2) 0)))
+                   (attempt (lambda () (set-cdr! (cdr '(1 2)) 0)))
+                   (attempt (lambda () (vector-set! #(1 2) 0 0)))
+                   fresh)",
+        );
+        let expected = "(\"set-car!: can't modify a literal constant\" \
+                         \"set-cdr!: can't modify a literal constant\" \
+                         \"vector-set!: can't modify a literal constant\" (0 2))";
+        assert_eq!(result, Ok(expected.to_string()));
+    }
+
+    #[test]
+    fn literal_checks_can_be_turned_off() {
+        let mut engine = Engine::new();
+        engine.set_literal_checks(false);
+        let result = engine.run("(let ((v '#(1 2))) (vector-set! v 0 0) v)");
+        assert_eq!(result, Ok("#(0 2)".to_string()));
+    }
+
+    #[test]
+    fn strings_and_bytevectors_can_be_modified_unless_literal() {
+        let result = run(
+            "(define (attempt thunk)
+               (guard (e ((error-object? e) (error-object-message e))) (thunk)))
+             (define z (string-ref \"xyz\" 2))
+             (define s (make-string 3 (string-ref \"a\" 0)))
+             (define t (string-copy \"abc\"))
+             (define b (make-bytevector 2 7))
+             (string-set! s 1 z)
+             (string-fill! t z)
+             (bytevector-u8-set! b 0 255)
+             (list s t (string-ref s 1) (string-length \"héllo\")
+                   (bytevector-u8-ref b 0) (bytevector-length b)
+                   (attempt (lambda () (string-set! \"abc\" 0 z)))
+                   (attempt (lambda () (string-fill! \"abc\" z)))
+                   (attempt (lambda () (bytevector-u8-set! b 0 256))))",
+        );
+        let expected = "(\"aza\" \"zzz\" #\\z 5 255 2 \
+                         \"string-set!: can't modify a literal constant\" \
+                         \"string-fill!: can't modify a literal constant\" \
+                         \"bytevector-u8-set!: expected a byte\")";
+        assert_eq!(result, Ok(expected.to_string()));
+    }
+
+    #[test]
+    fn quoted_data_in_macro_templates_stays_constant() {
+        let result = run(
+            "(define-syntax m (syntax-rules () ((_) '(1 2))))
+             (define-syntax v (syntax-rules () ((_ x) '#(x 2))))
+             (define (attempt thunk)
+               (guard (e ((error-object? e) (error-object-message e))) (thunk)))
+             (list (attempt (lambda () (set-car! (m) 0)))
+                   (attempt (lambda () (vector-set! (v 1) 0 0))))",
+        );
+        let expected = "(\"set-car!: can't modify a literal constant\" \
+                         \"vector-set!: can't modify a literal constant\")";
+        assert_eq!(result, Ok(expected.to_string()));
+    }
+
+    #[test]
+    fn deeply_nested_quoted_data_is_quoted_without_using_up_the_stack() {
+        let depth = 100_000;
+        let nested = format!("{}x{}", "(".repeat(depth), ")".repeat(depth));
+        let program = format!(
+            "(define-syntax q (syntax-rules () ((_ d) 'd)))
+             (define deep (list '{} (q {})))
+             (pair? (car deep))",
+            nested, nested
+        );
+        assert_eq!(run(&program), Ok("#t".to_string()));
+    }
+
+    #[test]
+    fn symbols_convert_to_and_from_strings() {
+        let result = run(
+            "(list (symbol->string 'abc)
+                   (eq? (string->symbol \"abc\") 'abc)
+                   (symbol=? 'a (string->symbol \"a\") 'a)
+                   (symbol=? 'a 'b))",
+        );
+        assert_eq!(result, Ok("(\"abc\" #t #t #f)".to_string()));
+    }
+
+    #[test]
+    fn gensyms_are_never_equal_to_interned_symbols() {
+        let result = run(
+            "(define a (gensym 'tmp))
+             (define b (generate-uninterned-symbol))
+             (list (symbol? a) (eq? a a) (eq? a b)
+                   (eq? a (string->symbol (symbol->string a))) a b)",
+        );
+        assert_eq!(result.map(|printed| renumbered(&printed)), Ok("(#t #t #f #f tmp#1 g#2)".to_string()));
+    }
+
+    #[test]
+    fn gensyms_made_by_different_engines_are_never_equal() {
+        let mut engine = Engine::new();
+        engine.eval("(define (fresh) (gensym 'tmp))").unwrap();
+        let mut fork = engine.fork().unwrap();
+        let ours = engine.run("(fresh)").unwrap();
+        assert_ne!(fork.run("(fresh)").unwrap(), ours);
+        assert_ne!(Engine::new().run("(gensym 'tmp)").unwrap(), ours);
+    }
+
+    #[test]
+    fn registered_functions_can_be_called_from_ruse() {
+        let mut engine = Engine::new();
+        engine.register_fn("add", |a: i64, b: i64| a + b);
+        engine.register_fn("greet", |name: String| format!("hello, {}", name));
+        engine.register_fn("answer", || 42);
+        let result = engine.run("(list (add 1 2) (greet \"ruse\") (answer) (procedure? add))");
+        assert_eq!(result, Ok("(3 \"hello, ruse\" 42 #t)".to_string()));
+    }
+
+    #[test]
+    fn registered_functions_check_their_arguments() {
+        let mut engine = Engine::new();
+        engine.register_fn("add", |a: i64, b: i64| a + b);
+        let result = engine.run(
+            "(define (attempt thunk)
+               (guard (e ((error-object? e)
+                          (cons (error-object-message e) (error-object-irritants e))))
+                 (thunk)))
+             (list (attempt (lambda () (add 1 \"two\")))
+                   (attempt (lambda () (add 1))))",
+        );
+        let expected = "((\"add: expected an integer which fits in i64\" \"two\") \
+                         (\"wrong number of arguments to add: expected 2, got 1\" 1))";
+        assert_eq!(result, Ok(expected.to_string()));
+    }
+
+    #[test]
+    fn ruse_procedures_can_be_called_from_rust() {
+        let mut engine = Engine::new();
+        engine
+            .run("(define (scale x factor) (* x factor)) (define (greet) \"hi\")")
+            .unwrap();
+        assert_eq!(engine.call::<i64, _>("scale", (3, 4)), Ok(12));
+        assert_eq!(engine.call::<String, _>("greet", ()), Ok("hi".to_string()));
+
+        let scale = engine.procedure("scale").unwrap();
+        let results: Vec<f64> = (1..4).map(|i| scale.call(&mut engine, (i, 0.5)).unwrap()).collect();
+        assert_eq!(results, vec![0.5, 1.0, 1.5]);
+    }
+
+    #[test]
+    fn calls_from_rust_report_exceptions_and_bad_results() {
+        let mut engine = Engine::new();
+        engine.run("(define (fail) (error \"failed\" 1)) (define (name) 'ruse)").unwrap();
+
+        match engine.call::<i64, _>("fail", ()) {
+            Err(Error::Uncaught(exception)) => assert_eq!(exception.message, "failed"),
+            other => panic!("expected an uncaught exception, got {:?}", other),
+        }
+        let expected = ConversionError {
+            expected: "a string".to_string(),
+            found: "ruse".to_string(),
+        };
+        assert_eq!(engine.call::<String, _>("name", ()), Err(Error::Conversion(expected)));
+        assert!(engine.procedure("missing").is_err());
+    }
+
+    #[test]
+    fn std_collections_convert_to_and_from_ruse() {
+        let mut engine = Engine::new();
+        engine.run("(define (id x) x) (define (first-key alist) (car (car alist)))").unwrap();
+
+        let numbers: Vec<i64> = engine.call("id", (vec![1, 2, 3],)).unwrap();
+        assert_eq!(numbers, vec![1, 2, 3]);
+        let mut scores = HashMap::new();
+        scores.insert("ada".to_string(), 3);
+        assert_eq!(engine.call::<String, _>("first-key", (scores.clone(),)), Ok("ada".to_string()));
+        assert_eq!(engine.call::<HashMap<String, i64>, _>("id", (scores.clone(),)), Ok(scores));
+        let pair: (i64, String) = engine.call("id", ((1, "one"),)).unwrap();
+        assert_eq!(pair, (1, "one".to_string()));
+        assert_eq!(engine.call::<Option<i64>, _>("id", (false,)), Ok(None));
+        let result: Result<i64, String> = engine.call("id", (Err::<i64, _>("bad"),)).unwrap();
+        assert_eq!(result, Err("bad".to_string()));
+    }
+
+    #[derive(Debug, PartialEq, FromRuse, IntoRuse)]
+    struct Player {
+        name: String,
+        high_score: i64,
+    }
+
+    #[derive(Debug, PartialEq, FromRuse, IntoRuse)]
+    #[ruse(record)]
+    struct Point {
+        x: i64,
+        y: i64,
+    }
+
+    #[derive(Debug, PartialEq, FromRuse, IntoRuse)]
+    enum Shape {
+        Empty,
+        Circle(f64),
+        Line(Point, Point),
+        Rect { width: i64, height: i64 },
+    }
+
+    fn printed<T: IntoRuse>(value: T) -> String {
+        print(&value.into_ruse())
+    }
+
+    #[test]
+    fn derived_structs_become_alists_or_records() {
+        let player = Player { name: "ada".to_string(), high_score: 10 };
+        assert_eq!(printed(player), "((name . \"ada\") (high-score . 10))");
+        assert_eq!(printed(Point { x: 1, y: 2 }), "#<point x: 1 y: 2>");
+
+        let mut engine = Engine::new();
+        engine.run("(define (promote p) (list (car p) (cons 'high-score 99)))").unwrap();
+        let player = Player { name: "bo".to_string(), high_score: 1 };
+        let player: Player = engine.call("promote", (player,)).unwrap();
+        assert_eq!(player, Player { name: "bo".to_string(), high_score: 99 });
+        let point = Point { x: 3, y: 4 }.into_ruse();
+        assert_eq!(Point::from_ruse(&point), Ok(Point { x: 3, y: 4 }));
+    }
+
+    #[test]
+    fn derived_enums_are_tagged_with_their_variant() {
+        let shapes = vec![
+            Shape::Empty,
+            Shape::Circle(1.5),
+            Shape::Line(Point { x: 0, y: 0 }, Point { x: 1, y: 1 }),
+            Shape::Rect { width: 2, height: 3 },
+        ];
+        assert_eq!(printed(Shape::Empty), "empty");
+        assert_eq!(printed(Shape::Circle(1.5)), "(circle 1.5)");
+        assert_eq!(printed(Shape::Rect { width: 2, height: 3 }), "(rect (width . 2) (height . 3))");
+        for shape in shapes {
+            let value = shape.into_ruse();
+            let shape = Shape::from_ruse(&value).unwrap();
+            assert_eq!(printed(shape), print(&value));
+        }
+
+        let error = Shape::from_ruse(&Expr::symbol("square")).unwrap_err();
+        assert_eq!(error.expected, "one of empty, circle, line, rect");
+    }
+
+    #[test]
+    fn programs_can_give_back_values_rather_than_text() {
+        let mut engine = Engine::new();
+        let value = engine.eval("(define xs (list 1 2 3)) (cdr xs)").unwrap();
+        assert_eq!(Vec::<i64>::from_ruse(&value), Ok(vec![2, 3]));
+        match engine.eval("(car '())") {
+            Err(Error::Uncaught(exception)) => assert_eq!(exception.irritants, vec!["()".to_string()]),
+            Err(other) => panic!("expected an uncaught exception, got {:?}", other),
+            Ok(..) => panic!("expected an uncaught exception"),
+        }
+        assert!(engine.eval("(car").is_err());
+    }
+
+    #[test]
+    fn files_which_cant_be_read_are_reported() {
+        let path = env::temp_dir().join("ruse-missing-program.rus");
+        let _ = fs::remove_file(&path);
+        match Engine::new().run_file(&path) {
+            Err(Error::Io(IoError { kind, .. })) => assert_eq!(kind, io::ErrorKind::NotFound),
+            other => panic!("expected an IO error, got {:?}", other),
+        }
+
+        fs::write(&path, "(+ 1 2)").unwrap();
+        assert_eq!(Engine::new().run_file(&path), Ok("3".to_string()));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn deeply_nested_programs_are_read_without_using_up_the_stack() {
+        let depth = 100_000;
+        let nested = format!("{}{}", "(".repeat(depth), ")".repeat(depth));
+        assert!(read(&nested).is_ok());
+        assert_eq!(Engine::new().run(&nested), Err(Error::Limit(Limit::Depth)));
+    }
+
+    #[test]
+    fn programs_which_cant_be_read_are_reported() {
+        let mut engine = Engine::new();
+        for program in &["(car", ")", "#\\nonsense"] {
+            match engine.run(program) {
+                Err(Error::ReadError(..)) => {}
+                other => panic!("expected {} not to be read, got {:?}", program, other),
+            }
+        }
+        assert_eq!(engine.run("(+ 1 2)"), Ok("3".to_string()));
+    }
+
+    #[test]
+    fn programs_are_stopped_when_they_run_out_of_fuel() {
+        let mut engine = Engine::new();
+        engine.set_limits(Limits {
+            fuel: Some(10_000),
+            ..Limits::default()
+        });
+        engine.run("(define (spin) (spin))").unwrap();
+        assert_eq!(engine.run("(spin)"), Err(Error::Limit(Limit::Fuel)));
+        assert_eq!(
+            engine.run("(guard (e (#t 'caught)) (dynamic-wind (lambda () #f) spin (lambda () #f)))"),
+            Err(Error::Limit(Limit::Fuel))
+        );
+        assert_eq!(engine.run("(+ 1 2)"), Ok("3".to_string()));
+    }
+
+    #[test]
+    fn programs_are_stopped_when_they_nest_too_deeply() {
+        let mut engine = Engine::new();
+        engine.set_limits(Limits {
+            depth: Some(200),
+            ..Limits::default()
+        });
+        engine
+            .run("(define (count n) (if (= n 0) 0 (+ 1 (count (- n 1)))))")
+            .unwrap();
+        assert_eq!(engine.run("(count 10000)"), Err(Error::Limit(Limit::Depth)));
+        assert_eq!(engine.run("(count 20)"), Ok("20".to_string()));
+    }
+
+    #[test]
+    fn programs_are_stopped_when_they_fill_the_heap_or_run_too_long() {
+        let mut engine = Engine::new();
+        engine
+            .run("(define (grow xs) (grow (cons xs xs))) (define (spin) (spin))")
+            .unwrap();
+        engine.set_limits(Limits {
+            heap: Some(engine.gc_stats().bytes + 500_000),
+            ..Limits::default()
+        });
+        assert_eq!(engine.run("(grow '())"), Err(Error::Limit(Limit::Heap)));
+        engine.set_limits(Limits {
+            timeout: Some(Duration::from_millis(50)),
+            ..Limits::default()
+        });
+        assert_eq!(engine.run("(spin)"), Err(Error::Limit(Limit::Timeout)));
+        assert_eq!(engine.run("(length (list 1 2 3))"), Ok("3".to_string()));
+    }
+
+    #[test]
+    fn interning_new_symbols_is_charged_to_the_heap() {
+        let mut engine = Engine::new();
+        engine.set_limits(Limits {
+            heap: Some(engine.gc_stats().bytes + 100_000),
+            ..Limits::default()
+        });
+        engine.run("(define (name length) (make-string length (string-ref \"z\" 0)))").unwrap();
+        let program = "(let loop ((i 1)) (string->symbol (name i)) (loop (+ i 1)))";
+        assert_eq!(engine.run(program), Err(Error::Limit(Limit::Heap)));
+        assert_eq!(engine.run("(string->symbol (name 3))"), Ok("zzz".to_string()));
+    }
+
+    #[test]
+    fn big_allocations_are_checked_against_the_heap_limit_before_they_happen() {
+        let mut engine = Engine::new();
+        engine.set_limits(Limits {
+            heap: Some(engine.gc_stats().bytes + 100_000),
+            ..Limits::default()
+        });
+        assert_eq!(engine.run("(make-vector 50000000 0)"), Err(Error::Limit(Limit::Heap)));
+        assert_eq!(engine.run("(make-string 50000000)"), Err(Error::Limit(Limit::Heap)));
+        assert_eq!(engine.run("(make-bytevector 50000000 0)"), Err(Error::Limit(Limit::Heap)));
+        assert_eq!(engine.run("(vector-length (make-vector 1000 0))"), Ok("1000".to_string()));
+    }
+
+    #[test]
+    fn each_engine_counts_only_its_own_values_against_its_heap_limit() {
+        let mut big = Engine::new();
+        big.run("(define strings (make-vector 1000 (make-string 1000)))").unwrap();
+        big.run("(define bytes (make-bytevector 1000000))").unwrap();
+        assert!(big.gc_stats().bytes > 1_000_000);
+
+        let mut small = Engine::new();
+        small.set_limits(Limits {
+            heap: Some(small.gc_stats().bytes + 100_000),
+            ..Limits::default()
+        });
+        assert_eq!(small.run("(vector-length (make-vector 100 0))"), Ok("100".to_string()));
+        assert_eq!(big.run("(bytevector-length bytes)"), Ok("1000000".to_string()));
+    }
+
+    #[test]
+    fn deep_recursion_is_stopped_before_it_overflows_the_stack() {
+        let mut engine = Engine::new();
+        engine
+            .run("(define (deep n) (if (= n 0) 0 (+ 1 (deep (- n 1)))))")
+            .unwrap();
+        assert_eq!(engine.run("(deep 100000)"), Err(Error::Limit(Limit::Depth)));
+        assert_eq!(engine.run("(deep 100)"), Ok("100".to_string()));
+    }
+
+    struct Counter {
+        count: i64,
+    }
+
+    #[test]
+    fn host_objects_are_worked_on_by_registered_procedures() {
+        let mut engine = Engine::new();
+        engine.register_type::<Counter>("counter");
+        engine.register_fn("make-counter", || Host::new(Counter { count: 0 }));
+        engine.register_fn("counter-increment!", |counter: Host<Counter>| {
+            counter.borrow_mut().count += 1;
+            counter.borrow().count
+        });
+        let result = engine.run(
+            "(define c (make-counter))
+             (counter-increment! c)
+             (list c (counter? c) (counter? 5) (counter-increment! c) (eq? c c))",
+        );
+        assert_eq!(result, Ok("(#<host:Counter> #t #f 2 #t)".to_string()));
+
+        let counter = Host::new(Counter { count: 10 });
+        assert_eq!(engine.call::<i64, _>("counter-increment!", (counter.clone(),)), Ok(11));
+        assert_eq!(engine.call::<bool, _>("eq?", (counter.clone(), counter)), Ok(true));
+        let result = engine.run(
+            "(guard (e ((error-object? e) (error-object-message e)))
+               (counter-increment! \"c\"))",
+        );
+        assert_eq!(result, Ok("\"counter-increment!: expected a host Counter\"".to_string()));
+    }
+
+    struct Tracked {
+        dropped: Arc<AtomicBool>,
+    }
+
+    impl Drop for Tracked {
+        fn drop(&mut self) {
+            self.dropped.store(true, Ordering::SeqCst);
+        }
+    }
+
+    #[test]
+    fn host_objects_are_freed_along_with_the_cycles_holding_them() {
+        let mut engine = Engine::new();
+        engine
+            .run("(define (hold x) (let ((ring (list x))) (set-cdr! ring ring) ring))")
+            .unwrap();
+        let kept = Arc::new(AtomicBool::new(false));
+        let lost = Arc::new(AtomicBool::new(false));
+        let ring: ExprRef = engine
+            .call("hold", (Host::new(Tracked { dropped: kept.clone() }),))
+            .unwrap();
+        engine.call::<ExprRef, _>("hold", (Host::new(Tracked { dropped: lost.clone() }),)).unwrap();
+
+        engine.collect_garbage();
+        assert_eq!((kept.load(Ordering::SeqCst), lost.load(Ordering::SeqCst)), (false, true));
+        drop(ring);
+        engine.collect_garbage();
+        assert!(kept.load(Ordering::SeqCst));
+    }
+
+    struct Holder {
+        held: Option<ExprRef>,
+        dropped: Arc<AtomicBool>,
+    }
+
+    impl Trace for Holder {
+        fn trace(&self, f: &mut FnMut(&ExprRef)) {
+            if let Some(ref held) = self.held {
+                f(held);
+            }
+        }
+    }
+
+    impl Drop for Holder {
+        fn drop(&mut self) {
+            self.dropped.store(true, Ordering::SeqCst);
+        }
+    }
+
+    #[test]
+    fn cycles_running_through_traced_host_objects_are_freed() {
+        let mut engine = Engine::new();
+        let kept = Arc::new(AtomicBool::new(false));
+        let lost = Arc::new(AtomicBool::new(false));
+        let flags = (kept.clone(), lost.clone());
+        engine.register_fn("make-holder", move |keep: bool| {
+            let dropped = if keep { &flags.0 } else { &flags.1 };
+            Host::traced(Holder {
+                held: None,
+                dropped: dropped.clone(),
+            })
+        });
+        engine.register_fn("hold-in!", |holder: Host<Holder>, value: ExprRef| {
+            holder.borrow_mut().held = Some(value);
+        });
+        engine.register_fn("held", |holder: Host<Holder>| holder.borrow().held.clone());
+        engine
+            .run(
+                "(define (hold holder) (hold-in! holder (list holder 1)) holder)
+                 (define kept (hold (make-holder #t)))
+                 (hold (make-holder #f))",
+            )
+            .unwrap();
+
+        engine.collect_garbage();
+        assert_eq!((kept.load(Ordering::SeqCst), lost.load(Ordering::SeqCst)), (false, true));
+        assert_eq!(engine.run("(car (cdr (held kept)))"), Ok("1".to_string()));
+    }
+
+    #[test]
+    fn sandboxed_engines_refuse_programs_reaching_for_what_they_leave_out() {
+        let mut engine = Engine::with_libraries(&[Library::Base]);
+        let unbound = |result: Result<String, Error>| match result {
+            Err(Error::Uncaught(exception)) => {
+                assert_eq!(exception.message, "unbound variable");
+                exception.irritants
+            }
+            other => panic!("expected an unbound variable, got {:?}", other),
+        };
+
+        let program = "(define ran #t) (define (peek) (open-input-file \"secret\"))";
+        assert_eq!(unbound(engine.run(program)), vec!["open-input-file".to_string()]);
+        assert_eq!(unbound(engine.run("ran")), vec!["ran".to_string()]);
+        assert_eq!(unbound(engine.run("(make-hash-table)")), vec!["make-hash-table".to_string()]);
+    }
+
+    #[test]
+    fn sandboxed_engines_expand_each_macro_use_once_and_keep_their_macros() {
+        let mut engine = Engine::with_libraries(&[Library::Base]);
+        engine.run("(define expansions 0)").unwrap();
+
+        let result = engine.run(
+            "(define-syntax counted
+               (er-macro-transformer
+                 (lambda (form rename compare)
+                   (set! expansions (+ expansions 1))
+                   (car (cdr form)))))
+             (define-syntax twice
+               (syntax-rules ()
+                 ((_ x) (helper x))))
+             (define (helper x) (* 2 x))
+             (twice (counted 4))",
+        );
+        assert_eq!(result, Ok("8".to_string()));
+        assert_eq!(engine.run("expansions"), Ok("1".to_string()));
+        assert_eq!(engine.run("(twice (counted 5))"), Ok("10".to_string()));
+        assert_eq!(engine.run("expansions"), Ok("2".to_string()));
+    }
+
+    #[test]
+    fn sandboxed_engines_can_be_given_libraries_and_single_procedures() {
+        let mut engine = Engine::with_libraries(&[Library::Base, Library::HashTables]);
+        engine.register_fn("double", |n: i64| n * 2);
+        assert!(engine.allow("force"));
+        assert!(engine.allow("make-promise"));
+        assert!(!engine.allow("no-such-procedure"));
+
+        let result = engine.run(
+            "(define (f) (g (force (make-promise 3))))
+             (define (g n) (cond ((> n 10) 'big) (else (double n))))
+             (define t (make-hash-table))
+             (hash-table-set! t 'k (f))
+             (hash-table-ref/default t 'k #f)",
+        );
+        assert_eq!(result, Ok("6".to_string()));
+        assert!(engine.run("(delay 1)").is_ok());
+        assert!(engine.run("(promise? 1)").is_err());
+    }
+
+    #[test]
+    fn engines_with_every_library_leave_unbound_variables_until_theyre_reached() {
+        let mut engine = Engine::new();
+        assert_eq!(engine.run("(define (later) (not-yet)) 1"), Ok("1".to_string()));
+    }
+
+    #[test]
+    fn programs_can_be_interrupted_from_other_threads() {
+        let mut engine = Engine::new();
+        engine.run("(define (spin) (spin))").unwrap();
+        let handle = engine.interrupt_handle();
+        let interrupt = |stop: bool| {
+            let handle = handle.clone();
+            thread::spawn(move || {
+                thread::sleep(Duration::from_millis(20));
+                if stop {
+                    handle.stop();
+                } else {
+                    handle.interrupt();
+                }
+            })
+        };
+
+        let waiting = interrupt(false);
+        let result = engine.run("(guard (e ((interrupt-condition? e) (error-object-message e))) (spin))");
+        assert_eq!(result, Ok("\"interrupted\"".to_string()));
+        waiting.join().unwrap();
+
+        let waiting = interrupt(false);
+        let result = engine.run("(guard (e ((error-object? e) 'caught)) (spin))");
+        assert_eq!(result, Err(Error::Interrupted));
+        waiting.join().unwrap();
+
+        let waiting = interrupt(true);
+        let result = engine.run("(guard (e (#t 'caught)) (spin))");
+        assert_eq!(result, Err(Error::Interrupted));
+        waiting.join().unwrap();
+        assert_eq!(engine.run("(+ 1 2)"), Ok("3".to_string()));
+    }
+
+    #[test]
+    fn interrupts_made_before_a_program_starts_stop_it() {
+        let mut engine = Engine::new();
+        let handle = engine.interrupt_handle();
+        handle.stop();
+        assert_eq!(engine.run("(+ 1 2)"), Err(Error::Interrupted));
+        handle.interrupt();
+        assert_eq!(engine.eval("(+ 1 2)").map(|_| ()), Err(Error::Interrupted));
+        assert_eq!(engine.run("(+ 1 2)"), Ok("3".to_string()));
+
+        handle.stop();
+        handle.reset();
+        assert_eq!(engine.run("(+ 1 2)"), Ok("3".to_string()));
+    }
+
+    #[test]
+    fn engines_can_move_between_threads() {
+        let mut engine = Engine::new();
+        engine.eval("(define numbers (list 1 2 3))").unwrap();
+        let result = thread::spawn(move || {
+            engine.run("(+ (car numbers) (car (cdr numbers)) (car (cdr (cdr numbers))))")
+        })
+        .join()
+        .unwrap();
+        assert_eq!(result, Ok("6".to_string()));
+    }
+
+    #[test]
+    fn forked_engines_keep_to_themselves() {
+        let mut engine = Engine::new();
+        engine.register_fn("twice", |n: i64| n * 2);
+        engine
+            .eval(
+                "(define items (list 1 2))
+                 (define (total) (twice (+ (car items) (car (cdr items)))))",
+            )
+            .unwrap();
+        let mut fork = engine.fork().unwrap();
+        assert_eq!(fork.run("(set-car! items 10) (total)"), Ok("24".to_string()));
+        assert_eq!(engine.run("(total)"), Ok("6".to_string()));
+    }
+
+    #[test]
+    fn forked_engines_keep_shared_structure() {
+        let mut engine = Engine::new();
+        engine
+            .eval(
+                "(define cycle (list 1 2))
+                 (set-cdr! (cdr cycle) cycle)
+                 (define same (list cycle cycle))",
+            )
+            .unwrap();
+        let mut fork = engine.fork().unwrap();
+        let result = fork.run("(list (car (cdr (cdr cycle))) (eq? (car same) (car (cdr same))))");
+        assert_eq!(result, Ok("(1 #t)".to_string()));
+    }
+
+    #[test]
+    fn engine_pools_hand_fresh_engines_to_any_thread() {
+        let mut engine = Engine::new();
+        engine.register_fn("twice", |n: i64| n * 2);
+        let prelude = "(define base 40) (define (answer) (+ base (twice 1)))";
+        let pool = EnginePool::new(engine, prelude, 2).unwrap();
+
+        let workers: Vec<_> = (0..4)
+            .map(|_| {
+                let pool = pool.clone();
+                thread::spawn(move || {
+                    let mut engine = pool.get();
+                    let first = engine.run("(set! base 0) (answer)");
+                    drop(engine);
+                    (first, pool.get().run("(answer)"))
+                })
+            })
+            .collect();
+        for worker in workers {
+            assert_eq!(worker.join().unwrap(), (Ok("2".to_string()), Ok("42".to_string())));
+        }
+    }
+
+    #[test]
+    fn engines_holding_open_ports_arent_forked() {
+        let path = env::temp_dir().join("ruse-fork-test.txt");
+        let prelude = format!("(define log (open-output-file {:?}))", path);
+        let unforkable = |result: Result<(), Error>| match result {
+            Err(Error::Unforkable(..)) => {}
+            Err(other) => panic!("expected the engine not to be forked, got {:?}", other),
+            Ok(..) => panic!("expected the engine not to be forked"),
+        };
+
+        unforkable(EnginePool::new(Engine::new(), &prelude, 1).map(|_| ()));
+        let mut engine = Engine::new();
+        engine.run(&prelude).unwrap();
+        unforkable(engine.fork().map(|_| ()));
+
+        engine.run("(close-port log)").unwrap();
+        let mut fork = engine.fork().unwrap();
+        let _ = fs::remove_file(&path);
+        assert_eq!(fork.run("(output-port-open? log)"), Ok("#f".to_string()));
+    }
+
+    #[test]
+    fn engine_pools_report_preludes_which_fail() {
+        match EnginePool::new(Engine::new(), "(car 1)", 1) {
+            Err(Error::Uncaught(exception)) => {
+                assert_eq!(exception.irritants, vec!["1".to_string()])
+            }
+            Err(other) => panic!("expected an uncaught exception, got {:?}", other),
+            Ok(..) => panic!("expected the prelude to fail"),
+        }
+    }
+
+    struct Bomb;
+
+    impl Drop for Bomb {
+        fn drop(&mut self) {
+            panic!("dropping a bomb");
+        }
+    }
+
+    #[test]
+    fn engine_pools_keep_handing_out_engines_once_they_stop_replacing_them() {
+        let pool = EnginePool::new(Engine::new(), "(define answer 3)", 1).unwrap();
+        let mut engine = pool.get();
+        let bomb = Bomb;
+        engine.register_fn("detonate", move |n: i64| {
+            let _ = &bomb;
+            n
+        });
+        // Dropping the engine panics on the thread replacing it.
+        drop(engine);
+
+        for _ in 0..3 {
+            assert_eq!(pool.get().run("answer"), Ok("3".to_string()));
+        }
+    }
+
+    #[cfg(feature = "serde")]
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    #[serde(rename_all = "kebab-case")]
+    struct Config {
+        name: String,
+        max_retries: u32,
+        timeout: Option<f64>,
+        limits: BTreeMap<String, i64>,
+    }
+
+    #[cfg(feature = "serde")]
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    enum Message {
+        Ping,
+        Say(String),
+        Move(i64, i64),
+        Resize { width: u32, height: u32 },
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde_structs_and_maps_become_alists() {
+        let mut limits = BTreeMap::new();
+        limits.insert("cpu".to_string(), 2);
+        limits.insert("memory".to_string(), 512);
+        let config = Config {
+            name: "worker".to_string(),
+            max_retries: 3,
+            timeout: None,
+            limits,
+        };
+        let datum = to_datum(&config).unwrap();
+        assert_eq!(
+            print(&datum),
+            "((name . \"worker\") (max-retries . 3) (timeout . #f) \
+             (limits (\"cpu\" . 2) (\"memory\" . 512)))"
+        );
+        assert_eq!(from_datum::<Config>(&datum), Ok(config));
+
+        let mut engine = Engine::new();
+        engine
+            .run("(define (config) '((name . \"web\") (limits) (max-retries . 5) (timeout . 1.5)))")
+            .unwrap();
+        let read: Config = from_datum(&engine.call("config", ()).unwrap()).unwrap();
+        assert_eq!(read.max_retries, 5);
+        assert_eq!(read.timeout, Some(1.5));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde_enums_and_bytes_round_trip() {
+        let messages = vec![
+            Message::Ping,
+            Message::Say("hi".to_string()),
+            Message::Move(1, -2),
+            Message::Resize { width: 80, height: 24 },
+        ];
+        let datum = to_datum(&messages).unwrap();
+        assert_eq!(
+            print(&datum),
+            "(Ping (Say \"hi\") (Move 1 -2) (Resize (width . 80) (height . 24)))"
+        );
+        assert_eq!(from_datum::<Vec<Message>>(&datum), Ok(messages));
+        assert!(from_datum::<Message>(&Expr::symbol("Shout")).is_err());
+
+        struct Bytes(Vec<u8>);
+        impl Serialize for Bytes {
+            fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                serializer.serialize_bytes(&self.0)
+            }
+        }
+        let bytes = to_datum(&Bytes(vec![1, 2, 255])).unwrap();
+        assert_eq!(print(&bytes), "#u8(1 2 255)");
+        assert_eq!(from_datum::<Vec<u8>>(&bytes), Ok(vec![1, 2, 255]));
+    }
+}
@@ -0,0 +1,235 @@
+//! Pools of engines, for hosts which run programs on many threads.
+//!
+//! A pool runs its prelude once, on an engine it keeps as a snapshot, and
+//! fills a queue with forks of that engine, each a copy with a heap of its
+//! own. Any thread can take an engine from the queue, waiting if there
+//! isn't one ready, and use it for as long as it likes.
+//!
+//! Engines aren't reused, so nothing one program does is seen by the next.
+//! One given back is handed to a thread belonging to the pool, which drops
+//! it and forks a fresh engine to put in the queue in its place. Neither
+//! giving an engine back nor taking one waits for that. If that thread
+//! stops, say because dropping an engine panicked, engines are forked by
+//! whichever thread asks for one instead, so nothing waits for an engine
+//! which will never come.
+
+use error::Error;
+use std::mem;
+use std::ops::{Deref, DerefMut};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::thread;
+use Engine;
+
+/// A pool of engines which all start out the same way.
+///
+/// Pools can be cloned and shared between threads, and hand out engines to
+/// whichever thread asks, like the workers of a web server each running a
+/// request. The thread making engines for the pool stops once every clone
+/// of it has been dropped.
+#[derive(Clone)]
+pub struct EnginePool {
+    handle: Arc<Handle>,
+}
+
+/// Closes the pool when the last clone of it is dropped.
+struct Handle {
+    shared: Arc<Shared>,
+}
+
+struct Shared {
+    /// The engine every other is forked from.
+    snapshot: Mutex<Engine>,
+    queue: Mutex<Queue>,
+    /// Signalled when an engine is put in the queue.
+    ready: Condvar,
+    /// Signalled when an engine is given back, or the pool is closed.
+    returned: Condvar,
+}
+
+struct Queue {
+    ready: Vec<Engine>,
+    /// Engines given back, still to be dropped and replaced.
+    returned: Vec<Engine>,
+    closed: bool,
+    /// Whether the thread replacing engines given back is still running.
+    refilling: bool,
+}
+
+impl EnginePool {
+    /// Create a pool of `size` engines, at least one, each starting out as
+    /// a copy of `engine` after it has run `prelude`. The engine can have
+    /// functions registered and limits set beforehand, and every copy has
+    /// them too. Host objects it holds are shared between the copies, as
+    /// cloning a `Host` shares it. If the engine can reach an open port once
+    /// it's run the prelude, it can't be copied, and the pool isn't made.
+    pub fn new<S: AsRef<str>>(
+        mut engine: Engine,
+        prelude: S,
+        size: usize,
+    ) -> Result<EnginePool, Error> {
+        engine.eval(prelude)?;
+        let ready = (0..size.max(1))
+            .map(|_| engine.fork())
+            .collect::<Result<_, _>>()?;
+        let shared = Arc::new(Shared {
+            snapshot: Mutex::new(engine),
+            queue: Mutex::new(Queue {
+                ready,
+                returned: Vec::new(),
+                closed: false,
+                refilling: true,
+            }),
+            ready: Condvar::new(),
+            returned: Condvar::new(),
+        });
+
+        let refill = shared.clone();
+        thread::spawn(move || {
+            let _stopped = Stopped(&refill);
+            refill.replace_returned();
+        });
+        Ok(EnginePool {
+            handle: Arc::new(Handle { shared }),
+        })
+    }
+
+    /// Take an engine, waiting until one is ready if they're all in use. If
+    /// the pool has stopped replacing engines, one is forked instead.
+    pub fn get(&self) -> PooledEngine {
+        let shared = &self.handle.shared;
+        let mut queue = shared.lock();
+        loop {
+            if let Some(engine) = queue.ready.pop() {
+                return PooledEngine {
+                    engine: Some(engine),
+                    shared: shared.clone(),
+                };
+            }
+            if !queue.refilling {
+                drop(queue);
+                return PooledEngine {
+                    engine: Some(shared.fork()),
+                    shared: shared.clone(),
+                };
+            }
+            queue = shared
+                .ready
+                .wait(queue)
+                .unwrap_or_else(|poisoned| poisoned.into_inner());
+        }
+    }
+
+    /// Take an engine if one is ready, without waiting.
+    pub fn try_get(&self) -> Option<PooledEngine> {
+        let shared = &self.handle.shared;
+        let engine = shared.lock().ready.pop()?;
+        Some(PooledEngine {
+            engine: Some(engine),
+            shared: shared.clone(),
+        })
+    }
+}
+
+impl Drop for Handle {
+    fn drop(&mut self) {
+        self.shared.lock().closed = true;
+        self.shared.returned.notify_all();
+    }
+}
+
+impl Shared {
+    // Nothing is left half-done if a thread panics while holding the
+    // queue, so it's still fine to use.
+    fn lock(&self) -> MutexGuard<Queue> {
+        self.queue.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Fork a fresh engine from the snapshot.
+    fn fork(&self) -> Engine {
+        self.snapshot
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .fork()
+            .expect("the snapshot was forked when the pool was made")
+    }
+
+    /// Drop the engines given back and fork new ones in their place, until
+    /// the pool is closed.
+    fn replace_returned(&self) {
+        loop {
+            let returned = {
+                let mut queue = self.lock();
+                while queue.returned.is_empty() && !queue.closed {
+                    queue = self
+                        .returned
+                        .wait(queue)
+                        .unwrap_or_else(|poisoned| poisoned.into_inner());
+                }
+                if queue.closed {
+                    return;
+                }
+                mem::replace(&mut queue.returned, Vec::new())
+            };
+
+            for engine in returned {
+                drop(engine);
+                let fresh = self.fork();
+                self.lock().ready.push(fresh);
+                self.ready.notify_one();
+            }
+        }
+    }
+}
+
+/// Marks the pool as no longer replacing engines when the thread doing it
+/// stops, however it stops, and wakes anyone waiting for one. Engines given
+/// back and not yet dropped are left for the pool to drop, as the thread may
+/// be panicking.
+struct Stopped<'a>(&'a Shared);
+
+impl<'a> Drop for Stopped<'a> {
+    fn drop(&mut self) {
+        self.0.lock().refilling = false;
+        self.0.ready.notify_all();
+    }
+}
+
+/// An engine taken from a pool. Dropping it gives it back, and the pool
+/// makes a fresh engine ready in its place.
+pub struct PooledEngine {
+    engine: Option<Engine>,
+    shared: Arc<Shared>,
+}
+
+impl Deref for PooledEngine {
+    type Target = Engine;
+
+    fn deref(&self) -> &Engine {
+        self.engine.as_ref().expect("a pooled engine is only taken when it's dropped")
+    }
+}
+
+impl DerefMut for PooledEngine {
+    fn deref_mut(&mut self) -> &mut Engine {
+        self.engine.as_mut().expect("a pooled engine is only taken when it's dropped")
+    }
+}
+
+impl Drop for PooledEngine {
+    fn drop(&mut self) {
+        let engine = match self.engine.take() {
+            Some(engine) => engine,
+            None => return,
+        };
+        let mut queue = self.shared.lock();
+        // Once the pool is closed, or has stopped replacing engines, the
+        // engine is dropped here.
+        if !queue.closed && queue.refilling {
+            queue.returned.push(engine);
+            self.shared.returned.notify_one();
+            return;
+        }
+        drop(queue);
+        drop(engine);
+    }
+}
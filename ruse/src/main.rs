@@ -6,7 +6,9 @@ extern crate libruse;
 extern crate clap;
 
 use libruse::Engine;
-use clap::{Arg, App};
+use clap::{Arg, App, AppSettings, SubCommand};
+use std::fs;
+use std::process;
 
 fn main() {
     // TODO: Expand the CLI to do more.
@@ -23,17 +25,59 @@ fn main() {
         .version(env!("CARGO_PKG_VERSION"))
         .author("Ruse Language Developers")
         .about("An embedded Scheme for Rust")
+        .setting(AppSettings::SubcommandsNegateReqs)
         .arg(
             Arg::with_name("SOURCE")
                 .help("The Ruse program to execute")
                 .required(true)
                 .index(1),
         )
+        .subcommand(
+            SubCommand::with_name("expand")
+                .about("Prints a program with its macros expanded")
+                .arg(
+                    Arg::with_name("FILE")
+                        .help("The file holding the program")
+                        .required(true)
+                        .index(1),
+                ),
+        )
         .get_matches();
 
+    if let Some(matches) = matches.subcommand_matches("expand") {
+        let file = matches.value_of("FILE").expect("No file provided.");
+        expand(file);
+        return;
+    }
+
     let source = matches.value_of("SOURCE").expect("No program provided.");
 
     let mut engine = Engine::new();
-    let result = engine.run(source);
-    println!("{}", result.unwrap());
+    match engine.run(source) {
+        Ok(result) => println!("{}", result),
+        Err(err) => {
+            eprintln!("{}", err);
+            process::exit(1);
+        }
+    }
+}
+
+/// Print the expansion of the program in a file, or why it couldn't be
+/// expanded.
+fn expand(file: &str) {
+    let source = match fs::read_to_string(file) {
+        Ok(source) => source,
+        Err(err) => {
+            eprintln!("could not read {}: {}", file, err);
+            process::exit(1);
+        }
+    };
+
+    match Engine::new().expand(source) {
+        Ok(expansion) => print!("{}", expansion),
+        Err(err) => {
+            eprintln!("{}", err);
+            process::exit(1);
+        }
+    }
 }